@@ -0,0 +1,184 @@
+use super::*;
+
+/// A line of `Environment::audit_log`, recording who executed what in which
+/// sandbox, under which limits, and how it ended.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct AuditRecord {
+  pub(crate) arguments: Vec<String>,
+  pub(crate) limits: Limits,
+  pub(crate) message: String,
+  pub(crate) program: String,
+  pub(crate) sandbox_id: u32,
+  pub(crate) status: String,
+  pub(crate) timestamp: SystemTime,
+  pub(crate) uid: u32,
+}
+
+impl AuditRecord {
+  /// Record the execution of `context` by `uid` in sandbox `sandbox_id`,
+  /// starting now, with the status left empty until it is `finish`ed.
+  pub(crate) fn new(sandbox_id: u32, uid: Uid, context: &ExecutionContext) -> Self {
+    Self {
+      arguments: context
+        .arguments
+        .iter()
+        .flatten()
+        .map(ToString::to_string)
+        .collect(),
+      limits: context.limits.clone(),
+      message: String::new(),
+      program: context.program.clone(),
+      sandbox_id,
+      status: String::new(),
+      timestamp: SystemTime::now(),
+      uid: uid.as_raw(),
+    }
+  }
+
+  /// Set the status to the one of `result`, or to `error` with the message
+  /// of the error if the execution failed.
+  pub(crate) fn finish(self, result: &Result<ExecutionResult>) -> Self {
+    let (status, message) = match result {
+      Ok(result) => (result.status.to_string(), result.status_message.clone()),
+      Err(error) => ("error".into(), error.to_string()),
+    };
+
+    Self {
+      message,
+      status,
+      ..self
+    }
+  }
+
+  /// The record as a single line of JSON, including the trailing newline.
+  ///
+  /// Limits that are not set are `null`.
+  pub(crate) fn to_json_line(&self) -> String {
+    let limits = &self.limits;
+
+    let limits = [
+      (
+        "core_size_limit_kb",
+        limits.core_size_limit_kb.map(f64::from),
+      ),
+      ("data_limit_kb", limits.data_limit_kb.map(f64::from)),
+      ("extra_time_ms", limits.extra_time_ms),
+      (
+        "file_size_limit_kb",
+        limits.file_size_limit_kb.map(f64::from),
+      ),
+      (
+        "locked_memory_limit_kb",
+        limits.locked_memory_limit_kb.map(f64::from),
+      ),
+      ("memory_limit_kb", limits.memory_limit_kb.map(f64::from)),
+      (
+        "message_queue_limit_kb",
+        limits.message_queue_limit_kb.map(f64::from),
+      ),
+      ("open_files_limit", limits.open_files_limit.map(f64::from)),
+      (
+        "pending_signals_limit",
+        limits.pending_signals_limit.map(f64::from),
+      ),
+      ("process_limit", limits.process_limit.map(f64::from)),
+      ("stack_limit_kb", limits.stack_limit_kb.map(f64::from)),
+      ("time_limit_ms", limits.time_limit_ms),
+      ("wall_time_limit_ms", limits.wall_time_limit_ms),
+    ]
+    .into_iter()
+    .map(|(name, value)| format!("\"{name}\":{}", ResultFormat::json_optional_number(value)))
+    .collect::<Vec<_>>()
+    .join(",");
+
+    let arguments = self
+      .arguments
+      .iter()
+      .map(|argument| ResultFormat::json_string(argument))
+      .collect::<Vec<_>>()
+      .join(",");
+
+    format!(
+      concat!(
+        "{{\"arguments\":[{}],\"limits\":{{{}}},\"message\":{},\"program\":{},",
+        "\"sandbox_id\":{},\"status\":{},\"timestamp\":{},\"uid\":{}}}\n"
+      ),
+      arguments,
+      limits,
+      ResultFormat::json_string(&self.message),
+      ResultFormat::json_string(&self.program),
+      self.sandbox_id,
+      ResultFormat::json_string(&self.status),
+      ResultFormat::json_string(&rfc3339::format(self.timestamp)),
+      self.uid,
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn record() -> AuditRecord {
+    AuditRecord {
+      timestamp: UNIX_EPOCH + Duration::from_millis(1_709_251_200_250),
+      ..AuditRecord::new(
+        3,
+        Uid::from_raw(1000),
+        &ExecutionContext::new("/bin/echo".into(), Some(vec!["a \"b\"", "c"]))
+          .limits(Limits::default().process_limit(4)),
+      )
+    }
+  }
+
+  #[test]
+  fn new() {
+    let record = record();
+
+    assert_eq!(record.arguments, ["a \"b\"", "c"]);
+    assert_eq!(record.limits, Limits::default().process_limit(4));
+    assert_eq!(record.program, "/bin/echo");
+    assert_eq!(record.sandbox_id, 3);
+    assert_eq!(record.status, "");
+    assert_eq!(record.uid, 1000);
+  }
+
+  #[test]
+  fn finish() {
+    let record = record().finish(&Ok(ExecutionResult {
+      status: Status::RuntimeError,
+      status_message: "Exited with error status 1".into(),
+      ..Default::default()
+    }));
+
+    assert_eq!(record.status, "RE");
+    assert_eq!(record.message, "Exited with error status 1");
+
+    let record = record.finish(&Err(Error::NotInitialized));
+
+    assert_eq!(record.status, "error");
+    assert_eq!(record.message, Error::NotInitialized.to_string());
+  }
+
+  #[test]
+  fn to_json_line() {
+    let record = record().finish(&Ok(ExecutionResult {
+      status: Status::Ok,
+      ..Default::default()
+    }));
+
+    assert_eq!(
+      record.to_json_line(),
+      concat!(
+        "{\"arguments\":[\"a \\\"b\\\"\",\"c\"],\"limits\":{\"core_size_limit_kb\":0,",
+        "\"data_limit_kb\":null,\"extra_time_ms\":500,\"file_size_limit_kb\":8192,",
+        "\"locked_memory_limit_kb\":0,\"memory_limit_kb\":256000,",
+        "\"message_queue_limit_kb\":0,\"open_files_limit\":64,",
+        "\"pending_signals_limit\":64,\"process_limit\":4,\"stack_limit_kb\":32000,",
+        "\"time_limit_ms\":1000,\"wall_time_limit_ms\":5000},\"message\":\"\",",
+        "\"program\":\"/bin/echo\",\"sandbox_id\":3,\"status\":\"OK\",",
+        "\"timestamp\":\"2024-03-01T00:00:00.250Z\",\"uid\":1000}\n"
+      )
+    );
+  }
+}
@@ -0,0 +1,247 @@
+use super::*;
+
+/// Maximum number of bytes of each stream kept in the `ExecutionResult`.
+pub(crate) const CAPTURE_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Identifies a file sent from the child to the parent.
+const STDERR_FILE: u8 = b'e';
+const STDOUT_FILE: u8 = b'o';
+
+/// Pipes and sockets used to capture the program's output into the result.
+///
+/// The child connects its standard output and standard error to the pipes,
+/// and hands any redirect files it opened over to the parent, which then
+/// copies the output into both the file and the result.
+///
+/// The files are opened by the child (inside the sandbox, as the sandbox
+/// user), so the parent never has to resolve a path inside the sandbox
+/// itself.
+#[derive(Debug)]
+pub(crate) struct Capture {
+  files: (OwnedFd, OwnedFd),
+  stderr: Option<(OwnedFd, OwnedFd)>,
+  stdout: (OwnedFd, OwnedFd),
+}
+
+/// The captured output of a run.
+#[derive(Debug, Default)]
+pub(crate) struct Output {
+  pub(crate) stderr: String,
+  pub(crate) stdout: String,
+}
+
+impl Capture {
+  pub(crate) fn new(context: &ExecutionContext) -> Result<Self> {
+    let pipe = || syscall("create capture pipe", pipe2(OFlag::O_CLOEXEC));
+
+    Ok(Self {
+      files: syscall(
+        "create capture socket",
+        socketpair(
+          AddressFamily::Unix,
+          SockType::SeqPacket,
+          None,
+          SockFlag::SOCK_CLOEXEC,
+        ),
+      )?,
+      stderr: if context.stderr_to_stdout && context.stderr.is_none() {
+        None
+      } else {
+        Some(pipe()?)
+      },
+      stdout: pipe()?,
+    })
+  }
+
+  /// Connect the standard output and standard error of the child to the
+  /// capture pipes.
+  ///
+  /// Runs in the child, after dropping privileges.
+  pub(crate) fn attach(&self, context: &ExecutionContext) -> Result {
+    if let Some(stdout) = &context.stdout {
+      self.send_file(STDOUT_FILE, stdout, context.stdout_append)?;
+    }
+
+    syscall(
+      "capture stdout",
+      dup2(self.stdout.1.as_raw_fd(), libc::STDOUT_FILENO),
+    )?;
+
+    if let Some(stderr) = &context.stderr {
+      self.send_file(STDERR_FILE, stderr, context.stderr_append)?;
+    }
+
+    match &self.stderr {
+      Some((_, writer)) => syscall(
+        "capture stderr",
+        dup2(writer.as_raw_fd(), libc::STDERR_FILENO),
+      )?,
+      None => syscall(
+        "redirect stderr to stdout",
+        dup2(libc::STDOUT_FILENO, libc::STDERR_FILENO),
+      )?,
+    };
+
+    Ok(())
+  }
+
+  fn send_file(&self, tag: u8, path: &Utf8Path, append: bool) -> Result {
+    let file = Sandbox::open_output(path, append)?;
+
+    let result = syscall(
+      &format!("hand over `{}`", path),
+      sendmsg::<()>(
+        self.files.1.as_raw_fd(),
+        &[IoSlice::new(&[tag])],
+        &[ControlMessage::ScmRights(&[file])],
+        MsgFlags::empty(),
+        None,
+      ),
+    );
+
+    syscall(&format!("close `{}`", path), close(file))?;
+
+    result.map(|_| ())
+  }
+
+  /// Copy the output of the child into the result and any redirect files
+  /// until the program and everything it spawned have exited.
+  ///
+  /// `wait` is called once the child's setup has finished and should reap the
+  /// child, while the output is being copied in the background. It is called
+  /// even if receiving the redirect files fails, and its value is returned
+  /// even if copying the output fails, so that the child is always reaped.
+  pub(crate) fn collect<T>(self, wait: impl FnOnce() -> T) -> (T, Result<Output>) {
+    let Self {
+      files: (files, files_child),
+      stderr,
+      stdout: (stdout, stdout_writer),
+    } = self;
+
+    drop(files_child);
+    drop(stdout_writer);
+
+    let stderr = stderr.map(|(reader, _)| reader);
+
+    let (received, (stdout_file, stderr_file)) = match Self::receive_files(&files) {
+      Ok(files) => (Ok(()), files),
+      Err(error) => (Err(error), (None, None)),
+    };
+
+    thread::scope(|scope| {
+      let stdout = scope.spawn(|| Self::copy(stdout, stdout_file));
+
+      let stderr = stderr.map(|stderr| scope.spawn(|| Self::copy(stderr, stderr_file)));
+
+      let value = wait();
+
+      let output = received.and_then(|()| {
+        Ok(Output {
+          stdout: stdout
+            .join()
+            .map_err(|_| Error::Internal("stdout capture panicked".into()))??,
+          stderr: match stderr {
+            Some(stderr) => stderr
+              .join()
+              .map_err(|_| Error::Internal("stderr capture panicked".into()))??,
+            None => String::new(),
+          },
+        })
+      });
+
+      (value, output)
+    })
+  }
+
+  /// Receive the redirect files until the child executes the program or
+  /// exits, which closes its end of the socket.
+  fn receive_files(files: &OwnedFd) -> Result<(Option<File>, Option<File>)> {
+    let (mut stdout_file, mut stderr_file) = (None, None);
+
+    loop {
+      let mut tag = [0];
+      let mut space = cmsg_space!(RawFd);
+
+      let fds = {
+        let mut iov = [IoSliceMut::new(&mut tag)];
+
+        let message = syscall(
+          "receive redirect file",
+          recvmsg::<()>(
+            files.as_raw_fd(),
+            &mut iov,
+            Some(&mut space),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+          ),
+        )?;
+
+        if message.bytes == 0 {
+          break;
+        }
+
+        syscall("receive redirect file", message.cmsgs())?
+          .flat_map(|message| match message {
+            ControlMessageOwned::ScmRights(fds) => fds,
+            _ => Vec::new(),
+          })
+          .collect::<Vec<RawFd>>()
+      };
+
+      for fd in fds {
+        // SAFETY: The descriptor was just received and nothing else owns it.
+        let file = unsafe { File::from_raw_fd(fd) };
+
+        match tag[0] {
+          STDOUT_FILE => stdout_file = Some(file),
+          STDERR_FILE => stderr_file = Some(file),
+          _ => {}
+        }
+      }
+    }
+
+    Ok((stdout_file, stderr_file))
+  }
+
+  /// Copy everything from `reader` into `file`, keeping the first
+  /// `CAPTURE_LIMIT` bytes.
+  fn copy(reader: OwnedFd, mut file: Option<File>) -> Result<String> {
+    let mut reader = File::from(reader);
+
+    let (mut captured, mut buffer) = (Vec::new(), [0; 8192]);
+
+    loop {
+      let read = match reader.read(&mut buffer) {
+        Ok(0) => break,
+        Ok(read) => read,
+        Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+        Err(error) => return Err(error.into()),
+      };
+
+      if let Some(file) = &mut file {
+        file.write_all(&buffer[..read])?;
+      }
+
+      let remaining = CAPTURE_LIMIT.saturating_sub(captured.len());
+
+      captured.extend_from_slice(&buffer[..read.min(remaining)]);
+    }
+
+    Ok(String::from_utf8_lossy(&captured).into_owned())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, assert_matches::assert_matches};
+
+  #[test]
+  fn collect_after_failed_wait() {
+    let capture = Capture::new(&ExecutionContext::default()).unwrap();
+
+    let (value, output) = capture.collect(|| Err::<(), _>(Error::Internal("wait failed".into())));
+
+    assert_matches!(value, Err(Error::Internal(message)) if message == "wait failed");
+
+    assert_matches!(output, Ok(Output { stdout, stderr }) if stdout.is_empty() && stderr.is_empty());
+  }
+}
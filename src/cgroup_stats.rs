@@ -0,0 +1,181 @@
+use super::*;
+
+/// Memory statistics of a control group, read from the `memory.peak` and
+/// `memory.events` files of its memory controller.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct CgroupStats {
+  /// How often the group's usage reached its memory limit, the `max`
+  /// counter of `memory.events`.
+  pub(crate) limit_hits: u64,
+  /// How many processes in the group the OOM killer killed, the `oom_kill`
+  /// counter of `memory.events`.
+  pub(crate) oom_kills: u64,
+  /// Peak memory usage of the group in kilobytes, from `memory.peak`.
+  ///
+  /// Zero on kernels older than 5.19, which lack `memory.peak`.
+  pub(crate) peak_memory_kb: u32,
+}
+
+impl CgroupStats {
+  /// Read the statistics of the control group at `cgroup`.
+  pub(crate) fn read(system: &dyn System, cgroup: &Utf8Path) -> Result<Self> {
+    let read_error = |path: &Utf8Path, error| {
+      Error::Cgroup(
+        format!("failed to read `{path}` of the memory controller"),
+        error,
+      )
+    };
+
+    let path = cgroup.join("memory.peak");
+
+    let peak_memory_kb = match system.read_file(&path) {
+      Ok(peak) => Self::parse_peak(&peak)?,
+      Err(error) if error.kind() == io::ErrorKind::NotFound => 0,
+      Err(error) => return Err(read_error(&path, error)),
+    };
+
+    let path = cgroup.join("memory.events");
+
+    let events = system
+      .read_file(&path)
+      .map_err(|error| read_error(&path, error))?;
+
+    Ok(Self {
+      limit_hits: Self::parse_event(&events, "max")?,
+      oom_kills: Self::parse_event(&events, "oom_kill")?,
+      peak_memory_kb,
+    })
+  }
+
+  /// The statistics of the group since `baseline` was read, for a group
+  /// that is reused between runs, whose counters keep counting.
+  pub(crate) fn since(self, baseline: Self) -> Self {
+    Self {
+      limit_hits: self.limit_hits.saturating_sub(baseline.limit_hits),
+      oom_kills: self.oom_kills.saturating_sub(baseline.oom_kills),
+      ..self
+    }
+  }
+
+  /// The counter `name` from the contents of `memory.events`, or zero if the
+  /// kernel does not report it.
+  fn parse_event(events: &str, name: &str) -> Result<u64> {
+    let Some(value) = events.lines().find_map(|line| {
+      line
+        .split_once(' ')
+        .filter(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+    }) else {
+      return Ok(0);
+    };
+
+    value
+      .trim()
+      .parse()
+      .map_err(|_| Error::Internal(format!("unexpected memory event `{name} {value}`")))
+  }
+
+  /// The peak usage in kilobytes from the contents of `memory.peak`, which
+  /// is in bytes.
+  fn parse_peak(peak: &str) -> Result<u32> {
+    let bytes = peak
+      .trim()
+      .parse::<u64>()
+      .map_err(|_| Error::Internal(format!("unexpected memory peak `{}`", peak.trim())))?;
+
+    Ok(u32::try_from(bytes.div_ceil(1024)).unwrap_or(u32::MAX))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, assert_matches::assert_matches};
+
+  #[test]
+  fn read() {
+    let system = InMemorySystem::default();
+
+    let cgroup = Utf8Path::new("/sys/fs/cgroup/isolate/box-0");
+
+    system
+      .write_file(&cgroup.join("memory.peak"), "1048577\n")
+      .unwrap();
+
+    system
+      .write_file(
+        &cgroup.join("memory.events"),
+        "low 0\nhigh 0\nmax 12\noom 1\noom_kill 1\noom_group_kill 0\n",
+      )
+      .unwrap();
+
+    assert_eq!(
+      CgroupStats::read(&system, cgroup).unwrap(),
+      CgroupStats {
+        limit_hits: 12,
+        oom_kills: 1,
+        peak_memory_kb: 1025,
+      }
+    );
+  }
+
+  #[test]
+  fn read_without_peak() {
+    let system = InMemorySystem::default();
+
+    let cgroup = Utf8Path::new("/sys/fs/cgroup/isolate/box-0");
+
+    system
+      .write_file(
+        &cgroup.join("memory.events"),
+        "low 0\nhigh 0\nmax 0\noom 0\n",
+      )
+      .unwrap();
+
+    assert_eq!(
+      CgroupStats::read(&system, cgroup).unwrap(),
+      CgroupStats::default()
+    );
+
+    assert_matches!(
+      CgroupStats::read(&system, "/sys/fs/cgroup/isolate/box-1".into()),
+      Err(Error::Cgroup(message, error))
+        if message == "failed to read `/sys/fs/cgroup/isolate/box-1/memory.events` of the memory controller"
+          && error.kind() == io::ErrorKind::NotFound
+    );
+  }
+
+  #[test]
+  fn parse() {
+    assert_matches!(
+      CgroupStats::parse_event("oom_kill lots\n", "oom_kill"),
+      Err(Error::Internal(message)) if message == "unexpected memory event `oom_kill lots`"
+    );
+
+    assert_matches!(
+      CgroupStats::parse_peak("max\n"),
+      Err(Error::Internal(message)) if message == "unexpected memory peak `max`"
+    );
+  }
+
+  #[test]
+  fn since() {
+    let stats = CgroupStats {
+      limit_hits: 5,
+      oom_kills: 3,
+      peak_memory_kb: 2048,
+    };
+
+    assert_eq!(
+      stats.since(CgroupStats {
+        limit_hits: 5,
+        oom_kills: 2,
+        peak_memory_kb: 1024,
+      }),
+      CgroupStats {
+        limit_hits: 0,
+        oom_kills: 1,
+        peak_memory_kb: 2048,
+      }
+    );
+  }
+}
@@ -0,0 +1,224 @@
+use super::*;
+
+/// Maximum number of system calls kept by `Child::trace`.
+#[cfg(feature = "trace")]
+const TRACE_LIMIT: usize = 100_000;
+
+/// The process running the program.
+///
+/// Whenever the kernel supports it, signals are sent through a pidfd rather
+/// than to its pid, so they always target the process that was spawned and
+/// never one that happens to reuse its pid. Kernels without pidfds (before
+/// Linux 5.3) fall back to the plain pid.
+///
+/// The process is reaped with `wait4`, which also reports its resource usage.
+/// Until then its pid cannot be reused, so waiting on the pid is safe.
+#[derive(Debug)]
+pub(crate) struct Child {
+  /// The pid of the process, as seen from the sandbox manager.
+  pid: Pid,
+  /// A pidfd referring to the process, if the kernel supports them.
+  pidfd: Option<OwnedFd>,
+}
+
+impl Child {
+  pub(crate) fn new(pid: Pid) -> Result<Self> {
+    // SAFETY: `pidfd_open` takes no pointers.
+    let pidfd = match Errno::result(unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) })
+    {
+      // SAFETY: The descriptor was just created and nothing else owns it.
+      Ok(fd) => Some(unsafe { OwnedFd::from_raw_fd(fd as RawFd) }),
+      Err(Errno::ENOSYS) => None,
+      Err(error) => syscall("open pidfd", Err(error))?,
+    };
+
+    Ok(Self { pid, pidfd })
+  }
+
+  /// Whether the process has exited, without reaping it.
+  ///
+  /// A process that was reaped already, e.g. by `Child::trace` while the
+  /// watchdog runs on another thread, counts as exited.
+  pub(crate) fn exited(&self) -> Result<bool> {
+    match waitid(
+      Id::Pid(self.pid),
+      WaitPidFlag::WEXITED | WaitPidFlag::WNOHANG | WaitPidFlag::WNOWAIT,
+    ) {
+      Ok(status) => Ok(status != WaitStatus::StillAlive),
+      Err(Errno::ECHILD) => Ok(true),
+      Err(error) => syscall("check on child", Err(error)),
+    }
+  }
+
+  /// Send `SIGKILL` to the process.
+  pub(crate) fn kill(&self) -> Result {
+    if let Some(pidfd) = &self.pidfd {
+      // SAFETY: A null `info` is allowed and means the same as `kill`.
+      match Errno::result(unsafe {
+        libc::syscall(
+          libc::SYS_pidfd_send_signal,
+          pidfd.as_raw_fd(),
+          libc::SIGKILL,
+          std::ptr::null::<libc::siginfo_t>(),
+          0,
+        )
+      }) {
+        Ok(_) => return Ok(()),
+        Err(Errno::ENOSYS) => {}
+        Err(error) => return syscall("kill child", Err(error)),
+      }
+    }
+
+    syscall("kill child", kill(self.pid, Signal::SIGKILL))
+  }
+
+  pub(crate) fn pid(&self) -> Pid {
+    self.pid
+  }
+
+  /// The pidfd of the process, which becomes readable once it exits.
+  pub(crate) fn pidfd(&self) -> Option<BorrowedFd<'_>> {
+    self.pidfd.as_ref().map(|pidfd| pidfd.as_fd())
+  }
+
+  /// Wait for the process to exit and reap it, returning the resources used
+  /// by the process and the descendants it waited for.
+  pub(crate) fn wait(&self, system: &dyn System) -> Result<(WaitStatus, ResourceUsage)> {
+    system.wait4(self.pid)
+  }
+
+  /// Like `Child::wait`, but record the system calls the process makes on the
+  /// way, keeping the last `TRACE_LIMIT` of them.
+  ///
+  /// The process must have called `PTRACE_TRACEME` before executing the
+  /// program, and this must be called from the thread that created it, which
+  /// is the only one allowed to trace it.
+  #[cfg(feature = "trace")]
+  pub(crate) fn trace(
+    &self,
+    system: &dyn System,
+  ) -> Result<(WaitStatus, ResourceUsage, Vec<TracedSyscall>)> {
+    let mut syscalls = VecDeque::new();
+
+    let mut started = false;
+
+    loop {
+      let (status, usage) = system.wait4(self.pid)?;
+
+      let signal = match status {
+        // The process stops with `SIGTRAP` once it has executed the program.
+        WaitStatus::Stopped(_, Signal::SIGTRAP) if !started => {
+          started = true;
+
+          syscall(
+            "set trace options",
+            ptrace::setoptions(
+              self.pid,
+              ptrace::Options::PTRACE_O_TRACESYSGOOD | ptrace::Options::PTRACE_O_EXITKILL,
+            ),
+          )?;
+
+          None
+        }
+        WaitStatus::PtraceSyscall(_) => {
+          self.record(&mut syscalls)?;
+          None
+        }
+        WaitStatus::PtraceEvent(..) => None,
+        WaitStatus::Stopped(_, signal) => Some(signal),
+        status => return Ok((status, usage, syscalls.into())),
+      };
+
+      // The process may have been killed in the meantime, which the next
+      // wait reports.
+      match ptrace::syscall(self.pid, signal) {
+        Ok(()) | Err(Errno::ESRCH) => {}
+        Err(error) => syscall("resume traced child", Err(error))?,
+      }
+    }
+  }
+
+  /// Record the system call the process is stopped at, either entering it or
+  /// returning from it.
+  #[cfg(feature = "trace")]
+  fn record(&self, syscalls: &mut VecDeque<TracedSyscall>) -> Result {
+    // SAFETY: All-zero bytes are a valid value for this plain C struct.
+    let mut info = unsafe { mem::zeroed::<libc::ptrace_syscall_info>() };
+
+    // SAFETY: The kernel writes at most `size_of` bytes into `info`.
+    syscall(
+      "read traced system call",
+      Errno::result(unsafe {
+        libc::ptrace(
+          libc::PTRACE_GET_SYSCALL_INFO,
+          self.pid.as_raw(),
+          mem::size_of::<libc::ptrace_syscall_info>(),
+          &mut info,
+        )
+      }),
+    )?;
+
+    match info.op {
+      libc::PTRACE_SYSCALL_INFO_ENTRY => {
+        if syscalls.len() == TRACE_LIMIT {
+          syscalls.pop_front();
+        }
+
+        // SAFETY: `op` says which member of the union the kernel filled in.
+        let entry = unsafe { info.u.entry };
+
+        syscalls.push_back(TracedSyscall {
+          arguments: entry.args,
+          number: entry.nr,
+          result: None,
+        });
+      }
+      libc::PTRACE_SYSCALL_INFO_EXIT => {
+        if let Some(last) = syscalls.back_mut() {
+          // SAFETY: `op` says which member of the union the kernel filled in.
+          last.result = Some(unsafe { info.u.exit.sval });
+        }
+      }
+      _ => {}
+    }
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, assert_matches::assert_matches, std::process::Command};
+
+  // The process is reaped by `Child::wait` instead.
+  #[allow(clippy::zombie_processes)]
+  fn spawn(program: &str, arguments: &[&str]) -> Child {
+    let process = Command::new(program).args(arguments).spawn().unwrap();
+
+    Child::new(Pid::from_raw(process.id() as i32)).unwrap()
+  }
+
+  #[test]
+  fn wait() {
+    let child = spawn("false", &[]);
+
+    assert_matches!(
+      child.wait(&MaterialSystem).unwrap().0,
+      WaitStatus::Exited(_, 1)
+    );
+  }
+
+  #[test]
+  fn kill() {
+    let child = spawn("sleep", &["5"]);
+
+    assert!(!child.exited().unwrap());
+
+    child.kill().unwrap();
+
+    assert_matches!(
+      child.wait(&MaterialSystem).unwrap().0,
+      WaitStatus::Signaled(_, Signal::SIGKILL, _)
+    );
+  }
+}
@@ -1,6 +1,6 @@
 use super::*;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum CgroupRoot {
   Automatic(Utf8PathBuf),
   Manual(Utf8PathBuf),
@@ -12,6 +12,31 @@ impl Default for CgroupRoot {
   }
 }
 
+impl CgroupRoot {
+  /// Resolve the path of the root, reading it from the file of an
+  /// `Automatic` root.
+  pub fn resolve(&self) -> Result<Utf8PathBuf> {
+    self.resolve_with(&MaterialSystem)
+  }
+
+  /// Resolve the path of the root like `CgroupRoot::resolve`, reading the
+  /// file of an `Automatic` root through `system`.
+  pub(crate) fn resolve_with(&self, system: &dyn System) -> Result<Utf8PathBuf> {
+    match self {
+      Self::Automatic(file) => system
+        .read_file(file)
+        .map(|root| Utf8PathBuf::from(root.trim()))
+        .map_err(|error| {
+          Error::Cgroup(
+            format!("failed to read control group root from `{file}`"),
+            error,
+          )
+        }),
+      Self::Manual(path) => Ok(path.clone()),
+    }
+  }
+}
+
 impl From<Utf8PathBuf> for CgroupRoot {
   fn from(path: Utf8PathBuf) -> Self {
     if let Some(stripped) = path.as_str().strip_prefix("auto:") {
@@ -31,7 +56,7 @@ impl From<CgroupRoot> for Utf8PathBuf {
   }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct CgroupConfig {
   /// Defines the CPU cores available for this control group using the cpuset format.
   ///
@@ -39,9 +64,23 @@ pub struct CgroupConfig {
   /// Refer to the [cpusets documentation](https://docs.kernel.org/admin-guide/cgroup-v1/cpusets.html) for valid syntax.
   pub cpu_cores: Option<String>,
 
+  /// Create a control group for every run below `root` and remove it
+  /// afterwards (default: true).
+  ///
+  /// Disable this to use `root` itself, a control group created and removed
+  /// by someone else, e.g. one delegated to a container by its platform,
+  /// where no sibling groups can be created. Every run then writes its
+  /// limits to `root` and joins it, but nothing is ever created or removed
+  /// below it. Since a control group cannot hold processes while it has
+  /// controllers enabled for its children, `root` must not have any, and
+  /// because the runs share it, so should only one sandbox at a time.
+  pub manage: bool,
+
   /// Specifies the maximum memory allocation for the control group, in kilobytes.
   ///
-  /// This value limits the total memory usage of all tasks within the group.
+  /// This value limits the total memory usage of all tasks within the group,
+  /// and takes the place of `Limits::memory_limit_kb`, which is not enforced
+  /// in control group mode.
   pub memory_limit: Option<u32>,
 
   /// Configures the memory nodes that this control group can access.
@@ -58,7 +97,15 @@ pub struct CgroupConfig {
   /// - A fixed path in the cgroup filesystem, or
   /// - A dynamic path specified as `"auto:file"`, where the actual path is read from `file`
   ///
-  /// The default value is `"auto:/run/isolate/cgroup"`.
+  /// The default value is `"auto:/run/isolate/cgroup"`, or
+  /// `Environment::cgroup_root` if that is set.
+  ///
+  /// Every run gets a control group of its own below the root, named
+  /// `box-<id>-<pid>-<counter>` after the id of the sandbox, the process id
+  /// of the caller and the number of control groups the caller created
+  /// before, so runs never collide, even when a sandbox id is reused. It is
+  /// removed after the run, or by `Sandbox::cleanup` if that fails. Unless
+  /// `manage` is disabled, in which case the runs use the root itself.
   pub root: CgroupRoot,
 }
 
@@ -66,6 +113,7 @@ impl Default for CgroupConfig {
   fn default() -> Self {
     Self {
       cpu_cores: None,
+      manage: true,
       memory_limit: Some(1024 * 1024),
       memory_nodes: None,
       root: CgroupRoot::default(),
@@ -73,7 +121,97 @@ impl Default for CgroupConfig {
   }
 }
 
-#[derive(Debug, PartialEq)]
+impl CgroupConfig {
+  /// Write the limits to the existing control group at `cgroup`, replacing
+  /// the ones it had before.
+  ///
+  /// This only configures the group, which has to be created beforehand, so
+  /// that a group can be set up once and reconfigured between runs. Limits
+  /// that are not set are reset: `memory.max` to `max`, and `cpuset.cpus`
+  /// and `cpuset.mems` to the ones of the parent group.
+  pub fn apply(&self, cgroup: &Utf8Path) -> Result {
+    self.apply_with(&MaterialSystem, cgroup)
+  }
+
+  /// Write the limits to the control group at `cgroup` like
+  /// `CgroupConfig::apply`, through `system`.
+  pub(crate) fn apply_with(&self, system: &dyn System, cgroup: &Utf8Path) -> Result {
+    self.validate()?;
+
+    let memory_max = self.memory_limit.map_or_else(
+      || "max".into(),
+      |limit| (u64::from(limit) * 1024).to_string(),
+    );
+
+    for (file, value) in [
+      ("memory.max", memory_max.as_str()),
+      ("cpuset.cpus", self.cpu_cores.as_deref().unwrap_or_default()),
+      (
+        "cpuset.mems",
+        self.memory_nodes.as_deref().unwrap_or_default(),
+      ),
+    ] {
+      let path = cgroup.join(file);
+
+      let controller = file
+        .split_once('.')
+        .map_or(file, |(controller, _)| controller);
+
+      system.write_file(&path, value).map_err(|error| {
+        Error::Cgroup(
+          format!("failed to write `{path}` of the {controller} controller"),
+          error,
+        )
+      })?;
+    }
+
+    Ok(())
+  }
+
+  /// Check that `cpu_cores` and `memory_nodes` use valid cpuset syntax, i.e.
+  /// a comma-separated list of numbers and ranges (e.g. `"0-3,5,7"`) in
+  /// ascending order.
+  pub fn validate(&self) -> Result {
+    for (name, value) in [
+      ("cpu_cores", &self.cpu_cores),
+      ("memory_nodes", &self.memory_nodes),
+    ] {
+      if let Some(value) = value {
+        Self::validate_set(value).map_err(|token| {
+          Error::Config(format!("invalid {name} `{value}`: bad token `{token}`"))
+        })?;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Returns the offending token if `set` is not a valid cpuset list.
+  fn validate_set(set: &str) -> Result<(), &str> {
+    let mut previous: Option<u32> = None;
+
+    for token in set.split(',') {
+      let (start, end) = match token.split_once('-') {
+        Some((start, end)) => (start, end),
+        None => (token, token),
+      };
+
+      let (Ok(start), Ok(end)) = (start.parse::<u32>(), end.parse::<u32>()) else {
+        return Err(token);
+      };
+
+      if start > end || previous.is_some_and(|previous| start <= previous) {
+        return Err(token);
+      }
+
+      previous = Some(end);
+    }
+
+    Ok(())
+  }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Config {
   /// Act on behalf of the specified group id (only if Isolate was invoked by
   /// root).
@@ -105,6 +243,16 @@ pub struct Config {
   /// Control group configuration.
   pub cgroup: Option<CgroupConfig>,
 
+  /// Hand the box and everything in it over to the original user after every
+  /// run, and back to the sandbox user before the next one.
+  ///
+  /// Files created by the program belong to the sandbox user, so without
+  /// this, the user the sandbox acts on behalf of (see `as_uid`) cannot read
+  /// them without elevated privileges.
+  ///
+  /// Defaults to true.
+  pub chown_output_to_invoker: bool,
+
   /// Set disk quota to a given number of inodes.
   ///
   /// This requires the filesystem to be mounted with support for quotas.
@@ -125,7 +273,29 @@ pub struct Config {
   /// This defaults to 0.
   pub sandbox_id: Option<u32>,
 
+  /// Give up on a run whose setup takes longer than this many milliseconds,
+  /// failing with `Error::Timeout` (default: 5000).
+  ///
+  /// The setup is everything the sandbox does from creating the process the
+  /// program runs in until executing it, such as mounting its filesystems
+  /// and running the prelude, so raise this for slow preludes. Without a
+  /// timeout, a mount that hangs, e.g. of an unresponsive network
+  /// filesystem, blocks `Sandbox::execute` forever. The process is killed
+  /// when the time is up, so this cannot help with operations that ignore
+  /// `SIGKILL`.
+  ///
+  /// What the sandbox does on the calling thread before creating the
+  /// process, such as preparing the control group and handing the box to the
+  /// sandbox user, is not covered and cannot be abandoned. When the time is
+  /// up, the box is still handed back and special files are removed from it
+  /// as after any run, before `Sandbox::execute` fails.
+  pub setup_timeout_ms: Option<u64>,
+
   /// Tell the sandbox manager to be verbose and report on what is going on.
+  ///
+  /// Before a program is executed, the user and group it runs as, its
+  /// limits, its mounts, and its arguments and environment are written to
+  /// stderr, unless `ExecutionContext::silent` is set.
   pub verbose: bool,
 
   /// Multiple instances of Isolate cannot manage the same sandbox
@@ -145,14 +315,33 @@ impl Default for Config {
       as_uid: None,
       block_quota: None,
       cgroup: None,
+      chown_output_to_invoker: true,
       inode_quota: None,
       sandbox_id: Some(0),
+      setup_timeout_ms: Some(5000),
       verbose: false,
       wait: false,
     }
   }
 }
 
+impl Config {
+  /// Whether the sandbox runs in control group mode.
+  pub fn cgroup_enabled(&self) -> bool {
+    self.cgroup.is_some()
+  }
+
+  /// Enable control group mode with the default `CgroupConfig`.
+  ///
+  /// A control group that is already configured is kept as is.
+  pub fn with_default_cgroup(self) -> Self {
+    Self {
+      cgroup: Some(self.cgroup.unwrap_or_default()),
+      ..self
+    }
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use {super::*, assert_matches::assert_matches};
@@ -195,4 +384,185 @@ mod tests {
       CgroupRoot::Automatic(path) if path == "/run/isolate/cgroup"
     );
   }
+
+  #[test]
+  fn with_default_cgroup() {
+    let config = Config::default();
+
+    assert!(!config.cgroup_enabled());
+
+    let config = config.with_default_cgroup();
+
+    assert!(config.cgroup_enabled());
+
+    assert_eq!(config.cgroup, Some(CgroupConfig::default()));
+
+    let config = Config {
+      cgroup: Some(CgroupConfig {
+        cpu_cores: Some("0-3".into()),
+        ..Default::default()
+      }),
+      ..Default::default()
+    }
+    .with_default_cgroup();
+
+    assert_eq!(config.cgroup.unwrap().cpu_cores.as_deref(), Some("0-3"));
+  }
+
+  #[test]
+  fn validate_valid_sets() {
+    for set in ["0", "0-3", "0-3,5,7", "1,3,5-8", "0-0"] {
+      let config = CgroupConfig {
+        cpu_cores: Some(set.into()),
+        memory_nodes: Some(set.into()),
+        ..Default::default()
+      };
+
+      assert!(config.validate().is_ok(), "{set} should be valid");
+    }
+
+    assert!(CgroupConfig::default().validate().is_ok());
+  }
+
+  #[test]
+  fn validate_malformed_sets() {
+    for (set, token) in [
+      ("", ""),
+      ("0--3", "0--3"),
+      ("0-3,,5", ""),
+      ("3-0", "3-0"),
+      ("0-3,2", "2"),
+      ("5,1", "1"),
+      ("a", "a"),
+      ("0-", "0-"),
+      (" 1", " 1"),
+    ] {
+      let config = CgroupConfig {
+        cpu_cores: Some(set.into()),
+        ..Default::default()
+      };
+
+      assert_matches!(
+        config.validate(),
+        Err(Error::Config(message)) if message == format!("invalid cpu_cores `{set}`: bad token `{token}`")
+      );
+    }
+
+    let config = CgroupConfig {
+      memory_nodes: Some("0,0".into()),
+      ..Default::default()
+    };
+
+    assert_matches!(
+      config.validate(),
+      Err(Error::Config(message)) if message.starts_with("invalid memory_nodes")
+    );
+  }
+
+  #[test]
+  fn apply() {
+    let cgroup = tempfile::tempdir().unwrap();
+
+    let path = Utf8Path::from_path(cgroup.path()).unwrap();
+
+    let read = |file| fs::read_to_string(path.join(file)).unwrap();
+
+    CgroupConfig {
+      cpu_cores: Some("0-3".into()),
+      memory_limit: Some(1024),
+      ..Default::default()
+    }
+    .apply(path)
+    .unwrap();
+
+    assert_eq!(read("memory.max"), "1048576");
+    assert_eq!(read("cpuset.cpus"), "0-3");
+    assert_eq!(read("cpuset.mems"), "");
+
+    CgroupConfig {
+      memory_limit: None,
+      ..Default::default()
+    }
+    .apply(path)
+    .unwrap();
+
+    assert_eq!(read("memory.max"), "max");
+    assert_eq!(read("cpuset.cpus"), "");
+  }
+
+  #[test]
+  fn apply_with_system() {
+    let system = InMemorySystem::default();
+
+    let cgroup = Utf8Path::new("/sys/fs/cgroup/isolate/box-0");
+
+    CgroupConfig {
+      memory_limit: Some(2048),
+      memory_nodes: Some("0".into()),
+      ..Default::default()
+    }
+    .apply_with(&system, cgroup)
+    .unwrap();
+
+    assert_eq!(
+      *system.files.lock().unwrap(),
+      BTreeMap::from([
+        (cgroup.join("cpuset.cpus"), String::new()),
+        (cgroup.join("cpuset.mems"), "0".into()),
+        (cgroup.join("memory.max"), "2097152".into()),
+      ])
+    );
+  }
+
+  #[test]
+  fn apply_to_missing_cgroup() {
+    assert_matches!(
+      CgroupConfig::default().apply(Utf8Path::new("/nonexistent/cgroup")),
+      Err(Error::Cgroup(message, error))
+        if message.starts_with("failed to write `/nonexistent/cgroup/memory.max` of the memory controller")
+          && error.kind() == io::ErrorKind::NotFound
+    );
+  }
+
+  #[test]
+  fn resolve_cgroup_root() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+
+    fs::write(file.path(), "/sys/fs/cgroup/isolate\n").unwrap();
+
+    let root = CgroupRoot::Automatic(Utf8PathBuf::from_path_buf(file.path().into()).unwrap());
+
+    assert_eq!(root.resolve().unwrap(), "/sys/fs/cgroup/isolate");
+
+    let root = CgroupRoot::Manual(Utf8PathBuf::from("/sys/fs/cgroup/isolate"));
+
+    assert_eq!(root.resolve().unwrap(), "/sys/fs/cgroup/isolate");
+
+    assert_matches!(
+      CgroupRoot::Automatic(Utf8PathBuf::from("/nonexistent/root")).resolve(),
+      Err(Error::Cgroup(message, _))
+        if message.starts_with("failed to read control group root from `/nonexistent/root`")
+    );
+  }
+
+  #[test]
+  fn resolve_cgroup_root_with_system() {
+    let system = InMemorySystem::default();
+
+    system
+      .write_file("/run/isolate/cgroup".into(), "/sys/fs/cgroup/isolate\n")
+      .unwrap();
+
+    assert_eq!(
+      CgroupRoot::default().resolve_with(&system).unwrap(),
+      "/sys/fs/cgroup/isolate"
+    );
+
+    assert_matches!(
+      CgroupRoot::Automatic("/run/isolate/missing".into()).resolve_with(&system),
+      Err(Error::Cgroup(message, error))
+        if message.starts_with("failed to read control group root from `/run/isolate/missing`")
+          && error.kind() == io::ErrorKind::NotFound
+    );
+  }
 }
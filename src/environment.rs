@@ -1,7 +1,43 @@
 use super::*;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Environment {
+  /// Append a record of every execution to this file, as a line of JSON
+  /// with the time it started, the sandbox id, the uid of the caller, the
+  /// program and its arguments, the limits, and the final status, which is
+  /// `error` if the execution failed.
+  ///
+  /// The file is created with mode 0600 if it does not exist. Writing the
+  /// record is best-effort: a failure is reported on stderr, but does not
+  /// fail the execution.
+  pub audit_log: Option<Utf8PathBuf>,
+
+  /// Mode of the box directory the program runs in (default: 0700).
+  ///
+  /// The box is owned by the sandbox user and group, so e.g. 0750 lets a
+  /// monitoring process in the sandbox group read its contents. The
+  /// sandbox directory containing the box is made traversable to the same
+  /// classes of users as the box itself. The mode must not grant write
+  /// access to others, see `Environment::validate`.
+  pub box_mode: u32,
+
+  /// Default root of the control groups of every sandbox.
+  ///
+  /// Used by sandboxes whose `CgroupConfig::root` is left at its default, so
+  /// the root can be set once for all of them. A root set explicitly in a
+  /// `CgroupConfig` takes precedence over this one, unless it is equal to
+  /// the default root, which is indistinguishable from leaving it unset.
+  pub cgroup_root: Option<CgroupRoot>,
+
+  /// Create `sandbox_root` upon `Sandbox` initialization if it does not
+  /// exist (default: true).
+  ///
+  /// Disable this when `sandbox_root` is provisioned ahead of time, e.g. by
+  /// configuration management, so that initialization only verifies it and
+  /// fails with `Error::Config` if it is missing, rather than creating it
+  /// with permissions of its own.
+  pub create_root: bool,
+
   /// First gid to use for sandboxes.
   ///
   /// The gids from `first_sandbox_gid` to `first_sandbox_gid + num_sandboxes` will be used for
@@ -16,6 +52,9 @@ pub struct Environment {
 
   /// Directory where lock files are created.
   ///
+  /// Like `sandbox_root`, this directory and all its ancestors must be
+  /// writeable only to root.
+  ///
   /// This directory is created and verified upon `Sandbox` initialization.
   pub lock_root: Utf8PathBuf,
 
@@ -30,19 +69,234 @@ pub struct Environment {
   /// To avoid symlink attacks, this directory and all its ancestors
   /// must be writeable only to root.
   ///
-  /// This directory is created and verified upon `Sandbox` initialization.
+  /// This directory is (optionally, see `create_root`) created and verified
+  /// upon `Sandbox` initialization.
   pub sandbox_root: Utf8PathBuf,
+
+  /// Back `sandbox_root` with a `tmpfs` of the given size in megabytes.
+  ///
+  /// The filesystem is mounted upon `Sandbox` initialization (unless it is
+  /// already mounted) and unmounted by `Environment::teardown`, which avoids
+  /// creating and removing sandbox directories on disk for every run.
+  ///
+  /// Everything stored in the sandboxes lives in memory, so it does not
+  /// survive a reboot or a teardown.
+  pub sandbox_root_tmpfs: Option<u32>,
 }
 
 impl Default for Environment {
   fn default() -> Self {
     Self {
+      audit_log: None,
+      box_mode: 0o700,
+      cgroup_root: None,
+      create_root: true,
       first_sandbox_gid: 60000,
       first_sandbox_uid: 60000,
       lock_root: Utf8PathBuf::from("/run/isolate/locks"),
       num_sandboxes: 1000,
       restrict_initialization: false,
       sandbox_root: Utf8PathBuf::from("/var/local/lib/isolate"),
+      sandbox_root_tmpfs: None,
+    }
+  }
+}
+
+impl Environment {
+  /// Initialize the first sandbox that is not in use by anyone else.
+  ///
+  /// The ids from 0 to `num_sandboxes` are tried in order, skipping every
+  /// sandbox whose lock is held, and the sandbox keeps its lock until it is
+  /// cleaned up or dropped. Fails with `Error::Config` if every sandbox is in
+  /// use.
+  pub fn allocate_sandbox(&self) -> Result<Sandbox<'_>> {
+    for id in 0..self.num_sandboxes {
+      let mut sandbox = Sandbox::try_from((
+        Config {
+          sandbox_id: Some(id),
+          ..Default::default()
+        },
+        self,
+      ))?;
+
+      match sandbox.initialize() {
+        Ok(()) => return Ok(sandbox),
+        Err(Error::Busy(_)) => continue,
+        Err(error) => return Err(error),
+      }
+    }
+
+    Err(Error::Config("no free sandbox available".into()))
+  }
+
+  /// Environment for short-lived hosts such as CI runners and containers.
+  ///
+  /// Keeps a small pool of 16 sandboxes in a 512 MB `tmpfs` mounted over
+  /// `sandbox_root`, so nothing is written to disk and the host is expected
+  /// to be thrown away afterwards (or `Environment::teardown` to be called).
+  /// Assumes the host has enough memory to spare for the `tmpfs`, that it
+  /// runs few jobs at once, and that the default uids and gids from 60000
+  /// are not used by anyone else.
+  pub fn ci_ephemeral() -> Self {
+    Self {
+      num_sandboxes: 16,
+      ..Default::default()
+    }
+    .with_tmpfs_sandbox_root(512)
+  }
+
+  /// Environment for a dedicated judging host.
+  ///
+  /// Keeps the default pool of 1000 sandboxes on disk under
+  /// `/var/local/lib/isolate` and only lets root create sandboxes. Assumes
+  /// the host runs nothing but the judge, and that the uids and gids from
+  /// 60000 to 61000 are reserved for it.
+  ///
+  /// Control groups are configured per sandbox, with `Config::cgroup`, not
+  /// by this preset.
+  pub fn judge() -> Self {
+    Self {
+      restrict_initialization: true,
+      ..Default::default()
+    }
+  }
+
+  /// Unmount the `tmpfs` backing `sandbox_root`, discarding the contents of
+  /// every sandbox.
+  ///
+  /// Does nothing unless `sandbox_root_tmpfs` is set.
+  pub fn teardown(&self) -> Result {
+    self.teardown_with(&MaterialSystem)
+  }
+
+  /// Unmount the `tmpfs` backing `sandbox_root` like
+  /// `Environment::teardown`, through `system`.
+  pub(crate) fn teardown_with(&self, system: &dyn System) -> Result {
+    if self.sandbox_root_tmpfs.is_none() {
+      return Ok(());
+    }
+
+    system.unmount(&self.sandbox_root)
+  }
+
+  /// Check that the uids and gids of all sandboxes fit into a `u32`, and
+  /// that `box_mode` does not make the box world-writable.
+  ///
+  /// Otherwise, the ids would wrap around to low ids such as root's, and the
+  /// last id, `u32::MAX`, means "unchanged" to `setresuid(2)`.
+  pub fn validate(&self) -> Result {
+    ensure!(
+      self.box_mode & 0o002 == 0,
+      Error::Config(format!("box mode {:o} is world-writable", self.box_mode))
+    );
+
+    for first in [self.first_sandbox_uid, self.first_sandbox_gid] {
+      ensure!(
+        first.checked_add(self.num_sandboxes).is_some(),
+        Error::Config("sandbox uid/gid range overflows".into())
+      );
+    }
+
+    Ok(())
+  }
+
+  /// Back `sandbox_root` with a `tmpfs` of `size_mb` megabytes.
+  pub fn with_tmpfs_sandbox_root(self, size_mb: u32) -> Self {
+    Self {
+      sandbox_root_tmpfs: Some(size_mb),
+      ..self
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use {super::*, assert_matches::assert_matches};
+
+  #[test]
+  fn clone() {
+    let environment = Environment {
+      cgroup_root: Some(CgroupRoot::Manual("/sys/fs/cgroup/isolate".into())),
+      num_sandboxes: 100,
+      ..Default::default()
+    };
+
+    let mut worker = environment.clone();
+
+    assert_eq!(worker, environment);
+
+    worker.sandbox_root = "/var/local/lib/isolate-worker".into();
+
+    assert_ne!(worker, environment);
+  }
+
+  #[test]
+  fn validate() {
+    assert_matches!(Environment::default().validate(), Ok(()));
+
+    assert_matches!(
+      Environment {
+        first_sandbox_uid: u32::MAX - 10,
+        num_sandboxes: 10,
+        ..Default::default()
+      }
+      .validate(),
+      Ok(())
+    );
+
+    for environment in [
+      Environment {
+        first_sandbox_uid: u32::MAX - 1,
+        num_sandboxes: 10,
+        ..Default::default()
+      },
+      Environment {
+        first_sandbox_gid: u32::MAX - 1,
+        num_sandboxes: 10,
+        ..Default::default()
+      },
+    ] {
+      assert_matches!(
+        environment.validate(),
+        Err(Error::Config(message)) if message == "sandbox uid/gid range overflows"
+      );
+    }
+
+    assert_matches!(
+      Environment {
+        box_mode: 0o751,
+        ..Default::default()
+      }
+      .validate(),
+      Ok(())
+    );
+
+    assert_matches!(
+      Environment {
+        box_mode: 0o777,
+        ..Default::default()
+      }
+      .validate(),
+      Err(Error::Config(message)) if message == "box mode 777 is world-writable"
+    );
+  }
+
+  #[test]
+  fn teardown() {
+    let system = InMemorySystem::default();
+
+    system
+      .mounts
+      .lock()
+      .unwrap()
+      .insert("/var/local/lib/isolate".into(), 64);
+
+    Environment::default().teardown_with(&system).unwrap();
+
+    assert_eq!(system.mounts.lock().unwrap().len(), 1);
+
+    Environment::ci_ephemeral().teardown_with(&system).unwrap();
+
+    assert!(system.mounts.lock().unwrap().is_empty());
+  }
+}
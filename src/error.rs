@@ -1,4 +1,4 @@
-use thiserror::Error;
+use {super::*, thiserror::Error};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -6,16 +6,99 @@ pub enum Error {
   AlreadyInitialized,
   #[error("box id {0} out of range (allowed: 0-{1})")]
   BoxIdOutOfRange(u32, u32),
+  #[error("sandbox {0} is in use")]
+  Busy(u32),
+  #[error("cgroup error: {0}")]
+  Cgroup(String, #[source] io::Error),
   #[error("configuration error: {0}")]
   Config(String),
+  #[error("internal error: {0}")]
+  Internal(String),
   #[error("io error: {0}")]
   Io(#[from] std::io::Error),
+  #[error("sandbox {0} is already executing a program")]
+  Locked(u32),
   #[error("invalid mount: {0}")]
   Mount(String),
   #[error("sandbox has not been initialized")]
   NotInitialized,
   #[error("operation requires root privileges")]
   NotRoot,
+  #[error("sandbox {0} is not executing a program")]
+  NotRunning(u32),
   #[error("permission error: {0}")]
   Permission(String),
+  #[error("prelude failed: {0}")]
+  Prelude(String),
+  #[error("timeout: {0}")]
+  Timeout(String),
+  #[error("transient error: {0}")]
+  Transient(String),
+}
+
+impl Error {
+  /// Whether the operation that failed may succeed when it is tried again
+  /// later, e.g. because the sandbox was busy or the system was temporarily
+  /// out of resources.
+  ///
+  /// Errors caused by the configuration, missing privileges or a broken
+  /// setup are never retryable, since trying again fails the same way. This
+  /// includes control group errors, which usually mean that the host is
+  /// misconfigured, unless a control group was just busy.
+  pub fn is_retryable(&self) -> bool {
+    match self {
+      Self::Busy(_) | Self::Locked(_) | Self::Transient(_) => true,
+      Self::Cgroup(_, error) | Self::Io(error) => matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::ResourceBusy | io::ErrorKind::WouldBlock
+      ),
+      _ => false,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, std::error::Error as _};
+
+  #[test]
+  fn cgroup() {
+    let error = Error::Cgroup(
+      "failed to write `/sys/fs/cgroup/isolate/memory.max` of the memory controller".into(),
+      io::Error::from_raw_os_error(libc::ENOENT),
+    );
+
+    assert_eq!(
+      error.to_string(),
+      "cgroup error: failed to write `/sys/fs/cgroup/isolate/memory.max` of the memory controller"
+    );
+
+    assert_eq!(
+      error.source().unwrap().to_string(),
+      io::Error::from_raw_os_error(libc::ENOENT).to_string()
+    );
+  }
+
+  #[test]
+  fn is_retryable() {
+    assert!(Error::Busy(0).is_retryable());
+    assert!(Error::Locked(0).is_retryable());
+    assert!(Error::Transient("failed to create child: EAGAIN".into()).is_retryable());
+    assert!(Error::Io(io::ErrorKind::WouldBlock.into()).is_retryable());
+    assert!(Error::Cgroup(
+      "failed to write `cgroup.procs`".into(),
+      io::Error::from_raw_os_error(libc::EBUSY)
+    )
+    .is_retryable());
+
+    assert!(!Error::Config("`program` must be set".into()).is_retryable());
+    assert!(!Error::Internal("failed to create pipe: EMFILE".into()).is_retryable());
+    assert!(!Error::Io(io::ErrorKind::NotFound.into()).is_retryable());
+    assert!(!Error::NotRoot.is_retryable());
+    assert!(!Error::Cgroup(
+      "failed to write `memory.max`".into(),
+      io::ErrorKind::NotFound.into()
+    )
+    .is_retryable());
+  }
 }
@@ -1,43 +1,74 @@
 use super::*;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ExecutionContext<'a> {
-  /// Arguments to pass to the program.
-  pub arguments: Option<Vec<&'a str>>,
+  /// Fall back to `chroot` when `pivot_root` cannot be used to switch to the
+  /// sandbox's root filesystem.
+  ///
+  /// The sandbox uses `pivot_root` by default, which moves the original root
+  /// out of the mount namespace entirely. A `chroot` only changes the
+  /// directory path lookups start from, and a process keeping a reference
+  /// to a directory outside of it (or able to regain the capability to call
+  /// `chroot` again) can escape it, so this should only be enabled on kernels
+  /// or filesystems where `pivot_root` fails (e.g. when the sandbox runs from
+  /// an initramfs).
+  ///
+  /// Defaults to `false`.
+  pub allow_chroot_fallback: bool,
 
-  /// Limit size of core files created when a process crashes to 'size'
-  /// kilobytes.
+  /// Arguments to pass to the program.
   ///
-  /// Defaults to zero, meaning that no core files are produced inside the
-  /// sandbox.
-  pub core_size_limit_kb: Option<u32>,
+  /// They are either borrowed, see `ExecutionContext::new`, or owned, see
+  /// `ExecutionContext::owned`.
+  pub arguments: Option<Vec<Cow<'a, str>>>,
 
-  /// When the `time` limit is exceeded, do not kill the program immediately,
-  /// but wait until `extra_time` seconds elapse since the start of the
-  /// program.
+  /// Capture the program's standard output and standard error into
+  /// `ExecutionResult::stdout` and `ExecutionResult::stderr`.
   ///
-  /// This allows to report the real execution time, even if it exceeds the
-  /// limit slightly.
+  /// This works together with `stdout` and `stderr`: when a file is set as
+  /// well, the output is written to the file in full and also captured, like
+  /// `tee`. With `stderr_to_stdout`, both streams end up in `stdout`.
   ///
-  /// Fractional numbers are allowed.
+  /// Only the first 16 MiB of each stream are kept in the result, anything
+  /// past that is dropped from the captured copy (but still written to the
+  /// file, if any). Invalid UTF-8 is replaced with U+FFFD.
+  ///
+  /// Defaults to `false`.
+  pub capture_output: bool,
+
+  /// Disable address space layout randomization for the program.
+  ///
+  /// This sets `ADDR_NO_RANDOMIZE` in the personality of the program, so the
+  /// addresses of its stack, heap and libraries are the same on every run,
+  /// which helps reproducing crashes and other flaky behavior of a program.
   ///
-  /// Defaults to 0.5 seconds.
-  pub extra_time_ms: Option<f64>,
+  /// Defaults to `false`.
+  pub disable_aslr: bool,
 
-  /// Limit size of each file created (or modified) by the program to 'size'
-  /// kilobytes.
+  /// Describe the limits of the program to it in its environment, so that
+  /// it can adapt to them, e.g. pick the size of its heap.
+  ///
+  /// The variables, each set only if the limit is, are:
   ///
-  /// In most cases, it is better to restrict overall disk usage by a disk
-  /// quota (see below).
+  /// - `ISOLATE_MEMORY_LIMIT_KB`: `Limits::memory_limit_kb`
+  /// - `ISOLATE_PROCESS_LIMIT`: `Limits::process_limit`
+  /// - `ISOLATE_STACK_LIMIT_KB`: `Limits::stack_limit_kb`
+  /// - `ISOLATE_TIME_LIMIT_MS`: `Limits::time_limit_ms`
+  /// - `ISOLATE_WALL_TIME_LIMIT_MS`: `Limits::wall_time_limit_ms`
   ///
-  /// This option can help in cases when quotas are not enabled
-  /// on the underlying filesystem.
+  /// Times may be fractional, e.g. `1500.5`. The variables replace inherited
+  /// ones of the same name, and variable rules apply on top of them.
+  /// Defaults to `false`.
+  pub export_limits_to_env: bool,
+
+  /// The hostname of the sandbox.
   ///
-  /// If this limit is reached, system calls expanding files fail with error
-  /// EFBIG and the program receives the SIGXFSZ signal.
+  /// The program runs in a UTS namespace of its own, so it never sees the
+  /// hostname of the machine it runs on. If this is `None`, the namespace
+  /// keeps a copy of that hostname instead.
   ///
-  /// Defaults to 8 MB.
-  pub file_size_limit_kb: Option<u32>,
+  /// Defaults to `box`.
+  pub hostname: Option<String>,
 
   /// Inherit all variables from the parent.
   ///
@@ -47,51 +78,106 @@ pub struct ExecutionContext<'a> {
   pub inherit_env: bool,
 
   /// By default, isolate closes all file descriptors passed from its parent
-  /// except for descriptors 0, 1, and 2.
+  /// except for descriptors 0, 1, and 2, when the program is executed.
   ///
   /// This prevents unintentional descriptor leaks. In some cases, passing
   /// extra descriptors to the sandbox can be desirable, so you can use this
   /// switch to make them survive.
   pub inherit_fds: bool,
 
-  /// Limit address space of the program to 'size' kilobytes.
+  /// Resource limits of the program, see `Limits`.
+  pub limits: Limits,
+
+  /// Let the program mark the end of its warmup, e.g. the startup of an
+  /// interpreter, so that the time after it can be told apart.
   ///
-  /// If more processes are allowed, this applies to each of them separately.
+  /// The program gets the write end of a pipe, whose descriptor is in the
+  /// `ISOLATE_WARMUP_FD` variable, and writes anything to it once it is
+  /// ready. The CPU and wall-clock time it used after that are reported in
+  /// `ExecutionResult::cpu_time_after_warmup_ms` and
+  /// `ExecutionResult::wall_time_after_warmup_ms`, besides the total ones.
   ///
-  /// If this limit is reached, further memory allocations fail (e.g., malloc
-  /// returns NULL).
-  pub memory_limit_kb: Option<u32>,
+  /// This only measures: the limits still apply to the whole run, so that a
+  /// program cannot escape them by signalling late. Raise them by the
+  /// expected warmup to keep it from counting against them. The CPU time at
+  /// the end of the warmup is that of the program itself, not counting the
+  /// processes it started. Defaults to `false`.
+  pub measure_after_warmup: bool,
 
   /// Which directories to mount for this program.
   ///
   /// See `ExecutionContext::default_mounts` for the default set of mounts.
-  mounts: Vec<Mount>,
+  pub(crate) mounts: Vec<Mount>,
 
-  /// Limit number of open files to 'max'. The default value is 64. Setting
-  /// this option to 0 will result in unlimited open files.
+  /// How often the watchdog checks the program's CPU time, in milliseconds.
   ///
-  /// If this limit is reached, system calls creating file descriptors fail
-  /// with error EMFILE.
-  pub open_files_limit: Option<u32>,
-
-  /// Permit the program to create up to 'max' processes and/or threads.
+  /// The watchdog sleeps on the program and a timer rather than polling, and
+  /// always wakes up exactly at the wall-time deadline, so this only affects
+  /// how far the program can overshoot `time_limit_ms` (plus
+  /// `extra_time_ms`) before it is killed. Smaller values give tighter
+  /// enforcement of short limits at the cost of more wakeups.
   ///
-  /// Please keep in mind that time and memory limit do not work with multiple
-  /// processes unless you enable the control group mode.
+  /// Fractional numbers are allowed.
   ///
-  /// If 'max' is not given, an arbitrary number of processes can be run.
+  /// Defaults to 10 milliseconds.
+  pub poll_interval_ms: f64,
+
+  /// A command run inside the box before the program, e.g. to unpack an
+  /// archive or generate input, given as the path of the executable followed
+  /// by its arguments.
   ///
-  /// By default, only one process is permitted.
+  /// The command runs as the same user and with the same environment,
+  /// mounts and working directory as the program, but without its resource
+  /// limits. The wall time limit only starts once the program is executed,
+  /// so a command that hangs is only stopped by `Config::setup_timeout_ms`.
+  /// Its standard streams are connected to `/dev/null`, so it does not show
+  /// up in the captured output.
   ///
-  /// If this limit is exceeded, system calls creating processes fail with
-  /// error EAGAIN.
-  pub process_limit: Option<u32>,
+  /// If the command does not exit with status 0, the program is not run and
+  /// the result has `Status::InternalError`. Exit status 127 means that the
+  /// command could not be executed.
+  pub prelude: Option<Vec<String>>,
 
   /// The program to run.
   ///
-  /// This is the only required field.
+  /// This is the only required field. It is a path inside the sandbox,
+  /// unless `search_path` is set.
   pub program: String,
 
+  /// Restore the box to the state it was in before `Sandbox::execute_batch`
+  /// was called before each of its runs but the first, so no run sees the
+  /// files of the runs before it.
+  ///
+  /// The box is copied when the batch starts and recreated from the copy
+  /// between runs, so files put there beforehand, e.g. a program compiled by
+  /// an earlier `Sandbox::execute`, survive, while the files the runs of the
+  /// batch write do not. Plain `Sandbox::execute` ignores this.
+  pub reset_box_between_runs: bool,
+
+  /// Run the program as the user who invoked the sandbox (or `as_uid` and
+  /// `as_gid`, if set) instead of the sandbox user.
+  ///
+  /// Resource limits, namespaces, mounts and usage reporting are applied as
+  /// usual, so this is meant for measuring trusted code, e.g. for profiling.
+  ///
+  /// This defeats isolation: the program can access everything the invoking
+  /// user can, which is everything if the sandbox was invoked by root. Never
+  /// use it for untrusted code.
+  ///
+  /// The box is owned by the sandbox user, so unless the invoking user is
+  /// root, the program cannot write to its working directory.
+  pub run_as_invoking_user: bool,
+
+  /// Look up a `program` without a slash in the directories of `PATH`, like
+  /// `execvp(3)` does, instead of treating it as a path relative to the
+  /// working directory.
+  ///
+  /// The lookup happens inside the sandbox, so only programs that are
+  /// mounted into it are found, and it uses the `PATH` of the program's
+  /// environment (see `variables`), not the one of the caller, falling back
+  /// to `/bin:/usr/bin` if it is not set.
+  pub search_path: bool,
+
   /// By default, isolate creates a new network namespace for its child
   /// process.
   ///
@@ -106,13 +192,17 @@ pub struct ExecutionContext<'a> {
 
   /// Tell the sandbox manager to keep silence.
   ///
-  /// No status messages are printed to stderr except for fatal errors of the
-  /// sandbox itself.
+  /// No status messages, such as the outcome of every run or the description
+  /// of the run in verbose mode, are printed to stderr. Fatal errors of the
+  /// sandbox itself are returned as errors either way.
   pub silent: bool,
 
   /// By default, Isolate removes all special files (other than regular files
   /// and directories) created inside the sandbox.
   ///
+  /// The box is swept after every run, and symbolic links count as special
+  /// files, too. Nothing outside of the box is touched.
+  ///
   /// If you need them, this option disables that behavior, but you need to
   /// carefully check what you open.
   pub special_files: bool,
@@ -120,7 +210,8 @@ pub struct ExecutionContext<'a> {
   /// Redirect standard error output to a file.
   ///
   /// The file has to be accessible inside the sandbox (which means that the
-  /// sandboxed program can manipulate it arbitrarily).
+  /// sandboxed program can manipulate it arbitrarily). Like `stdout`, the
+  /// path is a path inside the sandbox.
   ///
   /// If not specified, standard error output is inherited from the parent
   /// process.
@@ -128,15 +219,10 @@ pub struct ExecutionContext<'a> {
   /// See also `stderr-to-stdout`.
   pub stderr: Option<Utf8PathBuf>,
 
-  /// Limit process stack to 'size' kilobytes.
+  /// Append to the `stderr` file instead of truncating it.
   ///
-  /// By default, the whole address space is available for the stack, but it is
-  /// subject to the `memory_limit` limit.
-  ///
-  /// If this limit is exceeded, the program receives the SIGSEGV signal.
-  ///
-  /// Defaults to 32 MB.
-  pub stack_limit_kb: Option<u32>,
+  /// Requires `stderr` to be set.
+  pub stderr_append: bool,
 
   /// Redirect standard error output to standard output.
   ///
@@ -145,34 +231,68 @@ pub struct ExecutionContext<'a> {
   /// Mutually exclusive with `stderr`.
   pub stderr_to_stdout: bool,
 
-  /// Redirect standard input from a file.
-  ///
-  /// The file has to be accessible inside the sandbox
-  /// (which means that the sandboxed program can manipulate it arbitrarily).
+  /// Where standard input comes from.
   ///
-  /// If not specified, standard input is inherited from the parent process.
-  pub stdin: Option<Utf8PathBuf>,
+  /// Defaults to `/dev/null` (unless `tty_hack` is set), so that programs
+  /// waiting for input do not hang until they hit the wall time limit. Use
+  /// `Stdin::Inherit` to inherit it from the parent process instead.
+  pub stdin: Stdin,
 
   /// Redirect standard output to a file.
   ///
   /// The file has to be accessible inside the sandbox (which means that the
   /// sandboxed program can manipulate it arbitrarily).
   ///
+  /// Like every redirection, the path is a path inside the sandbox, never on
+  /// the host: it is opened as the sandbox user after entering the sandbox,
+  /// and relative paths are resolved against `working_directory`. Executing
+  /// fails with `Error::Config` if the directory of the file does not exist
+  /// inside the sandbox.
+  ///
   /// If not specified, standard output is inherited from the parent process
   /// and the sandbox manager does not write anything to it.
   pub stdout: Option<Utf8PathBuf>,
 
-  /// Limit run time of the program to 'time' milliseconds.
+  /// Append to the `stdout` file instead of truncating it.
   ///
-  /// Fractional numbers are allowed.
+  /// This allows collecting the output of several runs in the same file.
+  ///
+  /// Requires `stdout` to be set.
+  pub stdout_append: bool,
+
+  /// Make the whole filesystem read-only except for `/box`.
+  ///
+  /// Every default mount is forced to be read-only and the temporary `/tmp`
+  /// mount is dropped, so that any attempt to write outside of the working
+  /// directory fails.
+  ///
+  /// Mounts added by the user are left untouched, so one that was explicitly
+  /// made read-write stays writable.
+  pub strict_readonly: bool,
+
+  /// Give the program an `/etc/passwd` and an `/etc/group` that contain
+  /// nothing but the user and the group it runs as, both named `sandbox`.
   ///
-  /// Time in which the OS assigns the processor to other tasks is not counted.
+  /// Without them, looking up the user the program runs as (e.g. with
+  /// `getpwuid(3)`, `whoami` or `id -un`) fails. The files are written to
+  /// the sandbox directory before every run and mounted read-only, shadowing
+  /// any `/etc/passwd` and `/etc/group` from the mounts. The home directory
+  /// of the user is `/box`.
+  pub synthetic_passwd: bool,
+
+  /// Record the system calls the program makes into
+  /// `ExecutionResult::syscalls`, to find out e.g. which call a program that
+  /// fails mysteriously died on.
   ///
-  /// If this limit is exceeded, the program is killed (after `extra_time`, if
-  /// set).
+  /// The program is traced with `ptrace`, which stops it on every system call
+  /// and slows down programs that make many of them considerably, so this is
+  /// only meant for debugging and time limits are not meaningful with it.
+  /// Only the program itself is traced, not the processes it starts, and only
+  /// the last 100000 calls are kept.
   ///
-  /// Defaults to 1 second.
-  pub time_limit_ms: Option<f64>,
+  /// Requires the `trace` feature.
+  #[cfg(feature = "trace")]
+  pub trace_syscalls: bool,
 
   /// Try to handle interactive programs communicating over a tty.
   ///
@@ -180,7 +300,12 @@ pub struct ExecutionContext<'a> {
   /// temporarily become the foreground process group of the terminal.
   ///
   /// When the program exits, the process group will be switched back to the
-  /// caller.
+  /// caller, and the terminal attributes (see `termios(3)`) are restored to
+  /// what they were before the run.
+  ///
+  /// Standard input is inherited from the caller unless `stdin` is set, and
+  /// the run fails with `Error::Config` if the caller has no controlling
+  /// terminal.
   ///
   /// Please note that the program can do many nasty things including (but not
   /// limited to) changing terminal settings, changing the line discipline, and
@@ -194,57 +319,53 @@ pub struct ExecutionContext<'a> {
   ///
   /// If `inherit_env` is set to `true`, all environment variables from the parent are inherited,
   /// other variables specified by the user are added to the environment after.
-  variables: Vec<Variable>,
-
-  /// Limit wall-clock time to 'time' seconds.
-  ///
-  /// Fractional values are allowed.
-  ///
-  /// This clock measures the time from the start of the program to its exit,
-  /// so it does not stop when the program has lost the CPU or when it is
-  /// waiting for an external event.
-  ///
-  /// We recommend to use `time_limit` as the main limit, but set
-  /// `wall_time_limit_ms` to a much higher value as a precaution against
-  /// sleeping programs.
-  ///
-  /// If this limit is exceeded, the program is killed.
   ///
-  /// Defaults to 5 seconds.
-  pub wall_time_limit_ms: Option<f64>,
+  /// The rules are applied in order, so a later rule for a key overrides an
+  /// earlier one, and `Clear` removes an inherited variable.
+  variables: Vec<Variable>,
 
   /// Change directory to a specified path before executing the program.
   ///
-  /// This path must be relative to the root of the sandbox.
+  /// This path must be relative to the root of the sandbox. Defaults to
+  /// `/box`, where the files of the caller live, if it is not set.
   pub working_directory: Option<Utf8PathBuf>,
 }
 
 impl Default for ExecutionContext<'_> {
   fn default() -> Self {
     Self {
+      allow_chroot_fallback: false,
       arguments: None,
-      core_size_limit_kb: Some(0),
-      extra_time_ms: Some(0.5 * 1000.0),
-      file_size_limit_kb: Some(8192),
+      capture_output: false,
+      disable_aslr: false,
+      export_limits_to_env: false,
+      hostname: Some("box".into()),
       inherit_env: false,
       inherit_fds: false,
-      memory_limit_kb: Some(256_000),
-      mounts: Self::default_mounts().unwrap(),
-      open_files_limit: Some(64),
-      process_limit: Some(1),
+      limits: Limits::default(),
+      measure_after_warmup: false,
+      mounts: Self::default_mounts(),
+      poll_interval_ms: 10.0,
+      prelude: None,
       program: String::new(),
+      reset_box_between_runs: false,
+      run_as_invoking_user: false,
+      search_path: false,
       share_net: false,
       silent: false,
       special_files: false,
-      stack_limit_kb: Some(32_000),
       stderr: None,
+      stderr_append: false,
       stderr_to_stdout: false,
-      stdin: None,
+      stdin: Stdin::default(),
       stdout: None,
-      time_limit_ms: Some(1.0 * 1000.0),
+      stdout_append: false,
+      strict_readonly: false,
+      synthetic_passwd: false,
+      #[cfg(feature = "trace")]
+      trace_syscalls: false,
       tty_hack: false,
       variables: Vec::new(),
-      wall_time_limit_ms: Some(5.0 * 1000.0),
       working_directory: None,
     }
   }
@@ -254,18 +375,71 @@ impl<'a> ExecutionContext<'a> {
   pub fn new(program: String, arguments: Option<Vec<&'a str>>) -> Self {
     Self {
       program,
-      arguments,
+      ..Default::default()
+    }
+    .arguments(arguments)
+  }
+
+  /// Like `new`, but taking ownership of the arguments, e.g. for an argument
+  /// list built at runtime, so that the context borrows nothing.
+  pub fn owned(program: String, arguments: Vec<String>) -> ExecutionContext<'static> {
+    ExecutionContext {
+      arguments: Some(arguments.into_iter().map(Cow::Owned).collect()),
+      program,
       ..Default::default()
     }
   }
 
+  /// Add several mounts to the list of mounts, in order.
+  ///
+  /// Like `mount`, each mount replaces any mount with the same inside path
+  /// in place, so the default mounts are kept unless overridden.
+  pub fn add_mounts(mut self, mounts: impl IntoIterator<Item = Mount>) -> Self {
+    for mount in mounts {
+      match self
+        .mounts
+        .iter_mut()
+        .find(|existing| existing.relative_inside_path() == mount.relative_inside_path())
+      {
+        Some(existing) => *existing = mount,
+        None => self.mounts.push(mount),
+      }
+    }
+
+    self
+  }
+
+  pub fn allow_chroot_fallback(self, allow_chroot_fallback: bool) -> Self {
+    Self {
+      allow_chroot_fallback,
+      ..self
+    }
+  }
+
   pub fn arguments(self, arguments: Option<Vec<&'a str>>) -> Self {
-    Self { arguments, ..self }
+    Self {
+      arguments: arguments.map(|arguments| arguments.into_iter().map(Cow::Borrowed).collect()),
+      ..self
+    }
+  }
+
+  pub fn capture_output(self, capture_output: bool) -> Self {
+    Self {
+      capture_output,
+      ..self
+    }
   }
 
   pub fn core_size_limit_kb(self, core_size_limit_kb: u32) -> Self {
     Self {
-      core_size_limit_kb: Some(core_size_limit_kb),
+      limits: self.limits.core_size_limit_kb(core_size_limit_kb),
+      ..self
+    }
+  }
+
+  pub fn data_limit_kb(self, data_limit_kb: u32) -> Self {
+    Self {
+      limits: self.limits.data_limit_kb(data_limit_kb),
       ..self
     }
   }
@@ -286,46 +460,207 @@ impl<'a> ExecutionContext<'a> {
   /// - `/proc` (proc filesystem)
   /// - `/tmp` (temporary directory, read-write)
   ///
-  /// Mounts are processed in the order they are specified, with default mounts preceding
-  /// user-defined ones. When a mount is replaced, it maintains its original position
-  /// in the sequence.
+  /// Mounts are processed from the shallowest to the deepest `inside_path`, see
+  /// `normalized_mounts`, with default mounts preceding user-defined ones at
+  /// the same depth. When a mount is replaced, it maintains its original
+  /// position in the sequence.
   ///
   /// This ordering is significant when one mount's `inside_path` is a subdirectory of another
   /// mount's `inside_path`.
   ///
-  /// For example, mounting "a" followed by "a/b" works as expected, but subdirectory "b" must exist
-  /// in the directory mounted at "a" (the sandbox never creates subdirectories in mounted
-  /// directories for security).
-  ///
-  /// If "a/b" is mounted before "a", the mount at "a/b" becomes inaccessible due to
-  /// being overshadowed by the mount at "a".
-  fn default_mounts() -> Result<Vec<Mount>> {
-    Ok(vec![
-      Mount::read_write("box", Some("./box"))?,
-      Mount::read_only("bin", None::<&Utf8Path>)?,
-      Mount::device("dev", None::<&Utf8Path>)?,
-      Mount::read_only("lib", None::<&Utf8Path>)?,
-      Mount::optional("lib64", None::<&Utf8Path>)?,
-      Mount::filesystem("proc", "proc")?,
-      Mount::temporary("tmp")?,
-      Mount::read_only("usr", None::<&Utf8Path>)?,
-    ])
+  /// For example, "a" is always mounted before "a/b", so "a/b" is never overshadowed by
+  /// the mount at "a", but subdirectory "b" must exist in the directory mounted at "a" (the
+  /// sandbox never creates subdirectories in mounted directories for security).
+  fn default_mounts() -> Vec<Mount> {
+    let host = None::<&Utf8Path>;
+
+    let options = MountOptions::default;
+
+    vec![
+      Mount::from_parts(
+        "box",
+        Some("./box"),
+        MountOptions {
+          read_write: true,
+          ..options()
+        },
+      ),
+      Mount::from_parts("bin", host, options()),
+      Mount::from_parts(
+        "dev",
+        host,
+        MountOptions {
+          allow_devices: true,
+          ..options()
+        },
+      ),
+      Mount::from_parts("lib", host, options()),
+      Mount::from_parts(
+        "lib64",
+        host,
+        MountOptions {
+          optional: true,
+          ..options()
+        },
+      ),
+      Mount::from_parts(
+        "proc",
+        host,
+        MountOptions {
+          filesystem: Some("proc".into()),
+          ..options()
+        },
+      ),
+      Mount::from_parts(
+        "tmp",
+        host,
+        MountOptions {
+          temporary: true,
+          ..options()
+        },
+      ),
+      Mount::from_parts("usr", host, options()),
+    ]
+  }
+
+  pub fn disable_aslr(self, disable_aslr: bool) -> Self {
+    Self {
+      disable_aslr,
+      ..self
+    }
+  }
+
+  /// The mounts set up in the sandbox, taking `strict_readonly` and
+  /// `synthetic_passwd` into account.
+  pub(crate) fn effective_mounts(&self) -> Vec<Mount> {
+    let mut mounts = if self.strict_readonly {
+      let defaults = Self::default_mounts();
+
+      self
+        .mounts
+        .iter()
+        .filter(|mount| !(mount.is_temporary() && defaults.contains(mount)))
+        .map(|mount| {
+          if defaults.contains(mount) && mount.relative_inside_path() != "box" {
+            mount.clone().into_read_only()
+          } else {
+            mount.clone()
+          }
+        })
+        .collect()
+    } else {
+      self.mounts.clone()
+    };
+
+    if self.synthetic_passwd {
+      mounts.extend(["passwd", "group"].map(|name| {
+        Mount::from_parts(
+          format!("etc/{name}"),
+          Some(format!("./etc/{name}")),
+          MountOptions::default(),
+        )
+      }));
+    }
+
+    mounts
+  }
+
+  /// The directory the program runs in, `working_directory` or `/box` if it
+  /// is not set.
+  pub(crate) fn effective_working_directory(&self) -> &Utf8Path {
+    self
+      .working_directory
+      .as_deref()
+      .unwrap_or(Utf8Path::new("/box"))
+  }
+
+  /// Resolve the environment passed to the program.
+  ///
+  /// Starts from the parent's environment if `inherit_env` is set, or from an
+  /// empty one otherwise, and then applies each variable rule in order.
+  pub(crate) fn environment(&self) -> Vec<(String, String)> {
+    let parent = env::vars_os()
+      .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+      .collect::<Vec<_>>();
+
+    self.environment_from(&parent)
+  }
+
+  /// Resolve the environment against the given `parent` environment.
+  ///
+  /// Rules are applied in order, each one on top of the result of the
+  /// previous ones:
+  ///
+  /// - `Clear` removes the key, including one inherited via `inherit_env`.
+  /// - `Set` replaces any previous value of the key.
+  /// - `Inherit` replaces any previous value with the parent's, and does nothing if the parent does
+  ///   not have the key.
+  fn environment_from(&self, parent: &[(String, String)]) -> Vec<(String, String)> {
+    let mut environment = if self.inherit_env {
+      parent.to_vec()
+    } else {
+      Vec::new()
+    };
+
+    if self.export_limits_to_env {
+      for (key, value) in self.limit_variables() {
+        environment.retain(|(name, _)| name != key);
+        environment.push((key.into(), value));
+      }
+    }
+
+    let lookup = |key: &str| {
+      parent
+        .iter()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value.clone())
+    };
+
+    for variable in &self.variables {
+      let value = match &variable.action {
+        Action::Clear => None,
+        Action::Inherit => match lookup(&variable.key) {
+          Some(value) => Some(value),
+          None => continue,
+        },
+        Action::Set(value) => Some(value.clone()),
+      };
+
+      environment.retain(|(key, _)| *key != variable.key);
+
+      if let Some(value) = value {
+        environment.push((variable.key.clone(), value));
+      }
+    }
+
+    environment
+  }
+
+  pub fn export_limits_to_env(self, export_limits_to_env: bool) -> Self {
+    Self {
+      export_limits_to_env,
+      ..self
+    }
   }
 
   pub fn extra_time_ms(self, extra_time_ms: f64) -> Self {
     Self {
-      extra_time_ms: Some(extra_time_ms),
+      limits: self.limits.extra_time_ms(extra_time_ms),
       ..self
     }
   }
 
   pub fn file_size_limit_kb(self, file_size_limit_kb: u32) -> Self {
     Self {
-      file_size_limit_kb: Some(file_size_limit_kb),
+      limits: self.limits.file_size_limit_kb(file_size_limit_kb),
       ..self
     }
   }
 
+  pub fn hostname(self, hostname: Option<String>) -> Self {
+    Self { hostname, ..self }
+  }
+
   pub fn inherit_env(self, inherit_env: bool) -> Self {
     Self {
       inherit_env,
@@ -340,21 +675,125 @@ impl<'a> ExecutionContext<'a> {
     }
   }
 
+  /// Add an `Inherit` rule for each of the given keys.
+  pub fn inherit_variables(self, keys: impl IntoIterator<Item = impl Into<String>>) -> Self {
+    Self {
+      variables: self
+        .variables
+        .into_iter()
+        .chain(
+          keys
+            .into_iter()
+            .map(|key| Variable::new(key, Action::Inherit)),
+        )
+        .collect(),
+      ..self
+    }
+  }
+
+  /// The variables describing the limits, see `export_limits_to_env`.
+  fn limit_variables(&self) -> Vec<(&'static str, String)> {
+    let limits = &self.limits;
+
+    [
+      (
+        "ISOLATE_MEMORY_LIMIT_KB",
+        limits.memory_limit_kb.map(|limit| limit.to_string()),
+      ),
+      (
+        "ISOLATE_PROCESS_LIMIT",
+        limits.process_limit.map(|limit| limit.to_string()),
+      ),
+      (
+        "ISOLATE_STACK_LIMIT_KB",
+        limits.stack_limit_kb.map(|limit| limit.to_string()),
+      ),
+      (
+        "ISOLATE_TIME_LIMIT_MS",
+        limits.time_limit_ms.map(|limit| limit.to_string()),
+      ),
+      (
+        "ISOLATE_WALL_TIME_LIMIT_MS",
+        limits.wall_time_limit_ms.map(|limit| limit.to_string()),
+      ),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| Some((key, value?)))
+    .collect()
+  }
+
+  pub fn limits(self, limits: Limits) -> Self {
+    Self { limits, ..self }
+  }
+
+  pub fn locked_memory_limit_kb(self, locked_memory_limit_kb: u32) -> Self {
+    Self {
+      limits: self.limits.locked_memory_limit_kb(locked_memory_limit_kb),
+      ..self
+    }
+  }
+
+  pub fn measure_after_warmup(self, measure_after_warmup: bool) -> Self {
+    Self {
+      measure_after_warmup,
+      ..self
+    }
+  }
+
   pub fn memory_limit_kb(self, memory_limit_kb: u32) -> Self {
     Self {
-      memory_limit_kb: Some(memory_limit_kb),
+      limits: self.limits.memory_limit_kb(memory_limit_kb),
       ..self
     }
   }
 
-  /// Add a mount to the list of mounts.
-  pub fn mount(self, mount: Mount) -> Self {
+  pub fn memory_limit_mb(self, memory_limit_mb: u32) -> Self {
+    Self {
+      limits: self.limits.memory_limit_mb(memory_limit_mb),
+      ..self
+    }
+  }
+
+  pub fn message_queue_limit_kb(self, message_queue_limit_kb: u32) -> Self {
     Self {
-      mounts: self.mounts.into_iter().chain(Some(mount)).collect(),
+      limits: self.limits.message_queue_limit_kb(message_queue_limit_kb),
       ..self
     }
   }
 
+  /// Change the options of the mount at `inside_path` in place with
+  /// `modify`, e.g. to make the default `/box` read-only, keeping the rest
+  /// of its options and its position.
+  ///
+  /// Fails with `Error::Mount` if there is no mount at `inside_path`, or if
+  /// the options are invalid afterwards. As with `Mount::new`, a temporary
+  /// directory stays writable.
+  pub fn modify_mount(
+    mut self,
+    inside_path: impl AsRef<Utf8Path>,
+    modify: impl FnOnce(&mut MountOptions),
+  ) -> Result<Self> {
+    let inside_path = inside_path.as_ref();
+
+    let relative = inside_path.strip_prefix("/").unwrap_or(inside_path);
+
+    let mount = self
+      .mounts
+      .iter_mut()
+      .find(|mount| mount.relative_inside_path() == relative)
+      .ok_or_else(|| Error::Mount(format!("no mount at `{inside_path}` to modify")))?;
+
+    *mount = mount.clone().modify(modify)?;
+
+    Ok(self)
+  }
+
+  /// Add a mount to the list of mounts, replacing any mount with the same
+  /// inside path in place.
+  pub fn mount(self, mount: Mount) -> Self {
+    self.add_mounts(Some(mount))
+  }
+
   /// Replace the list of mounts with a new list.
   ///
   /// Care has to be taken to specify the correct set of
@@ -365,16 +804,148 @@ impl<'a> ExecutionContext<'a> {
     Self { mounts, ..self }
   }
 
+  /// Sort `mounts` into the order they are set up in, keeping the last of
+  /// several mounts with the same inside path in the position of the first.
+  pub(crate) fn normalize(mounts: &[Mount]) -> Result<Vec<&Mount>> {
+    let mut normalized: Vec<&Mount> = Vec::new();
+
+    for mount in mounts {
+      mount.validate()?;
+
+      match normalized
+        .iter_mut()
+        .find(|existing| existing.relative_inside_path() == mount.relative_inside_path())
+      {
+        Some(existing) => *existing = mount,
+        None => normalized.push(mount),
+      }
+    }
+
+    // Stable, so mounts at the same depth keep their relative order.
+    normalized.sort_by_key(|mount| mount.depth());
+
+    Ok(normalized)
+  }
+
+  /// The mounts in the order they are set up in the sandbox.
+  ///
+  /// Parents are mounted before their children, i.e. mounts are ordered by
+  /// the number of components of their inside path, shallowest first, and
+  /// mounts at the same depth keep the order they were specified in. Of
+  /// several mounts with the same inside path (e.g. set with `mounts`), the
+  /// last one wins. Fails if a mount is invalid, e.g. because its inside path
+  /// escapes the sandbox.
+  ///
+  /// Useful for debugging the layout of the sandbox's filesystem.
+  pub fn normalized_mounts(&self) -> Result<Vec<&Mount>> {
+    Self::normalize(&self.mounts)
+  }
+
   pub fn open_files_limit(self, open_files_limit: u32) -> Self {
     Self {
-      open_files_limit: Some(open_files_limit),
+      limits: self.limits.open_files_limit(open_files_limit),
+      ..self
+    }
+  }
+
+  pub fn pending_signals_limit(self, pending_signals_limit: u32) -> Self {
+    Self {
+      limits: self.limits.pending_signals_limit(pending_signals_limit),
+      ..self
+    }
+  }
+
+  /// The personality of the program, given the `current` one of the child.
+  pub(crate) fn persona(&self, current: Persona) -> Persona {
+    if self.disable_aslr {
+      current | Persona::ADDR_NO_RANDOMIZE
+    } else {
+      current
+    }
+  }
+
+  pub fn poll_interval_ms(self, poll_interval_ms: f64) -> Self {
+    Self {
+      poll_interval_ms,
+      ..self
+    }
+  }
+
+  pub fn prelude(self, prelude: impl IntoIterator<Item = impl Into<String>>) -> Self {
+    Self {
+      prelude: Some(prelude.into_iter().map(Into::into).collect()),
       ..self
     }
   }
 
   pub fn process_limit(self, process_limit: u32) -> Self {
     Self {
-      process_limit: Some(process_limit),
+      limits: self.limits.process_limit(process_limit),
+      ..self
+    }
+  }
+
+  /// The paths to execute the program from, in order, see `search_path`.
+  pub(crate) fn program_paths(&self) -> Vec<String> {
+    if !self.search_path || self.program.contains('/') {
+      return vec![self.program.clone()];
+    }
+
+    let path = self
+      .environment()
+      .into_iter()
+      .find(|(key, _)| key == "PATH")
+      .map_or_else(|| "/bin:/usr/bin".into(), |(_, value)| value);
+
+    path
+      .split(':')
+      .map(|directory| {
+        // An empty entry stands for the working directory.
+        if directory.is_empty() {
+          self.program.clone()
+        } else {
+          format!("{}/{}", directory.trim_end_matches('/'), self.program)
+        }
+      })
+      .collect()
+  }
+
+  pub fn reset_box_between_runs(self, reset_box_between_runs: bool) -> Self {
+    Self {
+      reset_box_between_runs,
+      ..self
+    }
+  }
+
+  pub fn run_as_invoking_user(self, run_as_invoking_user: bool) -> Self {
+    Self {
+      run_as_invoking_user,
+      ..self
+    }
+  }
+
+  pub fn search_path(self, search_path: bool) -> Self {
+    Self {
+      search_path,
+      ..self
+    }
+  }
+
+  /// Add a `Set` rule for each of the given key-value pairs.
+  pub fn set_variables(
+    self,
+    variables: impl IntoIterator<Item = (impl Into<String>, impl Into<String>)>,
+  ) -> Self {
+    Self {
+      variables: self
+        .variables
+        .into_iter()
+        .chain(
+          variables
+            .into_iter()
+            .map(|(key, value)| Variable::with_set_value(key, value)),
+        )
+        .collect(),
       ..self
     }
   }
@@ -396,7 +967,14 @@ impl<'a> ExecutionContext<'a> {
 
   pub fn stack_limit_kb(self, stack_limit_kb: u32) -> Self {
     Self {
-      stack_limit_kb: Some(stack_limit_kb),
+      limits: self.limits.stack_limit_kb(stack_limit_kb),
+      ..self
+    }
+  }
+
+  pub fn stack_limit_mb(self, stack_limit_mb: u32) -> Self {
+    Self {
+      limits: self.limits.stack_limit_mb(stack_limit_mb),
       ..self
     }
   }
@@ -405,6 +983,13 @@ impl<'a> ExecutionContext<'a> {
     Self { stderr, ..self }
   }
 
+  pub fn stderr_append(self, stderr_append: bool) -> Self {
+    Self {
+      stderr_append,
+      ..self
+    }
+  }
+
   pub fn stderr_to_stdout(self, stderr_to_stdout: bool) -> Self {
     Self {
       stderr_to_stdout,
@@ -412,17 +997,49 @@ impl<'a> ExecutionContext<'a> {
     }
   }
 
-  pub fn stdin(self, stdin: Option<Utf8PathBuf>) -> Self {
-    Self { stdin, ..self }
+  pub fn stdin(self, stdin: impl Into<Stdin>) -> Self {
+    Self {
+      stdin: stdin.into(),
+      ..self
+    }
   }
 
   pub fn stdout(self, stdout: Option<Utf8PathBuf>) -> Self {
     Self { stdout, ..self }
   }
 
+  pub fn stdout_append(self, stdout_append: bool) -> Self {
+    Self {
+      stdout_append,
+      ..self
+    }
+  }
+
+  pub fn strict_readonly(self, strict_readonly: bool) -> Self {
+    Self {
+      strict_readonly,
+      ..self
+    }
+  }
+
   pub fn time_limit_ms(self, time_limit_ms: f64) -> Self {
     Self {
-      time_limit_ms: Some(time_limit_ms),
+      limits: self.limits.time_limit_ms(time_limit_ms),
+      ..self
+    }
+  }
+
+  pub fn time_limit_secs(self, time_limit_secs: f64) -> Self {
+    Self {
+      limits: self.limits.time_limit_secs(time_limit_secs),
+      ..self
+    }
+  }
+
+  #[cfg(feature = "trace")]
+  pub fn trace_syscalls(self, trace_syscalls: bool) -> Self {
+    Self {
+      trace_syscalls,
       ..self
     }
   }
@@ -431,6 +1048,65 @@ impl<'a> ExecutionContext<'a> {
     Self { tty_hack, ..self }
   }
 
+  /// Check the context for invalid or conflicting settings, returning the
+  /// first problem found.
+  ///
+  /// `Sandbox::execute` calls this before setting anything up, but it can
+  /// also be used on its own, e.g. to reject a context before handing it to
+  /// a sandbox. Settings that depend on the sandbox, such as whether the
+  /// working directory exists in it, are only checked by `execute`.
+  pub fn validate(&self) -> Result {
+    ensure!(
+      !self.program.is_empty(),
+      Error::Config("`program` must be set".into())
+    );
+
+    ensure!(
+      self.stdout.is_some() || !self.stdout_append,
+      Error::Config("`stdout_append` requires `stdout` to be set".into())
+    );
+
+    ensure!(
+      self.stderr.is_some() || !self.stderr_append,
+      Error::Config("`stderr_append` requires `stderr` to be set".into())
+    );
+
+    ensure!(
+      self.stderr.is_none() || !self.stderr_to_stdout,
+      Error::Config("`stderr` and `stderr_to_stdout` are mutually exclusive".into())
+    );
+
+    ensure!(
+      self
+        .prelude
+        .as_ref()
+        .is_none_or(|prelude| !prelude.is_empty()),
+      Error::Config("`prelude` must not be empty".into())
+    );
+
+    ensure!(
+      self.hostname.as_ref().is_none_or(|hostname| {
+        !hostname.is_empty() && hostname.len() <= 64 && !hostname.contains('\0')
+      }),
+      Error::Config(
+        "`hostname` must be between 1 and 64 bytes long and not contain a nul byte".into()
+      )
+    );
+
+    ensure!(
+      self.poll_interval_ms.is_finite() && self.poll_interval_ms > 0.0,
+      Error::Config("`poll_interval_ms` must be a positive number".into())
+    );
+
+    self.limits.validate()?;
+
+    for mount in &self.mounts {
+      mount.validate()?;
+    }
+
+    Ok(())
+  }
+
   /// Add an environment variable to the list of environment variables.
   pub fn variable(self, variable: Variable) -> Self {
     Self {
@@ -444,9 +1120,73 @@ impl<'a> ExecutionContext<'a> {
     Self { variables, ..self }
   }
 
+  /// Add the environment variables listed in the file at `path`, one per
+  /// line, in the format of `Variable::from_str`.
+  ///
+  /// Blank lines and lines starting with `#` are skipped. Fails with
+  /// `Error::Config` on the first malformed line.
+  pub fn variables_from_file(self, path: impl AsRef<Utf8Path>) -> Result<Self> {
+    let path = path.as_ref();
+
+    let mut variables = self.variables;
+
+    for (index, line) in fs::read_to_string(path)?.lines().enumerate() {
+      let line = line.trim();
+
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+
+      variables.push(line.parse().map_err(|_| {
+        Error::Config(format!(
+          "malformed variable `{}` on line {} of `{}`",
+          line,
+          index + 1,
+          path
+        ))
+      })?);
+    }
+
+    Ok(Self { variables, ..self })
+  }
+
   pub fn wall_time_limit_ms(self, wall_time_limit_ms: f64) -> Self {
     Self {
-      wall_time_limit_ms: Some(wall_time_limit_ms),
+      limits: self.limits.wall_time_limit_ms(wall_time_limit_ms),
+      ..self
+    }
+  }
+
+  pub fn wall_time_limit_secs(self, wall_time_limit_secs: f64) -> Self {
+    Self {
+      limits: self.limits.wall_time_limit_secs(wall_time_limit_secs),
+      ..self
+    }
+  }
+
+  /// Bind the host's `/etc/resolv.conf` and `/etc/hosts` read-only into the
+  /// sandbox, so the program can resolve host names the way the host does.
+  ///
+  /// Either file is skipped if it does not exist on the host. Without
+  /// `share_net`, the program only has a loopback device, so name
+  /// resolution still fails and this is a no-op in practice.
+  pub fn with_dns(self) -> Self {
+    let options = || MountOptions {
+      optional: true,
+      ..Default::default()
+    };
+
+    self.add_mounts([
+      Mount::from_parts("etc/resolv.conf", Some("/etc/resolv.conf"), options()),
+      Mount::from_parts("etc/hosts", Some("/etc/hosts"), options()),
+    ])
+  }
+
+  /// Give the program a minimal `/etc/passwd` and `/etc/group`, see
+  /// `synthetic_passwd`.
+  pub fn with_synthetic_passwd(self) -> Self {
+    Self {
+      synthetic_passwd: true,
       ..self
     }
   }
@@ -458,3 +1198,512 @@ impl<'a> ExecutionContext<'a> {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use {super::*, assert_matches::assert_matches};
+
+  fn parent() -> Vec<(String, String)> {
+    vec![
+      ("HOME".into(), "/home/user".into()),
+      ("PATH".into(), "/usr/bin".into()),
+    ]
+  }
+
+  fn environment(context: ExecutionContext) -> BTreeMap<String, String> {
+    context.environment_from(&parent()).into_iter().collect()
+  }
+
+  fn map<const N: usize>(pairs: [(&str, &str); N]) -> BTreeMap<String, String> {
+    pairs
+      .into_iter()
+      .map(|(key, value)| (key.into(), value.into()))
+      .collect()
+  }
+
+  #[test]
+  fn environment_without_inherit_env() {
+    assert_eq!(environment(ExecutionContext::default()), map([]));
+
+    assert_eq!(
+      environment(
+        ExecutionContext::default()
+          .inherit_variables(["HOME", "MISSING"])
+          .set_variables([("A", "1")])
+      ),
+      map([("A", "1"), ("HOME", "/home/user")])
+    );
+  }
+
+  #[test]
+  fn environment_with_limits() {
+    let context = || {
+      ExecutionContext::default()
+        .limits(Limits {
+          stack_limit_kb: None,
+          time_limit_ms: Some(1500.5),
+          ..Limits::default()
+        })
+        .export_limits_to_env(true)
+    };
+
+    assert_eq!(
+      environment(context()),
+      map([
+        ("ISOLATE_MEMORY_LIMIT_KB", "256000"),
+        ("ISOLATE_PROCESS_LIMIT", "1"),
+        ("ISOLATE_TIME_LIMIT_MS", "1500.5"),
+        ("ISOLATE_WALL_TIME_LIMIT_MS", "5000"),
+      ])
+    );
+
+    assert_eq!(
+      environment(
+        context()
+          .set_variables([("ISOLATE_PROCESS_LIMIT", "2")])
+          .variable(Variable::new("ISOLATE_TIME_LIMIT_MS", Action::Clear))
+      ),
+      map([
+        ("ISOLATE_MEMORY_LIMIT_KB", "256000"),
+        ("ISOLATE_PROCESS_LIMIT", "2"),
+        ("ISOLATE_WALL_TIME_LIMIT_MS", "5000"),
+      ])
+    );
+
+    assert_eq!(environment(ExecutionContext::default()), map([]));
+  }
+
+  #[test]
+  fn environment_from_file() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+
+    fs::write(
+      file.path(),
+      "# Rules for the judge\n\nHOME\n  A=1 \nPATH=/bin\n-PATH\n",
+    )
+    .unwrap();
+
+    let path = Utf8Path::from_path(file.path()).unwrap();
+
+    assert_eq!(
+      environment(
+        ExecutionContext::default()
+          .set_variables([("B", "2")])
+          .variables_from_file(path)
+          .unwrap()
+      ),
+      map([("A", "1"), ("B", "2"), ("HOME", "/home/user")])
+    );
+
+    fs::write(file.path(), "A=1\n\n=2\n").unwrap();
+
+    assert_matches!(
+      ExecutionContext::default().variables_from_file(path),
+      Err(Error::Config(message))
+        if message == format!("malformed variable `=2` on line 3 of `{}`", path)
+    );
+  }
+
+  #[test]
+  fn environment_clear_after_inherit_env() {
+    assert_eq!(
+      environment(
+        ExecutionContext::default()
+          .inherit_env(true)
+          .variable(Variable::new("HOME", Action::Clear))
+      ),
+      map([("PATH", "/usr/bin")])
+    );
+  }
+
+  #[test]
+  fn environment_set_after_inherit_env() {
+    assert_eq!(
+      environment(
+        ExecutionContext::default()
+          .inherit_env(true)
+          .set_variables([("HOME", "/box")])
+      ),
+      map([("HOME", "/box"), ("PATH", "/usr/bin")])
+    );
+  }
+
+  #[test]
+  fn environment_inherit_of_missing_key_is_a_no_op() {
+    assert_eq!(
+      environment(
+        ExecutionContext::default()
+          .set_variables([("MISSING", "value")])
+          .inherit_variables(["MISSING"])
+      ),
+      map([("MISSING", "value")])
+    );
+  }
+
+  #[test]
+  fn environment_later_rules_win() {
+    assert_eq!(
+      environment(
+        ExecutionContext::default()
+          .set_variables([("HOME", "/box")])
+          .inherit_variables(["HOME"])
+          .variable(Variable::new("PATH", Action::Clear))
+          .set_variables([("PATH", "/bin")])
+      ),
+      map([("HOME", "/home/user"), ("PATH", "/bin")])
+    );
+
+    assert_eq!(
+      environment(
+        ExecutionContext::default()
+          .set_variables([("A", "1")])
+          .variable(Variable::new("A", Action::Clear))
+      ),
+      map([])
+    );
+  }
+
+  #[test]
+  fn limits() {
+    let limits = Limits::default().memory_limit_kb(1024).time_limit_ms(250.0);
+
+    assert_eq!(
+      ExecutionContext::default()
+        .memory_limit_kb(1024)
+        .time_limit_ms(250.0)
+        .limits,
+      limits
+    );
+
+    assert_eq!(
+      ExecutionContext::default().limits(limits.clone()).limits,
+      limits
+    );
+  }
+
+  #[test]
+  fn owned() {
+    let arguments = (1..=2).map(|i| i.to_string()).collect::<Vec<_>>();
+
+    let context = ExecutionContext::owned("/bin/echo".into(), arguments);
+
+    assert_eq!(context.program, "/bin/echo");
+
+    assert_eq!(
+      context.arguments,
+      Some(vec![Cow::Borrowed("1"), Cow::Borrowed("2")])
+    );
+
+    assert_eq!(
+      ExecutionContext::new("/bin/echo".into(), Some(vec!["1", "2"])).arguments,
+      context.arguments
+    );
+  }
+
+  #[test]
+  fn validate() {
+    let context = || ExecutionContext::new("/bin/true".into(), None);
+
+    assert!(context().validate().is_ok());
+
+    for (context, expected) in [
+      (ExecutionContext::default(), "`program` must be set"),
+      (
+        context().stdout_append(true),
+        "`stdout_append` requires `stdout` to be set",
+      ),
+      (
+        context().stderr(Some("err".into())).stderr_to_stdout(true),
+        "`stderr` and `stderr_to_stdout` are mutually exclusive",
+      ),
+      (
+        context().prelude(Vec::<String>::new()),
+        "`prelude` must not be empty",
+      ),
+      (
+        context().hostname(Some(String::new())),
+        "`hostname` must be between 1 and 64 bytes long and not contain a nul byte",
+      ),
+      (
+        context().hostname(Some("x".repeat(65))),
+        "`hostname` must be between 1 and 64 bytes long and not contain a nul byte",
+      ),
+      (
+        context().time_limit_ms(f64::NAN),
+        "`time_limit_ms` must be a non-negative number",
+      ),
+    ] {
+      assert_matches!(
+        context.validate(),
+        Err(Error::Config(message)) if message == expected
+      );
+    }
+
+    assert_matches!(
+      context()
+        .mount(Mount::temporary("../escape").unwrap())
+        .validate(),
+      Err(Error::Mount(message)) if message == "inside path `../escape` must not contain `..`"
+    );
+  }
+
+  #[test]
+  fn validate_poll_interval() {
+    for interval in [0.0, -1.0, f64::NAN] {
+      assert_matches!(
+        ExecutionContext::new("/bin/true".into(), None)
+          .poll_interval_ms(interval)
+          .validate(),
+        Err(Error::Config(message)) if message.contains("`poll_interval_ms`")
+      );
+    }
+  }
+
+  #[test]
+  fn persona() {
+    let current = Persona::READ_IMPLIES_EXEC;
+
+    assert_eq!(ExecutionContext::default().persona(current), current);
+
+    assert_eq!(
+      ExecutionContext::default()
+        .disable_aslr(true)
+        .persona(current),
+      Persona::READ_IMPLIES_EXEC | Persona::ADDR_NO_RANDOMIZE
+    );
+  }
+
+  #[test]
+  fn set_variables() {
+    let context = ExecutionContext::default()
+      .variable(Variable::new("HOME", Action::Inherit))
+      .set_variables(BTreeMap::from([("A", "1"), ("B", "2")]));
+
+    assert_eq!(
+      context.variables,
+      vec![
+        Variable::new("HOME", Action::Inherit),
+        Variable::with_set_value("A", "1"),
+        Variable::with_set_value("B", "2"),
+      ]
+    );
+  }
+
+  #[test]
+  fn inherit_variables() {
+    let context = ExecutionContext::default()
+      .variable(Variable::with_set_value("A", "1"))
+      .inherit_variables(vec![String::from("HOME"), String::from("PATH")]);
+
+    assert_eq!(
+      context.variables,
+      vec![
+        Variable::with_set_value("A", "1"),
+        Variable::new("HOME", Action::Inherit),
+        Variable::new("PATH", Action::Inherit),
+      ]
+    );
+  }
+
+  #[test]
+  fn default_mounts() {
+    let host = None::<&Utf8Path>;
+
+    assert_eq!(
+      ExecutionContext::default().mounts,
+      [
+        Mount::read_write("box", Some("./box")).unwrap(),
+        Mount::read_only("bin", host).unwrap(),
+        Mount::device("dev", host).unwrap(),
+        Mount::read_only("lib", host).unwrap(),
+        Mount::optional("lib64", host).unwrap(),
+        Mount::filesystem("proc", "proc").unwrap(),
+        Mount::temporary("tmp").unwrap(),
+        Mount::read_only("usr", host).unwrap(),
+      ]
+    );
+
+    for mount in ExecutionContext::default().mounts {
+      assert!(mount.validate().is_ok(), "{mount:?}");
+    }
+  }
+
+  #[test]
+  fn with_dns() {
+    let defaults = ExecutionContext::default().mounts;
+
+    let context = ExecutionContext::default().with_dns();
+
+    assert_eq!(context.mounts[..defaults.len()], defaults);
+
+    assert_eq!(
+      context.mounts[defaults.len()..],
+      [
+        Mount::optional("etc/resolv.conf", Some("/etc/resolv.conf")).unwrap(),
+        Mount::optional("etc/hosts", Some("/etc/hosts")).unwrap(),
+      ]
+    );
+
+    assert_eq!(context.clone().with_dns().mounts, context.mounts);
+  }
+
+  #[test]
+  fn normalized_mounts() {
+    let mount = |inside_path: &str| Mount::read_only(inside_path, Some("/srv")).unwrap();
+
+    let context = ExecutionContext::default().mounts(vec![
+      mount("a/b/c"),
+      mount("/a"),
+      mount("x"),
+      mount("a/b"),
+      Mount::temporary("a/").unwrap(),
+    ]);
+
+    assert_eq!(
+      context.normalized_mounts().unwrap(),
+      [
+        &Mount::temporary("a/").unwrap(),
+        &mount("x"),
+        &mount("a/b"),
+        &mount("a/b/c"),
+      ]
+    );
+
+    assert_matches!(
+      context.mount(mount("a/../../escape")).normalized_mounts(),
+      Err(Error::Mount(_))
+    );
+  }
+
+  #[test]
+  fn synthetic_passwd_mounts() {
+    let context = ExecutionContext::default()
+      .mount(Mount::read_only("etc", None::<&str>).unwrap())
+      .with_synthetic_passwd();
+
+    let mounts = context.effective_mounts();
+
+    assert_eq!(
+      mounts[mounts.len() - 2..],
+      [
+        Mount::read_only("etc/passwd", Some("./etc/passwd")).unwrap(),
+        Mount::read_only("etc/group", Some("./etc/group")).unwrap(),
+      ]
+    );
+
+    assert_eq!(
+      ExecutionContext::normalize(&mounts).unwrap().last(),
+      Some(&&Mount::read_only("etc/group", Some("./etc/group")).unwrap())
+    );
+
+    assert_eq!(
+      ExecutionContext::default().effective_mounts(),
+      ExecutionContext::default().mounts
+    );
+  }
+
+  #[test]
+  fn program_paths() {
+    let context = |program: &str| {
+      ExecutionContext::new(program.into(), None)
+        .variables(Vec::new())
+        .search_path(true)
+    };
+
+    assert_eq!(
+      context("python3")
+        .set_variables([("PATH", "/usr/local/bin:/usr/bin/::/bin")])
+        .program_paths(),
+      [
+        "/usr/local/bin/python3",
+        "/usr/bin/python3",
+        "python3",
+        "/bin/python3"
+      ]
+    );
+
+    assert_eq!(
+      context("python3").program_paths(),
+      ["/bin/python3", "/usr/bin/python3"]
+    );
+
+    assert_eq!(context("./python3").program_paths(), ["./python3"]);
+
+    assert_eq!(
+      context("python3").search_path(false).program_paths(),
+      ["python3"]
+    );
+  }
+
+  #[test]
+  fn modify_mount() {
+    let defaults = ExecutionContext::default().mounts;
+
+    let context = ExecutionContext::default()
+      .modify_mount("/box", |options| options.read_write = false)
+      .unwrap();
+
+    assert_eq!(context.mounts.len(), defaults.len());
+
+    assert_eq!(
+      context.mounts[0],
+      Mount::read_only("box", Some("./box")).unwrap()
+    );
+
+    assert_eq!(context.mounts[1..], defaults[1..]);
+
+    assert_matches!(
+      ExecutionContext::default().modify_mount("srv", |options| options.no_exec = true),
+      Err(Error::Mount(message)) if message == "no mount at `srv` to modify"
+    );
+  }
+
+  #[test]
+  fn add_mounts() {
+    let defaults = ExecutionContext::default().mounts;
+
+    let context = ExecutionContext::default().add_mounts(vec![
+      Mount::read_write("data", Some("/data")).unwrap(),
+      Mount::temporary("/usr").unwrap(),
+      Mount::read_only("data", Some("/srv")).unwrap(),
+    ]);
+
+    assert_eq!(context.mounts.len(), defaults.len() + 1);
+
+    let usr = defaults
+      .iter()
+      .position(|mount| mount.relative_inside_path() == "usr")
+      .unwrap();
+
+    assert_eq!(context.mounts[usr], Mount::temporary("/usr").unwrap());
+
+    assert_eq!(
+      context.mounts.last(),
+      Some(&Mount::read_only("data", Some("/srv")).unwrap())
+    );
+  }
+
+  #[test]
+  fn strict_readonly_mounts() {
+    let context = ExecutionContext::default()
+      .mount(Mount::read_write("data", Some("/data")).unwrap())
+      .strict_readonly(true);
+
+    let mounts = context.effective_mounts();
+
+    assert!(!mounts.iter().any(|mount| mount.is_temporary()));
+
+    assert!(mounts.contains(&Mount::read_write("box", Some("./box")).unwrap()));
+
+    assert!(mounts.contains(&Mount::read_write("data", Some("/data")).unwrap()));
+
+    assert_eq!(mounts.len(), context.mounts.len() - 1);
+  }
+
+  #[test]
+  fn mounts_without_strict_readonly() {
+    let context = ExecutionContext::default();
+
+    assert_eq!(context.effective_mounts(), context.mounts);
+  }
+}
@@ -1,14 +1,27 @@
 use super::*;
 
-#[derive(Debug, Default)]
+/// Fraction of the memory limit a crashed program must have used for
+/// `ExecutionResult::exceeded_memory_limit` to be set.
+const MEMORY_LIMIT_THRESHOLD: f64 = 0.9;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 pub enum Status {
+  /// Program exited successfully.
+  Ok,
   /// Program exited with non-zero exit code.
   #[default]
   RuntimeError,
   /// Program terminated by signal.
   SignalError,
-  /// Program exceeded time limit.
+  /// Program exceeded the CPU time limit.
   Timeout,
+  /// Program exceeded the wall time limit, e.g. because it was sleeping or
+  /// deadlocked.
+  WallTimeout,
+  /// Program most likely crashed because it ran out of memory, see
+  /// `ExecutionResult::exceeded_memory_limit`.
+  MemoryLimit,
   /// Internal sandbox error.
   InternalError,
 }
@@ -19,53 +32,158 @@ impl Display for Status {
       f,
       "{}",
       match self {
+        Status::Ok => "OK",
         Status::RuntimeError => "RE",
         Status::SignalError => "SG",
         Status::Timeout => "TO",
+        Status::WallTimeout => "WT",
+        Status::MemoryLimit => "ML",
         Status::InternalError => "XX",
       }
     )
   }
 }
 
+impl Status {
+  /// Classify how the program ended, returning its status, exit code,
+  /// terminating signal and whether it dumped core.
+  ///
+  /// A program the watchdog killed because it `timed_out` is classified as
+  /// `Status::Timeout` however it ended (the caller tells CPU from wall
+  /// clock timeouts apart). Wait statuses of programs that have not ended,
+  /// e.g. stopped ones, are `Status::InternalError`.
+  pub(crate) fn classify(
+    status: WaitStatus,
+    timed_out: bool,
+  ) -> (Self, Option<i32>, Option<i32>, bool) {
+    let (classified, exit_code, signal, core_dumped) = match status {
+      WaitStatus::Exited(_, 0) => (Self::Ok, Some(0), None, false),
+      WaitStatus::Exited(_, code) => (Self::RuntimeError, Some(code), None, false),
+      WaitStatus::Signaled(_, signal, core_dumped) => {
+        (Self::SignalError, None, Some(signal as i32), core_dumped)
+      }
+      _ => return (Self::InternalError, None, None, false),
+    };
+
+    if timed_out {
+      (Self::Timeout, exit_code, signal, core_dumped)
+    } else {
+      (classified, exit_code, signal, core_dumped)
+    }
+  }
+
+  /// How bad the status is as an overall verdict, from `Ok`, the least
+  /// severe, through `RuntimeError`, `SignalError`, and the limits,
+  /// `Timeout`, `WallTimeout` and `MemoryLimit`, which are equally severe, up
+  /// to `InternalError`, see `ExecutionResult::worst_of`.
+  pub fn severity(self) -> u8 {
+    match self {
+      Self::Ok => 0,
+      Self::RuntimeError => 1,
+      Self::SignalError => 2,
+      Self::Timeout | Self::WallTimeout | Self::MemoryLimit => 3,
+      Self::InternalError => 4,
+    }
+  }
+}
+
 impl From<&str> for Status {
   fn from(s: &str) -> Self {
     match s {
+      "OK" => Status::Ok,
       "RE" => Status::RuntimeError,
       "SG" => Status::SignalError,
       "TO" => Status::Timeout,
+      "WT" => Status::WallTimeout,
+      "ML" => Status::MemoryLimit,
       "XX" => Status::InternalError,
       _ => Status::RuntimeError,
     }
   }
 }
 
-#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug)]
 pub struct ExecutionResult {
-  /// Total memory usage of the control group in kilobytes.
+  /// How much the program wrote to `/box`, in kilobytes.
   ///
-  /// Includes cached data from previous runs in the same sandbox.
-  pub cgroup_memory_kb: u32,
+  /// This is the total size of the regular files in the box after the run,
+  /// less the size the files had before it, so files put there before the
+  /// run (e.g. by a compile step) only count by how much they grew. Meant for
+  /// disk accounting without quota support, the size is apparent rather
+  /// than allocated, and space freed by deleting or truncating files is not
+  /// subtracted.
+  pub box_disk_usage_kb: u32,
 
-  /// Number of involuntary context switches (forced by kernel).
-  pub context_switches_forced: u32,
+  /// Whether the program dumped core when it was terminated by a signal.
+  ///
+  /// The core file is written to the working directory of the program, so
+  /// it counts towards the disk usage of the sandbox. Only possible when
+  /// `ExecutionContext::core_size_limit_kb` is raised above zero.
+  pub core_dumped: bool,
 
-  /// Number of voluntary context switches (process yielded CPU).
-  pub context_switches_voluntary: u32,
+  /// CPU time used after the program signalled the end of its warmup, in
+  /// milliseconds, see `ExecutionContext::measure_after_warmup`.
+  ///
+  /// `None` if the program did not signal it.
+  pub cpu_time_after_warmup_ms: Option<f64>,
 
-  /// CPU time used by the process in seconds.
-  pub cpu_time_ms: f64,
+  /// Process exit code, or `None` if the process did not exit normally
+  /// (e.g. it was terminated by a signal or never started).
+  pub exit_code: Option<i32>,
 
-  /// Process exit code (if terminated normally).
-  pub exit_code: i32,
+  /// Wall clock time at which the program was reaped.
+  ///
+  /// Meant for correlating the run with external logs, `wall_time_ms` stays
+  /// authoritative for its duration. Serialized as an RFC 3339 timestamp.
+  #[cfg_attr(feature = "serde", serde(serialize_with = "rfc3339::serialize"))]
+  pub finished_at: SystemTime,
 
-  /// Whether the program was terminated by the OOM killer.
+  /// Whether the program most likely failed because it ran out of memory.
   ///
-  /// Only reported on Linux 4.13+.
+  /// This is a heuristic: hitting `memory_limit_kb` or `data_limit_kb` makes
+  /// allocations fail rather than killing the program, so there is no
+  /// definite signal to go by. The flag is set when the program crashed
+  /// (exited with a non-zero status, or was terminated by `SIGSEGV`, `SIGBUS`
+  /// or `SIGABRT`) after using at least 90% of the smaller of the two limits.
+  ///
+  /// In control group mode, `CgroupConfig::memory_limit` takes the place of
+  /// `memory_limit_kb`, which is not enforced then, and the memory used by
+  /// the whole control group counts as well. The counters in the group's
+  /// `memory.events` are definite signals: the flag is also set when the OOM
+  /// killer killed one of its processes (see `killed_by_oom`), or when the
+  /// program crashed after the group reached its memory limit, according to
+  /// the `max` counter.
+  ///
+  /// As a consequence, a program that handles a failed allocation gracefully
+  /// and exits with status 0 is never flagged, and a program that crashes for
+  /// an unrelated reason while close to the limit is. The limits cap virtual
+  /// memory while `usage.peak_memory_kb` measures resident memory, so a
+  /// program that reserves much more than it touches is missed as well.
+  ///
+  /// When this is set, `status` is `Status::MemoryLimit` instead of
+  /// `Status::RuntimeError` or `Status::SignalError`.
+  pub exceeded_memory_limit: bool,
+
+  /// Why the sandbox killed the program, if it did.
+  pub kill_reason: Option<KillReason>,
+
+  /// Whether the OOM killer killed a process of the program's control group,
+  /// according to the `oom_kill` counter of its `memory.events`.
+  ///
+  /// Only reported in control group mode on Linux 4.13+.
   pub killed_by_oom: bool,
 
-  /// Peak memory usage (resident set size) in kilobytes.
-  pub peak_memory_kb: u32,
+  /// Time from the start of `Sandbox::execute` until the program was
+  /// executed, in milliseconds, i.e. the overhead of setting up the sandbox,
+  /// including the prelude. If the setup failed, this is the time until it
+  /// did.
+  ///
+  /// `setup_time_ms`, `wall_time_ms` and `teardown_time_ms` measure one
+  /// phase of `Sandbox::execute` each, one right after the other, so they
+  /// add up to the time it took, apart from reporting the run and writing
+  /// the audit log.
+  pub setup_time_ms: f64,
 
   /// Program's standard error.
   pub stderr: String,
@@ -73,40 +191,598 @@ pub struct ExecutionResult {
   /// Program's standard output.
   pub stdout: String,
 
+  /// Wall clock time at which the program was started, see `finished_at`.
+  #[cfg_attr(feature = "serde", serde(serialize_with = "rfc3339::serialize"))]
+  pub started_at: SystemTime,
+
   /// Execution status code.
   pub status: Status,
 
   /// Human-readable status description (e.g., "Time limit exceeded").
   pub status_message: String,
 
+  /// The system calls made by the program, oldest first.
+  ///
+  /// Only recorded with `ExecutionContext::trace_syscalls`, which requires
+  /// the `trace` feature, and empty otherwise.
+  pub syscalls: Vec<TracedSyscall>,
+
+  /// Time from the program being reaped until `Sandbox::execute` returned,
+  /// in milliseconds, e.g. for removing its control group, removing special
+  /// files from the box and measuring how much was written to it, see
+  /// `setup_time_ms`.
+  pub teardown_time_ms: f64,
+
   /// Whether the sandbox terminated the process (e.g., due to timeout).
+  ///
+  /// Always equal to `kill_reason.is_some()`.
   pub terminated_by_sandbox: bool,
 
   /// Signal that terminated the process (if killed by signal).
   pub termination_signal: i32,
 
-  /// Total wall clock time in seconds.
+  /// Resources used by the program.
+  pub usage: ResourceUsage,
+
+  /// Wall-clock time after the program signalled the end of its warmup, in
+  /// milliseconds, see `ExecutionContext::measure_after_warmup`.
+  ///
+  /// `None` if the program did not signal it.
+  pub wall_time_after_warmup_ms: Option<f64>,
+
+  /// Wall clock time from the program being executed until it was reaped,
+  /// in milliseconds, which does not include `setup_time_ms` and
+  /// `teardown_time_ms`.
+  ///
+  /// Like every `_ms` field in this crate, this holds milliseconds, see also
+  /// `ExecutionResult::wall_time_secs`.
   pub wall_time_ms: f64,
 }
 
+impl Default for ExecutionResult {
+  fn default() -> Self {
+    Self {
+      box_disk_usage_kb: 0,
+      core_dumped: false,
+      cpu_time_after_warmup_ms: None,
+      exceeded_memory_limit: false,
+      exit_code: None,
+      finished_at: UNIX_EPOCH,
+      kill_reason: None,
+      killed_by_oom: false,
+      setup_time_ms: 0.0,
+      started_at: UNIX_EPOCH,
+      status: Status::default(),
+      status_message: String::new(),
+      stderr: String::new(),
+      stdout: String::new(),
+      syscalls: Vec::new(),
+      teardown_time_ms: 0.0,
+      terminated_by_sandbox: false,
+      termination_signal: 0,
+      usage: ResourceUsage::default(),
+      wall_time_after_warmup_ms: None,
+      wall_time_ms: 0.0,
+    }
+  }
+}
+
+impl ExecutionResult {
+  #[deprecated(note = "use `usage.cgroup_memory_kb` instead")]
+  pub fn cgroup_memory_kb(&self) -> u32 {
+    self.usage.cgroup_memory_kb
+  }
+
+  #[deprecated(note = "use `usage.context_switches_forced` instead")]
+  pub fn context_switches_forced(&self) -> u32 {
+    self.usage.context_switches_forced
+  }
+
+  #[deprecated(note = "use `usage.context_switches_voluntary` instead")]
+  pub fn context_switches_voluntary(&self) -> u32 {
+    self.usage.context_switches_voluntary
+  }
+
+  #[deprecated(note = "use `usage.cpu_time_ms` instead")]
+  pub fn cpu_time_ms(&self) -> f64 {
+    self.usage.cpu_time_ms
+  }
+
+  #[deprecated(note = "use `usage.peak_memory_kb` instead")]
+  pub fn peak_memory_kb(&self) -> u32 {
+    self.usage.peak_memory_kb
+  }
+
+  /// CPU time used by the process in milliseconds.
+  pub fn cpu_time_millis(&self) -> f64 {
+    self.usage.cpu_time_ms
+  }
+
+  /// CPU time used by the process in seconds.
+  pub fn cpu_time_secs(&self) -> f64 {
+    self.usage.cpu_time_ms / 1000.0
+  }
+
+  /// Set `exceeded_memory_limit` for a run under the limits of `context` and,
+  /// in control group mode, of `cgroup`, turning the status of a program
+  /// that crashed because of it into `Status::MemoryLimit`.
+  ///
+  /// `limit_reached` tells whether the control group reached its memory
+  /// limit during the run.
+  pub(crate) fn detect_memory_limit(
+    &mut self,
+    context: &ExecutionContext,
+    cgroup: Option<&CgroupConfig>,
+    limit_reached: bool,
+  ) {
+    let crashed = match self.status {
+      Status::RuntimeError => true,
+      Status::SignalError => {
+        [libc::SIGABRT, libc::SIGBUS, libc::SIGSEGV].contains(&self.termination_signal)
+      }
+      _ => false,
+    };
+
+    // The control group enforces its own memory limit instead of
+    // `memory_limit_kb`, see `Limits::resource_limits`.
+    let (memory_limit, peak) = match cgroup {
+      Some(cgroup) => (
+        cgroup.memory_limit,
+        self.usage.peak_memory_kb.max(self.usage.cgroup_memory_kb),
+      ),
+      None => (context.limits.memory_limit_kb, self.usage.peak_memory_kb),
+    };
+
+    let limit = memory_limit
+      .into_iter()
+      .chain(context.limits.data_limit_kb)
+      .min();
+
+    self.exceeded_memory_limit = self.killed_by_oom
+      || (crashed
+        && (limit_reached
+          || limit
+            .is_some_and(|limit| f64::from(peak) >= f64::from(limit) * MEMORY_LIMIT_THRESHOLD)));
+
+    if self.exceeded_memory_limit
+      && matches!(self.status, Status::RuntimeError | Status::SignalError)
+    {
+      self.status = Status::MemoryLimit;
+    }
+  }
+
+  /// A one-line description of the outcome, in the format of isolate's
+  /// status messages, e.g. `OK (0.012 sec real, 0.015 sec wall)` or
+  /// `Exited with error status 1`.
+  pub(crate) fn summary(&self) -> String {
+    match self.status {
+      Status::Ok => format!(
+        "OK ({:.3} sec real, {:.3} sec wall)",
+        self.cpu_time_secs(),
+        self.wall_time_secs()
+      ),
+      _ => self.status_message.clone(),
+    }
+  }
+
+  /// Whether the program exited normally with exit code 0.
+  pub fn succeeded(&self) -> bool {
+    matches!(self.status, Status::Ok) && self.exit_code == Some(0)
+  }
+
+  /// Wall clock time of the program in milliseconds, see `wall_time_ms`.
+  pub fn wall_time_millis(&self) -> f64 {
+    self.wall_time_ms
+  }
+
+  /// Wall clock time of the program in seconds, see `wall_time_ms`.
+  pub fn wall_time_secs(&self) -> f64 {
+    self.wall_time_ms / 1000.0
+  }
+
+  /// Combine the results of a batch of runs, e.g. of a program on every test
+  /// case, into an overall one.
+  ///
+  /// The outcome (status, message, exit code, signal and output) is the one
+  /// of the first run with the highest `Status::severity`, while resource
+  /// usages, disk usage and times are the highest of any run, the memory
+  /// flags are set if they are set for any run, and the run starts with the
+  /// first and finishes with the last. Traced system calls are left out.
+  /// Without any results, the status is `Status::Ok`.
+  pub fn worst_of(results: &[ExecutionResult]) -> ExecutionResult {
+    let Some(worst) = results.iter().reduce(|worst, result| {
+      if result.status.severity() > worst.status.severity() {
+        result
+      } else {
+        worst
+      }
+    }) else {
+      return ExecutionResult {
+        status: Status::Ok,
+        ..Default::default()
+      };
+    };
+
+    let max = |value: fn(&ExecutionResult) -> u32| results.iter().map(value).max().unwrap_or(0);
+
+    let max_ms = |value: fn(&ExecutionResult) -> f64| results.iter().map(value).fold(0.0, f64::max);
+
+    let max_after_warmup = |value: fn(&ExecutionResult) -> Option<f64>| {
+      results.iter().filter_map(value).reduce(f64::max)
+    };
+
+    ExecutionResult {
+      box_disk_usage_kb: max(|result| result.box_disk_usage_kb),
+      core_dumped: worst.core_dumped,
+      cpu_time_after_warmup_ms: max_after_warmup(|result| result.cpu_time_after_warmup_ms),
+      exceeded_memory_limit: results.iter().any(|result| result.exceeded_memory_limit),
+      exit_code: worst.exit_code,
+      finished_at: results
+        .iter()
+        .map(|result| result.finished_at)
+        .max()
+        .unwrap_or(UNIX_EPOCH),
+      kill_reason: worst.kill_reason,
+      killed_by_oom: results.iter().any(|result| result.killed_by_oom),
+      setup_time_ms: max_ms(|result| result.setup_time_ms),
+      started_at: results
+        .iter()
+        .map(|result| result.started_at)
+        .min()
+        .unwrap_or(UNIX_EPOCH),
+      status: worst.status,
+      status_message: worst.status_message.clone(),
+      stderr: worst.stderr.clone(),
+      stdout: worst.stdout.clone(),
+      syscalls: Vec::new(),
+      teardown_time_ms: max_ms(|result| result.teardown_time_ms),
+      terminated_by_sandbox: worst.terminated_by_sandbox,
+      termination_signal: worst.termination_signal,
+      usage: ResourceUsage {
+        cgroup_memory_kb: max(|result| result.usage.cgroup_memory_kb),
+        context_switches_forced: max(|result| result.usage.context_switches_forced),
+        context_switches_voluntary: max(|result| result.usage.context_switches_voluntary),
+        cpu_time_ms: max_ms(|result| result.usage.cpu_time_ms),
+        peak_memory_kb: max(|result| result.usage.peak_memory_kb),
+        system_time_ms: max_ms(|result| result.usage.system_time_ms),
+        user_time_ms: max_ms(|result| result.usage.user_time_ms),
+      },
+      wall_time_after_warmup_ms: max_after_warmup(|result| result.wall_time_after_warmup_ms),
+      wall_time_ms: max_ms(|result| result.wall_time_ms),
+    }
+  }
+
+  /// Write the result to a meta file at `path`, replacing it if it exists.
+  pub fn write_meta(&self, path: impl AsRef<Utf8Path>) -> Result {
+    self.write_to(&mut File::create(path.as_ref())?, ResultFormat::Meta)
+  }
+
+  /// Write the result to `writer` in the given format.
+  pub fn write_to(&self, writer: &mut dyn Write, format: ResultFormat) -> Result {
+    Ok(format.write(self, writer)?)
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
 
   #[test]
   fn status_display() {
+    assert_eq!(Status::Ok.to_string(), "OK");
     assert_eq!(Status::RuntimeError.to_string(), "RE");
     assert_eq!(Status::SignalError.to_string(), "SG");
     assert_eq!(Status::Timeout.to_string(), "TO");
+    assert_eq!(Status::WallTimeout.to_string(), "WT");
+    assert_eq!(Status::MemoryLimit.to_string(), "ML");
     assert_eq!(Status::InternalError.to_string(), "XX");
   }
 
   #[test]
   fn status_from_str() {
+    assert!(matches!(Status::from("OK"), Status::Ok));
     assert!(matches!(Status::from("RE"), Status::RuntimeError));
     assert!(matches!(Status::from("SG"), Status::SignalError));
     assert!(matches!(Status::from("TO"), Status::Timeout));
+    assert!(matches!(Status::from("WT"), Status::WallTimeout));
+    assert!(matches!(Status::from("ML"), Status::MemoryLimit));
     assert!(matches!(Status::from("XX"), Status::InternalError));
     assert!(matches!(Status::from("invalid"), Status::RuntimeError));
   }
+
+  #[test]
+  fn classify() {
+    let pid = Pid::from_raw(1);
+
+    let classify = |status, timed_out| {
+      let (status, exit_code, signal, core_dumped) = Status::classify(status, timed_out);
+
+      (status.to_string(), exit_code, signal, core_dumped)
+    };
+
+    let cases = [
+      (
+        WaitStatus::Exited(pid, 0),
+        false,
+        ("OK", Some(0), None, false),
+      ),
+      (
+        WaitStatus::Exited(pid, 1),
+        false,
+        ("RE", Some(1), None, false),
+      ),
+      (
+        WaitStatus::Exited(pid, 255),
+        false,
+        ("RE", Some(255), None, false),
+      ),
+      (
+        WaitStatus::Signaled(pid, Signal::SIGSEGV, false),
+        false,
+        ("SG", None, Some(11), false),
+      ),
+      (
+        WaitStatus::Signaled(pid, Signal::SIGABRT, true),
+        false,
+        ("SG", None, Some(6), true),
+      ),
+      (
+        WaitStatus::Signaled(pid, Signal::SIGKILL, false),
+        true,
+        ("TO", None, Some(9), false),
+      ),
+      (
+        WaitStatus::Exited(pid, 0),
+        true,
+        ("TO", Some(0), None, false),
+      ),
+      (
+        WaitStatus::Stopped(pid, Signal::SIGSTOP),
+        false,
+        ("XX", None, None, false),
+      ),
+      (WaitStatus::Continued(pid), true, ("XX", None, None, false)),
+      (WaitStatus::StillAlive, false, ("XX", None, None, false)),
+    ];
+
+    for (status, timed_out, (expected, exit_code, signal, core_dumped)) in cases {
+      assert_eq!(
+        classify(status, timed_out),
+        (expected.to_string(), exit_code, signal, core_dumped),
+        "{status:?}"
+      );
+    }
+  }
+
+  #[test]
+  fn succeeded() {
+    let result = ExecutionResult {
+      exit_code: Some(0),
+      status: Status::Ok,
+      ..Default::default()
+    };
+
+    assert!(result.succeeded());
+
+    let result = ExecutionResult {
+      status: Status::SignalError,
+      termination_signal: 9,
+      ..Default::default()
+    };
+
+    assert_eq!(result.exit_code, None);
+
+    assert!(!result.succeeded());
+  }
+
+  #[test]
+  fn summary() {
+    let result = ExecutionResult {
+      exit_code: Some(0),
+      status: Status::Ok,
+      usage: ResourceUsage {
+        cpu_time_ms: 12.0,
+        ..Default::default()
+      },
+      wall_time_ms: 15.4,
+      ..Default::default()
+    };
+
+    assert_eq!(result.summary(), "OK (0.012 sec real, 0.015 sec wall)");
+
+    let result = ExecutionResult {
+      exit_code: Some(1),
+      status: Status::RuntimeError,
+      status_message: "Exited with error status 1".into(),
+      ..Default::default()
+    };
+
+    assert_eq!(result.summary(), "Exited with error status 1");
+  }
+
+  #[test]
+  fn detect_memory_limit() {
+    let context = ExecutionContext::default()
+      .memory_limit_kb(10_000)
+      .data_limit_kb(2000);
+
+    let crashed = |status, termination_signal, peak_memory_kb| {
+      let mut result = ExecutionResult {
+        status,
+        termination_signal,
+        usage: ResourceUsage {
+          peak_memory_kb,
+          ..Default::default()
+        },
+        ..Default::default()
+      };
+
+      result.detect_memory_limit(&context, None, false);
+
+      assert_eq!(
+        matches!(result.status, Status::MemoryLimit),
+        result.exceeded_memory_limit
+      );
+
+      result.exceeded_memory_limit
+    };
+
+    assert!(crashed(Status::SignalError, libc::SIGSEGV, 1900));
+    assert!(crashed(Status::RuntimeError, 0, 1800));
+    assert!(!crashed(Status::RuntimeError, 0, 1799));
+    assert!(!crashed(Status::SignalError, libc::SIGKILL, 1900));
+    assert!(!crashed(Status::Ok, 0, 2000));
+    assert!(!crashed(Status::Timeout, 0, 2000));
+
+    let mut result = ExecutionResult {
+      killed_by_oom: true,
+      status: Status::SignalError,
+      termination_signal: libc::SIGKILL,
+      ..Default::default()
+    };
+
+    result.detect_memory_limit(&ExecutionContext::default(), None, false);
+
+    assert!(result.exceeded_memory_limit);
+
+    assert!(matches!(result.status, Status::MemoryLimit));
+  }
+
+  #[test]
+  fn detect_memory_limit_in_cgroup() {
+    let context = ExecutionContext::default().memory_limit_kb(10_000);
+
+    let cgroup = CgroupConfig {
+      memory_limit: Some(100_000),
+      ..Default::default()
+    };
+
+    let crashed = |peak_memory_kb, cgroup_memory_kb, cgroup: Option<&CgroupConfig>| {
+      let mut result = ExecutionResult {
+        status: Status::SignalError,
+        termination_signal: libc::SIGSEGV,
+        usage: ResourceUsage {
+          cgroup_memory_kb,
+          peak_memory_kb,
+          ..Default::default()
+        },
+        ..Default::default()
+      };
+
+      result.detect_memory_limit(&context, cgroup, false);
+
+      result.exceeded_memory_limit
+    };
+
+    // Without a control group, `memory_limit_kb` is enforced.
+    assert!(crashed(9000, 0, None));
+
+    // With one, `memory_limit_kb` is ignored in favor of the limit of the
+    // control group, which covers all of its processes.
+    assert!(!crashed(9000, 0, Some(&cgroup)));
+    assert!(crashed(90_000, 0, Some(&cgroup)));
+    assert!(crashed(9000, 90_000, Some(&cgroup)));
+    assert!(!crashed(9000, 89_999, Some(&cgroup)));
+
+    assert!(!crashed(
+      90_000,
+      90_000,
+      Some(&CgroupConfig {
+        memory_limit: None,
+        ..Default::default()
+      })
+    ));
+
+    // Reaching the limit of the control group is a definite signal, but only
+    // for a program that crashed.
+    for (status, termination_signal, exceeded) in [
+      (Status::SignalError, libc::SIGSEGV, true),
+      (Status::RuntimeError, 0, true),
+      (Status::Ok, 0, false),
+    ] {
+      let mut result = ExecutionResult {
+        status,
+        termination_signal,
+        ..Default::default()
+      };
+
+      result.detect_memory_limit(&context, Some(&cgroup), true);
+
+      assert_eq!(result.exceeded_memory_limit, exceeded);
+    }
+  }
+
+  #[test]
+  fn severity() {
+    let statuses = [
+      Status::Ok,
+      Status::RuntimeError,
+      Status::SignalError,
+      Status::Timeout,
+      Status::InternalError,
+    ];
+
+    for pair in statuses.windows(2) {
+      assert!(pair[0].severity() < pair[1].severity(), "{pair:?}");
+    }
+
+    assert_eq!(Status::WallTimeout.severity(), Status::Timeout.severity());
+    assert_eq!(Status::MemoryLimit.severity(), Status::Timeout.severity());
+  }
+
+  #[test]
+  fn worst_of() {
+    let result = |status, cpu_time_ms, peak_memory_kb, started_at| ExecutionResult {
+      started_at: UNIX_EPOCH + Duration::from_secs(started_at),
+      finished_at: UNIX_EPOCH + Duration::from_secs(started_at + 1),
+      status,
+      status_message: format!("{status} at {started_at}"),
+      usage: ResourceUsage {
+        cpu_time_ms,
+        peak_memory_kb,
+        ..Default::default()
+      },
+      wall_time_ms: 1000.0,
+      ..Default::default()
+    };
+
+    let worst = ExecutionResult::worst_of(&[
+      result(Status::Ok, 10.0, 4096, 2),
+      result(Status::Timeout, 1500.0, 1024, 3),
+      result(Status::RuntimeError, 20.0, 8192, 4),
+      result(Status::MemoryLimit, 30.0, 2048, 5),
+    ]);
+
+    assert_eq!(worst.status, Status::Timeout);
+    assert_eq!(worst.status_message, "TO at 3");
+    assert_eq!(worst.usage.cpu_time_ms, 1500.0);
+    assert_eq!(worst.usage.peak_memory_kb, 8192);
+    assert_eq!(worst.wall_time_ms, 1000.0);
+    assert_eq!(worst.started_at, UNIX_EPOCH + Duration::from_secs(2));
+    assert_eq!(worst.finished_at, UNIX_EPOCH + Duration::from_secs(6));
+
+    assert_eq!(
+      ExecutionResult::worst_of(&[result(Status::Ok, 0.0, 0, 0)]).status,
+      Status::Ok
+    );
+
+    assert_eq!(ExecutionResult::worst_of(&[]).status, Status::Ok);
+  }
+
+  #[test]
+  fn time_accessors() {
+    let result = ExecutionResult {
+      usage: ResourceUsage {
+        cpu_time_ms: 1500.0,
+        ..Default::default()
+      },
+      wall_time_ms: 250.0,
+      ..Default::default()
+    };
+
+    assert_eq!(result.cpu_time_millis(), 1500.0);
+    assert_eq!(result.cpu_time_secs(), 1.5);
+    assert_eq!(result.wall_time_millis(), 250.0);
+    assert_eq!(result.wall_time_secs(), 0.25);
+  }
 }
@@ -0,0 +1,470 @@
+use {super::*, nix::sys::statfs::EXT4_SUPER_MAGIC, std::sync::Mutex, system::FileStatus};
+
+/// A directory in the simulated filesystem of an `InMemorySystem`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Directory {
+  pub(crate) gid: Gid,
+  pub(crate) mode: u32,
+  pub(crate) uid: Uid,
+}
+
+/// A `System` that simulates the filesystem in memory, for testing the
+/// sandbox without root and without touching the real filesystem.
+///
+/// The filesystem only holds directories, all of which start out owned by
+/// root. Every directory that is created, chowned or mounted over is recorded,
+/// so tests can check the resulting tree. Directories are on an `ext4`
+/// filesystem, unless they are below one of the `mounts`, which are `tmpfs`,
+/// or one of the `filesystems`. Lock files are backed by anonymous
+/// temporary files, so locking always succeeds, every process exits with
+/// status 0 right away, having used `usage`, and messages are recorded in
+/// `log` instead of being printed.
+///
+/// Regular files, such as the controller files of control groups and the
+/// files in `/proc`, live in `files`, independently of the directories.
+/// Reading a file that is not there fails with `NotFound`, and writing one
+/// replaces its contents, so tests can provide canned contents and check
+/// what was written. Like the controller files of a control group, the
+/// files in a directory do not keep it from being removed as empty.
+///
+/// The system calls that set up the root of the sandbox or change the
+/// limits of a process always succeed and are recorded in `calls`, e.g.
+/// `chdir /` or
+/// `mount - / - MsFlags(MS_REC | MS_PRIVATE) -`, with `-` for arguments that
+/// are `None`, so tests can check the exact sequence of mounts and their
+/// flags.
+#[derive(Debug)]
+pub(crate) struct InMemorySystem {
+  pub(crate) calls: Mutex<Vec<String>>,
+  pub(crate) capabilities: u64,
+  pub(crate) directories: Mutex<BTreeMap<Utf8PathBuf, Directory>>,
+  pub(crate) egid: Gid,
+  pub(crate) euid: Uid,
+  pub(crate) files: Mutex<BTreeMap<Utf8PathBuf, String>>,
+  pub(crate) filesystems: BTreeMap<Utf8PathBuf, FsType>,
+  pub(crate) gid: Gid,
+  pub(crate) log: Mutex<Vec<String>>,
+  pub(crate) mounts: Mutex<BTreeMap<Utf8PathBuf, u32>>,
+  pub(crate) uid: Uid,
+  pub(crate) uid_map: String,
+  pub(crate) umask: Mutex<Option<Mode>>,
+  pub(crate) usage: ResourceUsage,
+}
+
+impl Default for InMemorySystem {
+  fn default() -> Self {
+    Self {
+      calls: Mutex::new(Vec::new()),
+      capabilities: 1 << sandbox::CAP_SYS_ADMIN,
+      directories: Mutex::new(BTreeMap::from([(
+        Utf8PathBuf::from("/"),
+        Directory {
+          gid: Gid::from_raw(0),
+          mode: 0o755,
+          uid: Uid::from_raw(0),
+        },
+      )])),
+      egid: Gid::from_raw(0),
+      euid: Uid::from_raw(0),
+      files: Mutex::new(BTreeMap::new()),
+      filesystems: BTreeMap::new(),
+      gid: Gid::from_raw(0),
+      log: Mutex::new(Vec::new()),
+      mounts: Mutex::new(BTreeMap::new()),
+      uid: Uid::from_raw(0),
+      uid_map: "0 0 4294967295".into(),
+      umask: Mutex::new(None),
+      usage: ResourceUsage::default(),
+    }
+  }
+}
+
+impl InMemorySystem {
+  /// The directory at `path`, if it exists.
+  pub(crate) fn directory(&self, path: impl AsRef<Utf8Path>) -> Option<Directory> {
+    self.directories.lock().unwrap().get(path.as_ref()).cloned()
+  }
+
+  /// The contents of the file at `path`, if it exists.
+  pub(crate) fn file(&self, path: impl AsRef<Utf8Path>) -> Option<String> {
+    self.files.lock().unwrap().get(path.as_ref()).cloned()
+  }
+
+  fn call(&self, call: String) -> nix::Result<()> {
+    self.calls.lock().unwrap().push(call);
+    Ok(())
+  }
+
+  fn not_found(path: &Utf8Path) -> io::Error {
+    io::Error::new(
+      io::ErrorKind::NotFound,
+      format!("`{}` does not exist", path),
+    )
+  }
+}
+
+impl System for InMemorySystem {
+  fn append(&self, path: &Utf8Path, contents: &str) -> Result {
+    self
+      .calls
+      .lock()
+      .unwrap()
+      .push(format!("append {path} {}", contents.trim_end()));
+
+    Ok(())
+  }
+
+  fn chdir(&self, path: &Utf8Path) -> nix::Result<()> {
+    self.call(format!("chdir {path}"))
+  }
+
+  fn chown(&self, path: &Utf8Path, uid: Option<Uid>, gid: Option<Gid>) -> Result {
+    let mut directories = self.directories.lock().unwrap();
+
+    let directory = directories
+      .get_mut(path)
+      .ok_or_else(|| Self::not_found(path))?;
+
+    if let Some(uid) = uid {
+      directory.uid = uid;
+    }
+
+    if let Some(gid) = gid {
+      directory.gid = gid;
+    }
+
+    Ok(())
+  }
+
+  fn chroot(&self, path: &Utf8Path) -> nix::Result<()> {
+    self.call(format!("chroot {path}"))
+  }
+
+  /// Copy the directories and files below `source`, keeping `destination`
+  /// itself in place but giving it the owner and mode of `source`.
+  fn copy_tree(&self, source: &Utf8Path, destination: &Utf8Path) -> Result {
+    let rebase = |path: &Utf8Path| match path.strip_prefix(source).ok()? {
+      relative if relative.as_str().is_empty() => Some(destination.to_path_buf()),
+      relative => Some(destination.join(relative)),
+    };
+
+    let mut directories = self.directories.lock().unwrap();
+
+    let copies = directories
+      .iter()
+      .filter_map(|(path, directory)| Some((rebase(path)?, directory.clone())))
+      .collect::<Vec<_>>();
+
+    ensure!(
+      copies.iter().any(|(path, _)| path == destination),
+      Self::not_found(source).into()
+    );
+
+    directories.extend(copies);
+
+    let mut files = self.files.lock().unwrap();
+
+    let copies = files
+      .iter()
+      .filter_map(|(path, contents)| Some((rebase(path)?, contents.clone())))
+      .collect::<Vec<_>>();
+
+    files.extend(copies);
+
+    Ok(())
+  }
+
+  fn create_directory(&self, path: &Utf8Path) -> io::Result<()> {
+    let mut directories = self.directories.lock().unwrap();
+
+    if directories.contains_key(path) {
+      return Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("`{}` already exists", path),
+      ));
+    }
+
+    let parent = path.parent().unwrap_or(path);
+
+    if !directories.contains_key(parent) {
+      return Err(Self::not_found(parent));
+    }
+
+    directories.insert(
+      path.to_path_buf(),
+      Directory {
+        gid: Gid::from_raw(0),
+        mode: 0o755,
+        uid: Uid::from_raw(0),
+      },
+    );
+
+    Ok(())
+  }
+
+  fn create_directory_with_mode(&self, path: &Utf8Path, mode: u32) -> Result {
+    let mut directories = self.directories.lock().unwrap();
+
+    // Like `fs::create_dir_all`, missing ancestors are created with the
+    // default mode under a umask of 0022.
+    for ancestor in path.ancestors().skip(1) {
+      directories
+        .entry(ancestor.to_path_buf())
+        .or_insert(Directory {
+          gid: Gid::from_raw(0),
+          mode: 0o755,
+          uid: Uid::from_raw(0),
+        });
+    }
+
+    directories
+      .entry(path.to_path_buf())
+      .or_insert(Directory {
+        gid: Gid::from_raw(0),
+        mode,
+        uid: Uid::from_raw(0),
+      })
+      .mode = mode;
+
+    Ok(())
+  }
+
+  fn effective_capabilities(&self) -> Result<u64> {
+    Ok(self.capabilities)
+  }
+
+  fn filesystem_type(&self, path: &Utf8Path) -> Result<FsType> {
+    ensure!(
+      self.directories.lock().unwrap().contains_key(path),
+      Self::not_found(path).into()
+    );
+
+    let mounts = self.mounts.lock().unwrap();
+
+    Ok(
+      path
+        .ancestors()
+        .find_map(|ancestor| {
+          if mounts.contains_key(ancestor) {
+            Some(TMPFS_MAGIC)
+          } else {
+            self.filesystems.get(ancestor).copied()
+          }
+        })
+        .unwrap_or(EXT4_SUPER_MAGIC),
+    )
+  }
+
+  fn getegid(&self) -> Gid {
+    self.egid
+  }
+
+  fn geteuid(&self) -> Uid {
+    self.euid
+  }
+
+  fn getgid(&self) -> Gid {
+    self.gid
+  }
+
+  fn getuid(&self) -> Uid {
+    self.uid
+  }
+
+  fn kill(&self, pid: Pid, signal: Signal) -> Result {
+    self
+      .calls
+      .lock()
+      .unwrap()
+      .push(format!("kill {pid} {signal}"));
+
+    Ok(())
+  }
+
+  fn list_directory(&self, path: &Utf8Path) -> io::Result<Vec<Utf8PathBuf>> {
+    let directories = self.directories.lock().unwrap();
+
+    if !directories.contains_key(path) {
+      return Err(Self::not_found(path));
+    }
+
+    let mut entries = directories
+      .keys()
+      .chain(self.files.lock().unwrap().keys())
+      .filter(|entry| entry.parent() == Some(path))
+      .cloned()
+      .collect::<Vec<_>>();
+
+    entries.sort();
+
+    Ok(entries)
+  }
+
+  fn log(&self, message: &str) {
+    self.log.lock().unwrap().push(message.into());
+  }
+
+  fn mount(
+    &self,
+    source: Option<&str>,
+    target: &Utf8Path,
+    filesystem: Option<&str>,
+    flags: MsFlags,
+    data: Option<&str>,
+  ) -> nix::Result<()> {
+    self.call(format!(
+      "mount {} {target} {} {flags:?} {}",
+      source.unwrap_or("-"),
+      filesystem.unwrap_or("-"),
+      data.unwrap_or("-"),
+    ))
+  }
+
+  fn mount_tmpfs(&self, path: &Utf8Path, size_mb: u32) -> Result {
+    ensure!(
+      self.directories.lock().unwrap().contains_key(path),
+      Self::not_found(path).into()
+    );
+
+    self
+      .mounts
+      .lock()
+      .unwrap()
+      .insert(path.to_path_buf(), size_mb);
+
+    Ok(())
+  }
+
+  fn open_lock(&self, path: &Utf8Path) -> Result<File> {
+    let parent = path.parent().unwrap_or(path);
+
+    ensure!(
+      self.directories.lock().unwrap().contains_key(parent),
+      Self::not_found(parent).into()
+    );
+
+    Ok(tempfile::tempfile()?)
+  }
+
+  fn pivot_root(&self, new_root: &Utf8Path, put_old: &Utf8Path) -> nix::Result<()> {
+    self.call(format!("pivot_root {new_root} {put_old}"))
+  }
+
+  fn prlimit(&self, pid: Pid, resource: Resource, limit: rlim_t) -> Result {
+    self
+      .calls
+      .lock()
+      .unwrap()
+      .push(format!("prlimit {pid} {resource:?} {limit}"));
+
+    Ok(())
+  }
+
+  fn read_file(&self, path: &Utf8Path) -> io::Result<String> {
+    self.file(path).ok_or_else(|| Self::not_found(path))
+  }
+
+  fn recreate_directory_with_mode(&self, path: &Utf8Path, mode: u32) -> Result {
+    self
+      .directories
+      .lock()
+      .unwrap()
+      .retain(|directory, _| !directory.starts_with(path));
+
+    self
+      .files
+      .lock()
+      .unwrap()
+      .retain(|file, _| !file.starts_with(path));
+
+    self.create_directory_with_mode(path, mode)
+  }
+
+  fn remove_directory(&self, path: &Utf8Path) -> Result {
+    let mut directories = self.directories.lock().unwrap();
+
+    ensure!(directories.contains_key(path), Self::not_found(path).into());
+
+    directories.retain(|directory, _| !directory.starts_with(path));
+
+    self
+      .files
+      .lock()
+      .unwrap()
+      .retain(|file, _| !file.starts_with(path));
+
+    Ok(())
+  }
+
+  fn remove_empty_directory(&self, path: &Utf8Path) -> io::Result<()> {
+    let mut directories = self.directories.lock().unwrap();
+
+    if !directories.contains_key(path) {
+      return Err(Self::not_found(path));
+    }
+
+    if directories
+      .keys()
+      .any(|directory| directory != path && directory.starts_with(path))
+    {
+      return Err(io::Error::new(
+        io::ErrorKind::DirectoryNotEmpty,
+        format!("`{}` is not empty", path),
+      ));
+    }
+
+    directories.remove(path);
+
+    self
+      .files
+      .lock()
+      .unwrap()
+      .retain(|file, _| !file.starts_with(path));
+
+    Ok(())
+  }
+
+  fn setegid(&self, _gid: u32) -> Result {
+    Ok(())
+  }
+
+  fn status(&self, path: &Utf8Path) -> Result<Option<FileStatus>> {
+    Ok(self.directory(path).map(|directory| FileStatus {
+      directory: true,
+      mode: directory.mode,
+    }))
+  }
+
+  fn uid_map(&self) -> Result<String> {
+    Ok(self.uid_map.clone())
+  }
+
+  fn umask(&self, mask: Mode) -> Mode {
+    *self.umask.lock().unwrap() = Some(mask);
+    Mode::from_bits_truncate(0)
+  }
+
+  fn umount(&self, path: &Utf8Path, flags: MntFlags) -> nix::Result<()> {
+    self.call(format!("umount {path} {flags:?}"))
+  }
+
+  fn unmount(&self, path: &Utf8Path) -> Result {
+    self.mounts.lock().unwrap().remove(path);
+    Ok(())
+  }
+
+  /// Every process exits with status 0 right away, having used `usage`.
+  fn wait4(&self, pid: Pid) -> Result<(WaitStatus, ResourceUsage)> {
+    Ok((WaitStatus::Exited(pid, 0), self.usage.clone()))
+  }
+
+  fn write_file(&self, path: &Utf8Path, contents: &str) -> io::Result<()> {
+    self
+      .files
+      .lock()
+      .unwrap()
+      .insert(path.to_path_buf(), contents.into());
+
+    Ok(())
+  }
+}
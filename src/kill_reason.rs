@@ -0,0 +1,73 @@
+use super::*;
+
+/// Why the sandbox killed a program, see `ExecutionResult::kill_reason`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum KillReason {
+  /// Program exceeded the CPU time limit, and the watchdog killed it.
+  CpuTimeout,
+  /// Program exceeded the wall time limit, and the watchdog killed it.
+  WallTimeout,
+  /// Program was killed with `Sandbox::kill`, e.g. because the caller is
+  /// shutting down.
+  Shutdown,
+  /// Program was killed by the OOM killer of its control group, see
+  /// `ExecutionResult::killed_by_oom`.
+  Memory,
+}
+
+impl Display for KillReason {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{}",
+      match self {
+        Self::CpuTimeout => "cpu_timeout",
+        Self::WallTimeout => "wall_timeout",
+        Self::Shutdown => "shutdown",
+        Self::Memory => "memory",
+      }
+    )
+  }
+}
+
+impl KillReason {
+  pub(crate) fn message(self) -> &'static str {
+    match self {
+      Self::CpuTimeout => "Time limit exceeded",
+      Self::WallTimeout => "Time limit exceeded (wall clock)",
+      Self::Shutdown => "Killed by the sandbox on shutdown",
+      Self::Memory => "Memory limit exceeded",
+    }
+  }
+
+  /// The status of a program killed for this reason, where a program killed
+  /// on shutdown has no verdict and is `Status::InternalError`.
+  pub(crate) fn status(self) -> Status {
+    match self {
+      Self::CpuTimeout => Status::Timeout,
+      Self::WallTimeout => Status::WallTimeout,
+      Self::Shutdown => Status::InternalError,
+      Self::Memory => Status::MemoryLimit,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn display() {
+    for (reason, expected) in [
+      (KillReason::CpuTimeout, "cpu_timeout"),
+      (KillReason::WallTimeout, "wall_timeout"),
+      (KillReason::Shutdown, "shutdown"),
+      (KillReason::Memory, "memory"),
+    ] {
+      assert_eq!(reason.to_string(), expected);
+    }
+  }
+}
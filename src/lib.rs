@@ -1,34 +1,109 @@
 use {
-  camino::{Utf8Path, Utf8PathBuf},
-  execution_context::ExecutionContext,
-  execution_result::ExecutionResult,
-  mount::Mount,
+  audit_record::AuditRecord,
+  camino::{Utf8Component, Utf8Path, Utf8PathBuf},
+  capture::{Capture, Output},
+  cgroup_stats::CgroupStats,
+  child::Child,
   nix::{
-    sys::stat::{umask, Mode},
-    unistd::{chown, getegid, geteuid, getgid, getuid, setegid, Gid, Uid},
+    cmsg_space,
+    errno::Errno,
+    fcntl::{fcntl, open, FcntlArg, FdFlag, Flock, FlockArg, OFlag},
+    libc,
+    mount::{mount, umount2, MntFlags, MsFlags},
+    poll::{poll, PollFd, PollFlags, PollTimeout},
+    sched::{clone, CloneFlags},
+    sys::{
+      personality::{self, Persona},
+      resource::{getrlimit, rlim_t, setrlimit, Resource, RLIM_INFINITY},
+      signal::{kill, sigprocmask, SigSet, SigmaskHow, Signal},
+      socket::{
+        recvmsg, sendmsg, socketpair, AddressFamily, ControlMessage, ControlMessageOwned, MsgFlags,
+        SockFlag, SockType,
+      },
+      stat::{mknod, stat, umask, Mode, SFlag},
+      statfs::{statfs, FsType, CGROUP2_SUPER_MAGIC, CGROUP_SUPER_MAGIC, TMPFS_MAGIC},
+      termios::{tcgetattr, tcsetattr, SetArg, Termios},
+      time::TimeSpec,
+      timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags},
+      wait::{waitid, waitpid, Id, WaitPidFlag, WaitStatus},
+    },
+    unistd::{
+      access, chdir, chown, chroot, close, dup2, execve, fork, getegid, geteuid, getgid, getpgrp,
+      getuid, pipe2, pivot_root, setegid, setgroups, sethostname, setpgid, setresgid, setresuid,
+      sysconf, tcgetpgrp, tcsetpgrp, write, AccessFlags, ForkResult, Gid, Pid, SysconfVar, Uid,
+    },
   },
+  resource_limit::ResourceLimit,
   std::{
+    borrow::Cow,
+    cell::Cell,
+    collections::BTreeMap,
+    env,
+    ffi::CString,
     fmt::{self, Display, Formatter},
-    fs,
-    os::unix::fs::PermissionsExt,
+    fs::{self, File},
+    io::{self, IoSlice, IoSliceMut, Read, Write},
+    os::{
+      fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+      unix::fs::{lchown, symlink, MetadataExt, OpenOptionsExt, PermissionsExt},
+    },
     path::PathBuf,
+    process,
+    str::FromStr,
+    sync::{
+      atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering},
+      mpsc::{self, RecvTimeoutError},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
   },
+  syscall::syscall,
   system::{MaterialSystem, System},
-  variable::Variable,
+  terminal::Terminal,
+  watchdog::{Split, Watchdog},
+};
+
+#[cfg(feature = "trace")]
+use {
+  nix::sys::ptrace,
+  std::{collections::VecDeque, mem},
 };
 
 #[macro_use]
 mod ensure;
 
+mod audit_record;
+mod capture;
+mod cgroup_stats;
+mod child;
 mod config;
 mod environment;
 mod error;
 mod execution_context;
 mod execution_result;
+#[cfg(test)]
+mod in_memory_system;
+mod kill_reason;
+mod limits;
 mod mount;
+mod resource_limit;
+mod resource_sample;
+mod resource_usage;
+mod result_format;
+mod retry_policy;
+mod rfc3339;
 mod sandbox;
+mod sandbox_metadata;
+mod stdin;
+mod syscall;
 mod system;
+mod terminal;
+mod traced_syscall;
 mod variable;
+mod watchdog;
+
+#[cfg(test)]
+use in_memory_system::InMemorySystem;
 
 type Result<T = (), E = Error> = std::result::Result<T, E>;
 
@@ -36,5 +111,18 @@ pub use {
   config::{CgroupConfig, CgroupRoot, Config},
   environment::Environment,
   error::Error,
+  execution_context::ExecutionContext,
+  execution_result::{ExecutionResult, Status},
+  kill_reason::KillReason,
+  limits::Limits,
+  mount::{Mount, MountOptions},
+  resource_sample::ResourceSample,
+  resource_usage::ResourceUsage,
+  result_format::ResultFormat,
+  retry_policy::RetryPolicy,
   sandbox::Sandbox,
+  sandbox_metadata::SandboxMetadata,
+  stdin::Stdin,
+  traced_syscall::TracedSyscall,
+  variable::{Action, Variable},
 };
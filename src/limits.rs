@@ -0,0 +1,466 @@
+use super::*;
+
+/// The resource limits of a program, see `ExecutionContext::limits`.
+///
+/// Limits can be set up once and shared between contexts, e.g.
+/// `ExecutionContext::new(program, None).limits(limits.clone())`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+  /// Limit size of core files created when a process crashes to 'size'
+  /// kilobytes.
+  ///
+  /// Defaults to zero, meaning that no core files are produced inside the
+  /// sandbox. Otherwise, a core file is written to the working directory of a
+  /// program that crashes, and `ExecutionResult::core_dumped` is set.
+  pub core_size_limit_kb: Option<u32>,
+
+  /// Limit the data segment of the program (its heap, including anonymous
+  /// private mappings) to 'size' kilobytes, by setting `RLIMIT_DATA`.
+  ///
+  /// Unlike `memory_limit_kb`, which limits the whole address space, this
+  /// does not count address space that is merely reserved or mapped from
+  /// files. Prefer it for runtimes that reserve large address ranges up
+  /// front without using them (e.g. JIT compilers and virtual machines such
+  /// as the JVM or V8), which trip `memory_limit_kb` long before they
+  /// actually use that much memory, and leave `memory_limit_kb` unset or
+  /// high for them.
+  ///
+  /// If this limit is reached, memory allocations fail.
+  ///
+  /// Defaults to unlimited.
+  pub data_limit_kb: Option<u32>,
+
+  /// When the `time` limit is exceeded, do not kill the program immediately,
+  /// but wait until `extra_time_ms` milliseconds elapse since the start of the
+  /// program.
+  ///
+  /// This allows to report the real execution time, even if it exceeds the
+  /// limit slightly.
+  ///
+  /// Fractional numbers are allowed.
+  ///
+  /// Defaults to 0.5 seconds.
+  pub extra_time_ms: Option<f64>,
+
+  /// Limit size of each file created (or modified) by the program to 'size'
+  /// kilobytes.
+  ///
+  /// In most cases, it is better to restrict overall disk usage by a disk
+  /// quota (see below).
+  ///
+  /// This option can help in cases when quotas are not enabled
+  /// on the underlying filesystem.
+  ///
+  /// If this limit is reached, system calls expanding files fail with error
+  /// EFBIG and the program receives the SIGXFSZ signal.
+  ///
+  /// Defaults to 8 MB.
+  pub file_size_limit_kb: Option<u32>,
+
+  /// Limit the memory the program can lock into RAM (e.g. with `mlock` or
+  /// `MAP_LOCKED`) to 'size' kilobytes, by setting `RLIMIT_MEMLOCK`.
+  ///
+  /// Locked memory cannot be swapped out, so a program locking lots of it
+  /// puts pressure on the whole host.
+  ///
+  /// If this limit is reached, locking more memory fails with error EPERM or
+  /// ENOMEM.
+  ///
+  /// Defaults to zero, meaning that no memory can be locked.
+  pub locked_memory_limit_kb: Option<u32>,
+
+  /// Limit address space of the program to 'size' kilobytes.
+  ///
+  /// If more processes are allowed, this applies to each of them separately.
+  ///
+  /// If this limit is reached, further memory allocations fail (e.g., malloc
+  /// returns NULL).
+  ///
+  /// Only enforced without a control group. In control group mode, memory is
+  /// limited by `CgroupConfig::memory_limit` instead, which covers all
+  /// processes of the program together, and this limit is ignored.
+  pub memory_limit_kb: Option<u32>,
+
+  /// Limit the POSIX message queues created by the program to 'size'
+  /// kilobytes in total, by setting `RLIMIT_MSGQUEUE`.
+  ///
+  /// Message queues are kept in kernel memory, which is not accounted to the
+  /// program otherwise.
+  ///
+  /// If this limit is reached, `mq_open` fails with error EMFILE.
+  ///
+  /// Defaults to zero, meaning that no message queues can be created.
+  pub message_queue_limit_kb: Option<u32>,
+
+  /// Limit number of open files to 'max'. The default value is 64. Setting
+  /// this option to 0 will result in unlimited open files.
+  ///
+  /// If this limit is reached, system calls creating file descriptors fail
+  /// with error EMFILE.
+  pub open_files_limit: Option<u32>,
+
+  /// Limit the number of signals that can be queued for the user the program
+  /// runs as to 'max', by setting `RLIMIT_SIGPENDING`.
+  ///
+  /// Queued signals are kept in kernel memory, so this keeps a program from
+  /// exhausting it by queueing real-time signals to itself.
+  ///
+  /// If this limit is reached, queueing further signals with `sigqueue`
+  /// fails with error EAGAIN.
+  ///
+  /// Defaults to 64.
+  pub pending_signals_limit: Option<u32>,
+
+  /// Permit the program to create up to 'max' processes and/or threads.
+  ///
+  /// Please keep in mind that time and memory limit do not work with multiple
+  /// processes unless you enable the control group mode.
+  ///
+  /// If 'max' is not given, an arbitrary number of processes can be run.
+  ///
+  /// By default, only one process is permitted.
+  ///
+  /// If this limit is exceeded, system calls creating processes fail with
+  /// error EAGAIN.
+  pub process_limit: Option<u32>,
+
+  /// Limit process stack to 'size' kilobytes.
+  ///
+  /// By default, the whole address space is available for the stack, but it is
+  /// subject to the `memory_limit` limit.
+  ///
+  /// If this limit is exceeded, the program receives the SIGSEGV signal.
+  ///
+  /// Defaults to 32 MB.
+  pub stack_limit_kb: Option<u32>,
+
+  /// Limit run time of the program to 'time' milliseconds.
+  ///
+  /// Fractional numbers are allowed.
+  ///
+  /// Time in which the OS assigns the processor to other tasks is not counted.
+  ///
+  /// If this limit is exceeded, the program is killed (after `extra_time`, if
+  /// set).
+  ///
+  /// Defaults to 1 second.
+  pub time_limit_ms: Option<f64>,
+
+  /// Limit wall-clock time to 'time' milliseconds.
+  ///
+  /// Fractional values are allowed.
+  ///
+  /// This clock measures the time from the start of the program to its exit,
+  /// so it does not stop when the program has lost the CPU or when it is
+  /// waiting for an external event.
+  ///
+  /// We recommend to use `time_limit` as the main limit, but set
+  /// `wall_time_limit_ms` to a much higher value as a precaution against
+  /// sleeping programs.
+  ///
+  /// If this limit is exceeded, the program is killed.
+  ///
+  /// Defaults to 5 seconds.
+  pub wall_time_limit_ms: Option<f64>,
+}
+
+impl Default for Limits {
+  fn default() -> Self {
+    Self {
+      core_size_limit_kb: Some(0),
+      data_limit_kb: None,
+      extra_time_ms: Some(0.5 * 1000.0),
+      file_size_limit_kb: Some(8192),
+      locked_memory_limit_kb: Some(0),
+      memory_limit_kb: Some(256_000),
+      message_queue_limit_kb: Some(0),
+      open_files_limit: Some(64),
+      pending_signals_limit: Some(64),
+      process_limit: Some(1),
+      stack_limit_kb: Some(32_000),
+      time_limit_ms: Some(1.0 * 1000.0),
+      wall_time_limit_ms: Some(5.0 * 1000.0),
+    }
+  }
+}
+
+impl Limits {
+  pub fn core_size_limit_kb(self, core_size_limit_kb: u32) -> Self {
+    Self {
+      core_size_limit_kb: Some(core_size_limit_kb),
+      ..self
+    }
+  }
+
+  pub fn data_limit_kb(self, data_limit_kb: u32) -> Self {
+    Self {
+      data_limit_kb: Some(data_limit_kb),
+      ..self
+    }
+  }
+
+  pub fn extra_time_ms(self, extra_time_ms: f64) -> Self {
+    Self {
+      extra_time_ms: Some(extra_time_ms),
+      ..self
+    }
+  }
+
+  pub fn file_size_limit_kb(self, file_size_limit_kb: u32) -> Self {
+    Self {
+      file_size_limit_kb: Some(file_size_limit_kb),
+      ..self
+    }
+  }
+
+  pub fn locked_memory_limit_kb(self, locked_memory_limit_kb: u32) -> Self {
+    Self {
+      locked_memory_limit_kb: Some(locked_memory_limit_kb),
+      ..self
+    }
+  }
+
+  pub fn memory_limit_kb(self, memory_limit_kb: u32) -> Self {
+    Self {
+      memory_limit_kb: Some(memory_limit_kb),
+      ..self
+    }
+  }
+
+  /// Set `memory_limit_kb` in mebibytes, e.g. `memory_limit_mb(256)` for
+  /// 262144 KB.
+  pub fn memory_limit_mb(self, memory_limit_mb: u32) -> Self {
+    self.memory_limit_kb(memory_limit_mb.saturating_mul(1024))
+  }
+
+  pub fn message_queue_limit_kb(self, message_queue_limit_kb: u32) -> Self {
+    Self {
+      message_queue_limit_kb: Some(message_queue_limit_kb),
+      ..self
+    }
+  }
+
+  pub fn open_files_limit(self, open_files_limit: u32) -> Self {
+    Self {
+      open_files_limit: Some(open_files_limit),
+      ..self
+    }
+  }
+
+  pub fn pending_signals_limit(self, pending_signals_limit: u32) -> Self {
+    Self {
+      pending_signals_limit: Some(pending_signals_limit),
+      ..self
+    }
+  }
+
+  pub fn process_limit(self, process_limit: u32) -> Self {
+    Self {
+      process_limit: Some(process_limit),
+      ..self
+    }
+  }
+
+  pub fn stack_limit_kb(self, stack_limit_kb: u32) -> Self {
+    Self {
+      stack_limit_kb: Some(stack_limit_kb),
+      ..self
+    }
+  }
+
+  /// Set `stack_limit_kb` in mebibytes.
+  pub fn stack_limit_mb(self, stack_limit_mb: u32) -> Self {
+    self.stack_limit_kb(stack_limit_mb.saturating_mul(1024))
+  }
+
+  pub fn time_limit_ms(self, time_limit_ms: f64) -> Self {
+    Self {
+      time_limit_ms: Some(time_limit_ms),
+      ..self
+    }
+  }
+
+  /// Set `time_limit_ms` in seconds, e.g. `time_limit_secs(1.5)`.
+  pub fn time_limit_secs(self, time_limit_secs: f64) -> Self {
+    self.time_limit_ms(time_limit_secs * 1000.0)
+  }
+
+  pub fn wall_time_limit_ms(self, wall_time_limit_ms: f64) -> Self {
+    Self {
+      wall_time_limit_ms: Some(wall_time_limit_ms),
+      ..self
+    }
+  }
+
+  /// Set `wall_time_limit_ms` in seconds.
+  pub fn wall_time_limit_secs(self, wall_time_limit_secs: f64) -> Self {
+    self.wall_time_limit_ms(wall_time_limit_secs * 1000.0)
+  }
+
+  /// Check that the time limits are non-negative numbers.
+  pub fn validate(&self) -> Result {
+    for (name, value) in [
+      ("extra_time_ms", self.extra_time_ms),
+      ("time_limit_ms", self.time_limit_ms),
+      ("wall_time_limit_ms", self.wall_time_limit_ms),
+    ] {
+      ensure!(
+        value.is_none_or(|value| value.is_finite() && value >= 0.0),
+        Error::Config(format!("`{name}` must be a non-negative number"))
+      );
+    }
+
+    Ok(())
+  }
+
+  /// The resource limits applied to the program before it is executed.
+  ///
+  /// Sizes are converted from kilobytes to bytes, and the CPU time limit is
+  /// rounded up to whole seconds, including `extra_time_ms`. With `cgroup`
+  /// set, the address space is left unlimited, since the control group
+  /// enforces the memory limit.
+  pub(crate) fn resource_limits(&self, cgroup: bool) -> Vec<ResourceLimit> {
+    let bytes = |kilobytes: Option<u32>| kilobytes.map(|kilobytes| u64::from(kilobytes) * 1024);
+
+    let cpu_time_secs = self.time_limit_ms.map(|time_limit_ms| {
+      ((time_limit_ms + self.extra_time_ms.unwrap_or(0.0)) / 1000.0).ceil() as u64
+    });
+
+    let mut limits = vec![
+      ResourceLimit::new(
+        Resource::RLIMIT_AS,
+        bytes(self.memory_limit_kb.filter(|_| !cgroup)),
+      ),
+      ResourceLimit::new(Resource::RLIMIT_CORE, bytes(self.core_size_limit_kb)),
+      ResourceLimit::new(Resource::RLIMIT_CPU, cpu_time_secs),
+      ResourceLimit::new(Resource::RLIMIT_DATA, bytes(self.data_limit_kb)),
+      ResourceLimit::new(Resource::RLIMIT_FSIZE, bytes(self.file_size_limit_kb)),
+      ResourceLimit::new(Resource::RLIMIT_MEMLOCK, bytes(self.locked_memory_limit_kb)),
+      ResourceLimit::new(
+        Resource::RLIMIT_MSGQUEUE,
+        bytes(self.message_queue_limit_kb),
+      ),
+      ResourceLimit::new(Resource::RLIMIT_NPROC, self.process_limit.map(u64::from)),
+      ResourceLimit::new(
+        Resource::RLIMIT_SIGPENDING,
+        self.pending_signals_limit.map(u64::from),
+      ),
+      ResourceLimit::new(Resource::RLIMIT_STACK, bytes(self.stack_limit_kb)),
+    ];
+
+    // The kernel refuses an infinite `RLIMIT_NOFILE`, so "unlimited" leaves
+    // the inherited limit in place.
+    if let Some(open_files_limit) = self.open_files_limit.filter(|limit| *limit != 0) {
+      limits.push(ResourceLimit::new(
+        Resource::RLIMIT_NOFILE,
+        Some(u64::from(open_files_limit)),
+      ));
+    }
+
+    limits
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, assert_matches::assert_matches};
+
+  #[test]
+  fn data_limit() {
+    let limits = Limits::default().resource_limits(false);
+
+    assert!(limits.contains(&ResourceLimit::new(Resource::RLIMIT_DATA, None)));
+
+    let limits = Limits::default().data_limit_kb(1024).resource_limits(false);
+
+    assert!(limits.contains(&ResourceLimit::new(
+      Resource::RLIMIT_DATA,
+      Some(1024 * 1024)
+    )));
+  }
+
+  #[test]
+  fn human_units() {
+    let limits = Limits::default()
+      .memory_limit_mb(256)
+      .stack_limit_mb(32)
+      .time_limit_secs(1.5)
+      .wall_time_limit_secs(5.0);
+
+    assert_eq!(limits.memory_limit_kb, Some(262_144));
+    assert_eq!(limits.stack_limit_kb, Some(32_768));
+    assert_eq!(limits.time_limit_ms, Some(1500.0));
+    assert_eq!(limits.wall_time_limit_ms, Some(5000.0));
+
+    assert_eq!(
+      Limits::default().memory_limit_mb(u32::MAX).memory_limit_kb,
+      Some(u32::MAX)
+    );
+
+    assert_eq!(
+      ExecutionContext::new("/bin/true".into(), None)
+        .memory_limit_mb(256)
+        .time_limit_secs(2.0)
+        .limits,
+      Limits::default()
+        .memory_limit_kb(262_144)
+        .time_limit_ms(2000.0)
+    );
+  }
+
+  #[test]
+  fn kernel_memory_limits() {
+    let limits = Limits::default().resource_limits(false);
+
+    for limit in [
+      ResourceLimit::new(Resource::RLIMIT_MEMLOCK, Some(0)),
+      ResourceLimit::new(Resource::RLIMIT_MSGQUEUE, Some(0)),
+      ResourceLimit::new(Resource::RLIMIT_SIGPENDING, Some(64)),
+    ] {
+      assert!(limits.contains(&limit), "{limit:?}");
+    }
+
+    let limits = Limits::default()
+      .locked_memory_limit_kb(4)
+      .message_queue_limit_kb(8)
+      .pending_signals_limit(16)
+      .resource_limits(false);
+
+    for limit in [
+      ResourceLimit::new(Resource::RLIMIT_MEMLOCK, Some(4096)),
+      ResourceLimit::new(Resource::RLIMIT_MSGQUEUE, Some(8192)),
+      ResourceLimit::new(Resource::RLIMIT_SIGPENDING, Some(16)),
+    ] {
+      assert!(limits.contains(&limit), "{limit:?}");
+    }
+  }
+
+  #[test]
+  fn memory_limit() {
+    let limits = Limits::default().memory_limit_kb(1024);
+
+    assert!(limits
+      .resource_limits(false)
+      .contains(&ResourceLimit::new(Resource::RLIMIT_AS, Some(1024 * 1024))));
+
+    assert!(limits
+      .resource_limits(true)
+      .contains(&ResourceLimit::new(Resource::RLIMIT_AS, None)));
+  }
+
+  #[test]
+  fn validate() {
+    assert!(Limits::default().validate().is_ok());
+
+    assert_matches!(
+      Limits::default().time_limit_ms(-1.0).validate(),
+      Err(Error::Config(message)) if message == "`time_limit_ms` must be a non-negative number"
+    );
+
+    assert_matches!(
+      Limits::default().wall_time_limit_ms(f64::INFINITY).validate(),
+      Err(Error::Config(message)) if message.contains("`wall_time_limit_ms`")
+    );
+  }
+}
@@ -1,19 +1,50 @@
 use super::*;
 
-#[derive(Debug, Default, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct MountOptions {
   /// Allow access to character and block devices.
   pub allow_devices: bool,
 
+  /// Device nodes to create in a `filesystem` mount, by their name in the
+  /// `/dev` of the host, e.g. `null`.
+  ///
+  /// Each node is created with the type, device number and permissions of
+  /// the one on the host, before the mount is made read-only (unless it is
+  /// `read_write`). Requires `allow_devices` to be usable, see
+  /// `Mount::minimal_dev`.
+  pub device_nodes: Vec<String>,
+
   /// Instead of binding a directory, mount a device-less filesystem called
   /// `inside_path`.
   ///
   /// For example, this can be `proc` or `sysfs`.
   pub filesystem: Option<String>,
 
+  /// Options passed to the filesystem of a `filesystem` mount, as the data
+  /// argument of `mount(2)`.
+  ///
+  /// For example, `size=64m` limits the size of a `tmpfs`, and
+  /// `newinstance,ptmxmode=0666` gives a `devpts` mount its own set of
+  /// terminals. Only allowed together with `filesystem`.
+  pub fs_data: Option<String>,
+
   /// Disallow execution of binaries.
+  ///
+  /// The directory is mounted with `MS_NOEXEC`, so executing a file from it
+  /// fails with `EACCES`.
   pub no_exec: bool,
 
+  /// Ignore the setuid and setgid bits of binaries on the mount.
+  ///
+  /// The sandbox drops all privileges before running the program, but a
+  /// setuid binary (e.g. from a bind mount of `/usr`) would hand them back
+  /// to it, so this is enabled by default for every mount, devices included.
+  /// Only disable it for a directory whose setuid binaries are trusted and
+  /// genuinely needed inside the sandbox.
+  ///
+  /// Defaults to `true`.
+  pub no_suid: bool,
+
   /// Do not bind recursively.
   ///
   /// Without this option, mount points in the outside directory tree are
@@ -21,6 +52,9 @@ pub struct MountOptions {
   pub no_recursive: bool,
 
   /// Silently ignore the mount if the directory to be bound does not exist.
+  ///
+  /// For `filesystem` mounts, silently ignore a failure to mount the
+  /// filesystem instead, e.g. a `proc` mount inside of a restricted container.
   pub optional: bool,
 
   /// Allow read-write access.
@@ -33,7 +67,24 @@ pub struct MountOptions {
   pub temporary: bool,
 }
 
-#[derive(Debug, Default, PartialEq)]
+impl Default for MountOptions {
+  fn default() -> Self {
+    Self {
+      allow_devices: false,
+      device_nodes: Vec::new(),
+      filesystem: None,
+      fs_data: None,
+      no_exec: false,
+      no_recursive: false,
+      no_suid: true,
+      optional: false,
+      read_write: false,
+      temporary: false,
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Mount {
   /// Path inside the sandbox where the directory will be mounted.
   inside_path: Utf8PathBuf,
@@ -43,6 +94,45 @@ pub struct Mount {
   options: MountOptions,
 }
 
+/// Formats the mount like a `--dir` rule of isolate, e.g. `box=./box:rw` or
+/// `proc=proc:fs`.
+impl Display for Mount {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.inside_path)?;
+
+    if let Some(filesystem) = &self.options.filesystem {
+      write!(f, "={filesystem}")?;
+    } else if let Some(outside_path) = &self.outside_path {
+      write!(f, "={outside_path}")?;
+    }
+
+    let options = [
+      (self.options.read_write && !self.options.temporary, "rw"),
+      (self.options.allow_devices, "dev"),
+      (self.options.no_exec, "noexec"),
+      (!self.options.no_suid, "suid"),
+      (self.options.optional, "maybe"),
+      (self.options.filesystem.is_some(), "fs"),
+      (self.options.temporary, "tmp"),
+      (self.options.no_recursive, "norec"),
+    ];
+
+    for (_, option) in options.iter().filter(|(enabled, _)| *enabled) {
+      write!(f, ":{option}")?;
+    }
+
+    if let Some(fs_data) = &self.options.fs_data {
+      write!(f, " ({fs_data})")?;
+    }
+
+    if !self.options.device_nodes.is_empty() {
+      write!(f, " [{}]", self.options.device_nodes.join(","))?;
+    }
+
+    Ok(())
+  }
+}
+
 impl Mount {
   pub fn new(
     inside_path: impl AsRef<Utf8Path>,
@@ -57,20 +147,30 @@ impl Mount {
       ));
     }
 
+    Ok(Self::from_parts(inside_path, outside_path, options))
+  }
+
+  /// Create a mount without checking its options, for mounts that are known
+  /// to be valid, such as the default ones.
+  pub(crate) fn from_parts(
+    inside_path: impl AsRef<Utf8Path>,
+    outside_path: Option<impl AsRef<Utf8Path>>,
+    options: MountOptions,
+  ) -> Self {
     let read_write = if options.temporary {
       true
     } else {
       options.read_write
     };
 
-    Ok(Self {
-      inside_path: inside_path.to_path_buf(),
+    Self {
+      inside_path: inside_path.as_ref().to_path_buf(),
       outside_path: outside_path.map(|p| p.as_ref().to_path_buf()),
       options: MountOptions {
         read_write,
         ..options
       },
-    })
+    }
   }
 
   pub fn device(
@@ -98,6 +198,25 @@ impl Mount {
     )
   }
 
+  /// A read-only bind mount that also forbids executing binaries.
+  ///
+  /// It is also mounted with `MS_NODEV` and `MS_NOSUID`, which makes it the
+  /// most restrictive kind of bind mount, e.g. for directories that only hold
+  /// data.
+  pub fn hardened(
+    inside_path: impl AsRef<Utf8Path>,
+    outside_path: Option<impl AsRef<Utf8Path>>,
+  ) -> Result<Self> {
+    Self::new(
+      inside_path,
+      outside_path,
+      MountOptions {
+        no_exec: true,
+        ..Default::default()
+      },
+    )
+  }
+
   pub fn optional(
     inside_path: impl AsRef<Utf8Path>,
     outside_path: Option<impl AsRef<Utf8Path>>,
@@ -112,6 +231,117 @@ impl Mount {
     )
   }
 
+  /// A `tmpfs` with only the device nodes a program commonly needs, `null`,
+  /// `zero`, `full`, `random`, `urandom` and `tty`, instead of the whole
+  /// `/dev` of the host, which is what the default `dev` mount binds.
+  ///
+  /// Replace the default mount with it, e.g. with
+  /// `ExecutionContext::mount(Mount::minimal_dev("dev")?)`. The nodes only
+  /// exist inside the sandbox, so `ExecutionContext::special_files` and the
+  /// sweep of the box after a run do not affect them.
+  pub fn minimal_dev(inside_path: impl AsRef<Utf8Path>) -> Result<Self> {
+    Self::new(
+      inside_path,
+      None::<&Utf8Path>,
+      MountOptions {
+        allow_devices: true,
+        device_nodes: ["null", "zero", "full", "random", "urandom", "tty"]
+          .map(String::from)
+          .into(),
+        filesystem: Some("tmpfs".into()),
+        fs_data: Some("size=16k,mode=755".into()),
+        ..Default::default()
+      },
+    )
+  }
+
+  /// A copy-on-write view of the directory `lower`, backed by overlayfs.
+  ///
+  /// The program sees the contents of `lower` and can modify them, but its
+  /// changes go to `upper`, so a single base image can be shared by many
+  /// sandboxes without copying it for each of them. `work` is a scratch
+  /// directory needed by overlayfs, on the same filesystem as `upper`. The
+  /// mount point takes its owner and permissions from `upper`, so it must be
+  /// writable by the sandbox user for the program to create files in it.
+  ///
+  /// All three paths must be absolute, since they are handed to the kernel as
+  /// is, and they are checked when the mount is created: `lower` must be an
+  /// existing directory and `upper` and `work` writable ones.
+  pub fn overlay(
+    inside_path: impl AsRef<Utf8Path>,
+    lower: impl AsRef<Utf8Path>,
+    upper: impl AsRef<Utf8Path>,
+    work: impl AsRef<Utf8Path>,
+  ) -> Result<Self> {
+    let (lower, upper, work) = (lower.as_ref(), upper.as_ref(), work.as_ref());
+
+    for (name, path) in [("lower", lower), ("upper", upper), ("work", work)] {
+      ensure!(
+        path.is_absolute(),
+        Error::Mount(format!(
+          "overlay {name} directory `{path}` must be absolute"
+        ))
+      );
+
+      // The options of overlayfs are separated by commas and colons separate
+      // its lower directories.
+      ensure!(
+        !path.as_str().contains([',', ':']),
+        Error::Mount(format!(
+          "overlay {name} directory `{path}` must not contain `,` or `:`"
+        ))
+      );
+
+      ensure!(
+        path.is_dir(),
+        Error::Mount(format!("overlay {name} directory `{path}` does not exist"))
+      );
+    }
+
+    for (name, path) in [("upper", upper), ("work", work)] {
+      access(path.as_std_path(), AccessFlags::W_OK).map_err(|error| {
+        Error::Mount(format!(
+          "overlay {name} directory `{path}` is not writable: {error}"
+        ))
+      })?;
+    }
+
+    Self::new(
+      inside_path,
+      None::<&Utf8Path>,
+      MountOptions {
+        filesystem: Some("overlay".into()),
+        fs_data: Some(format!("lowerdir={lower},upperdir={upper},workdir={work}")),
+        read_write: true,
+        ..Default::default()
+      },
+    )
+  }
+
+  /// A read-only mount of a fresh `sysfs`, for runtimes that read the CPU
+  /// topology from `/sys/devices/system/cpu` or their limits from
+  /// `/sys/fs/cgroup`, e.g. the JVM, which otherwise might see no CPUs at all.
+  ///
+  /// Not mounted by default, since `sysfs` exposes a lot of information about
+  /// the host, such as its hardware, its devices and the control groups of
+  /// other processes. Only the network devices are limited to the ones of the
+  /// sandbox, i.e. its loopback device, unless `ExecutionContext::share_net`
+  /// is set. Binaries on it cannot be executed. Where less is enough, a
+  /// `Mount::read_only` bind of just the subtree that is needed, e.g.
+  /// `/sys/devices/system/cpu`, exposes less, but needs the directories
+  /// above it to exist in the sandbox.
+  pub fn sysfs(inside_path: impl AsRef<Utf8Path>) -> Result<Self> {
+    Self::new(
+      inside_path,
+      None::<&Utf8Path>,
+      MountOptions {
+        filesystem: Some("sysfs".into()),
+        no_exec: true,
+        ..Default::default()
+      },
+    )
+  }
+
   pub fn read_only(
     inside_path: impl AsRef<Utf8Path>,
     outside_path: Option<impl AsRef<Utf8Path>>,
@@ -133,6 +363,331 @@ impl Mount {
     )
   }
 
+  /// Mount this directory inside `root`.
+  ///
+  /// Relative outside paths are resolved against the sandbox `directory`, and
+  /// without an outside path the directory of the same name on the host is
+  /// bound.
+  ///
+  /// Missing mount points are created as empty files for sources that are
+  /// files and as directories otherwise, unless they would have to be created
+  /// inside one of the `previous` mounts, since that would modify a directory
+  /// outside of the sandbox.
+  pub(crate) fn apply(
+    &self,
+    system: &dyn System,
+    root: &Utf8Path,
+    directory: &Utf8Path,
+    previous: &[&Mount],
+  ) -> Result {
+    let inside_path = self.relative_inside_path();
+
+    let source = self.source(directory);
+
+    if let Some(source) = source.as_ref().filter(|_| self.options.temporary) {
+      fs::create_dir_all(source)?;
+      fs::set_permissions(source, fs::Permissions::from_mode(0o1777))?;
+    }
+
+    if self.options.optional && source.as_ref().is_some_and(|source| !source.exists()) {
+      return Ok(());
+    }
+
+    let target = root.join(inside_path);
+
+    if !target.exists() {
+      ensure!(
+        !previous
+          .iter()
+          .any(|mount| inside_path.starts_with(mount.relative_inside_path())),
+        Error::Mount(format!(
+          "mount point `/{}` does not exist in the directory mounted above it",
+          inside_path
+        ))
+      );
+
+      match &source {
+        Some(source) if source.is_file() => {
+          if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+          }
+
+          File::create(&target)?;
+        }
+        _ => fs::create_dir_all(&target)?,
+      }
+    }
+
+    let Some(source) = source else {
+      let filesystem = self.options.filesystem.as_deref().unwrap_or_default();
+
+      // Device nodes cannot be created on a read-only filesystem, so it is
+      // made read-only after creating them.
+      let flags = if self.options.device_nodes.is_empty() {
+        self.flags()
+      } else {
+        self.flags() - MsFlags::MS_RDONLY
+      };
+
+      match system.mount(
+        Some(filesystem),
+        &target,
+        Some(filesystem),
+        flags,
+        self.options.fs_data.as_deref(),
+      ) {
+        Err(_) if self.options.optional => return Ok(()),
+        Err(error) => return Err(Self::filesystem_error(filesystem, inside_path, error)),
+        Ok(()) => {}
+      }
+
+      if self.options.device_nodes.is_empty() {
+        return Ok(());
+      }
+
+      for node in &self.options.device_nodes {
+        Self::create_device_node(node, &target)?;
+      }
+
+      return system
+        .mount(
+          None,
+          &target,
+          None,
+          MsFlags::MS_REMOUNT | self.flags(),
+          self.options.fs_data.as_deref(),
+        )
+        .map_err(|error| Error::Mount(format!("failed to remount `/{}`: {}", inside_path, error)));
+    };
+
+    let recursive = if self.options.no_recursive {
+      MsFlags::empty()
+    } else {
+      MsFlags::MS_REC
+    };
+
+    system
+      .mount(
+        Some(source.as_str()),
+        &target,
+        None,
+        MsFlags::MS_BIND | recursive,
+        None,
+      )
+      .map_err(|error| {
+        Error::Mount(format!(
+          "failed to bind `{}` to `/{}`: {}",
+          source, inside_path, error
+        ))
+      })?;
+
+    // Bind mounts ignore all flags but `MS_REC` when they are created, so the
+    // restrictions have to be applied by remounting.
+    system
+      .mount(
+        None,
+        &target,
+        None,
+        MsFlags::MS_BIND | MsFlags::MS_REMOUNT | self.flags(),
+        None,
+      )
+      .map_err(|error| Error::Mount(format!("failed to remount `/{}`: {}", inside_path, error)))
+  }
+
+  /// Turn this into a read-only mount, dropping the write access a temporary
+  /// directory implies.
+  pub(crate) fn into_read_only(self) -> Self {
+    Self {
+      options: MountOptions {
+        read_write: false,
+        ..self.options
+      },
+      ..self
+    }
+  }
+
+  pub(crate) fn is_temporary(&self) -> bool {
+    self.options.temporary
+  }
+
+  /// Change the options of this mount with `modify`, checking them again
+  /// like `Mount::new` does.
+  pub(crate) fn modify(self, modify: impl FnOnce(&mut MountOptions)) -> Result<Self> {
+    let mut options = self.options;
+
+    modify(&mut options);
+
+    Self::new(self.inside_path, self.outside_path, options)
+  }
+
+  /// Create the device node `name` of the host in `directory`.
+  fn create_device_node(name: &str, directory: &Utf8Path) -> Result {
+    let source = Utf8Path::new("/dev").join(name);
+
+    let metadata = fs::metadata(&source).map_err(|error| {
+      Error::Mount(format!(
+        "failed to stat device node `{}`: {}",
+        source, error
+      ))
+    })?;
+
+    let path = directory.join(name);
+
+    mknod(
+      path.as_std_path(),
+      SFlag::from_bits_truncate(metadata.mode()) & SFlag::S_IFMT,
+      Mode::from_bits_truncate(metadata.mode()),
+      metadata.rdev(),
+    )
+    .map_err(|error| {
+      Error::Mount(format!(
+        "failed to create device node `{}`: {}",
+        path, error
+      ))
+    })?;
+
+    // The node is created subject to the umask.
+    fs::set_permissions(&path, fs::Permissions::from_mode(metadata.mode() & 0o7777))?;
+
+    Ok(())
+  }
+
+  fn filesystem_error(filesystem: &str, inside_path: &Utf8Path, error: Errno) -> Error {
+    // Mounting a fresh `/proc` is usually the first thing that fails inside
+    // of unprivileged or nested containers.
+    let hint = if filesystem == "proc" {
+      "; is the sandbox running in a restricted container? (mark the mount as optional to proceed without it)"
+    } else {
+      ""
+    };
+
+    Error::Mount(format!(
+      "failed to mount /{}: {}{}",
+      inside_path, error, hint
+    ))
+  }
+
+  fn flags(&self) -> MsFlags {
+    let mut flags = MsFlags::empty();
+
+    if self.options.no_suid {
+      flags |= MsFlags::MS_NOSUID;
+    }
+
+    if !self.options.allow_devices {
+      flags |= MsFlags::MS_NODEV;
+    }
+
+    if self.options.no_exec {
+      flags |= MsFlags::MS_NOEXEC;
+    }
+
+    if !self.options.read_write {
+      flags |= MsFlags::MS_RDONLY;
+    }
+
+    flags
+  }
+
+  /// The inside path relative to the root of the sandbox, so that it can
+  /// never escape it.
+  /// The directory on the host that is bound, resolved against the sandbox
+  /// `directory`, or `None` for `filesystem` mounts.
+  pub(crate) fn source(&self, directory: &Utf8Path) -> Option<Utf8PathBuf> {
+    if self.options.filesystem.is_some() {
+      None
+    } else if self.options.temporary {
+      Some(
+        directory
+          .join("temporary")
+          .join(self.relative_inside_path()),
+      )
+    } else {
+      Some(match &self.outside_path {
+        Some(outside_path) => directory.join(outside_path),
+        None => Utf8Path::new("/").join(self.relative_inside_path()),
+      })
+    }
+  }
+
+  /// Number of directories between the root of the sandbox and the mount
+  /// point.
+  pub(crate) fn depth(&self) -> usize {
+    self
+      .relative_inside_path()
+      .components()
+      .filter(|component| matches!(component, Utf8Component::Normal(_)))
+      .count()
+  }
+
+  pub(crate) fn relative_inside_path(&self) -> &Utf8Path {
+    self
+      .inside_path
+      .strip_prefix("/")
+      .unwrap_or(&self.inside_path)
+  }
+
+  /// Check that the mount stays inside the sandbox and that its options fit
+  /// together.
+  pub(crate) fn validate(&self) -> Result {
+    ensure!(
+      !self.relative_inside_path().as_str().is_empty(),
+      Error::Mount("inside path must not be empty".into())
+    );
+
+    ensure!(
+      self
+        .inside_path
+        .components()
+        .all(|component| component != Utf8Component::ParentDir),
+      Error::Mount(format!(
+        "inside path `{}` must not contain `..`",
+        self.inside_path
+      ))
+    );
+
+    ensure!(
+      !self.options.temporary || self.outside_path.is_none(),
+      Error::Mount("temporary directory cannot have an outside path".into())
+    );
+
+    ensure!(
+      self.options.filesystem.is_none() || self.outside_path.is_none(),
+      Error::Mount(format!(
+        "filesystem mount at `{}` cannot have an outside path",
+        self.inside_path
+      ))
+    );
+
+    ensure!(
+      self.options.filesystem.is_some() || self.options.device_nodes.is_empty(),
+      Error::Mount(format!(
+        "device nodes at `{}` require a filesystem",
+        self.inside_path
+      ))
+    );
+
+    for node in &self.options.device_nodes {
+      ensure!(
+        matches!(
+          Utf8Path::new(node).components().collect::<Vec<_>>()[..],
+          [Utf8Component::Normal(_)]
+        ),
+        Error::Mount(format!("invalid device node `{}`", node))
+      );
+    }
+
+    ensure!(
+      self.options.filesystem.is_some() || self.options.fs_data.is_none(),
+      Error::Mount(format!(
+        "mount data at `{}` requires a filesystem",
+        self.inside_path
+      ))
+    );
+
+    Ok(())
+  }
+
   pub fn temporary(inside_path: impl AsRef<Utf8Path>) -> Result<Self> {
     Self::new(
       inside_path,
@@ -148,7 +703,7 @@ impl Mount {
 
 #[cfg(test)]
 mod tests {
-  use {super::*, assert_matches::assert_matches};
+  use {super::*, assert_matches::assert_matches, tempfile::TempDir};
 
   #[test]
   fn valid_mount() {
@@ -161,6 +716,47 @@ mod tests {
     assert!(mount.is_ok());
   }
 
+  #[test]
+  fn display() {
+    let host = None::<&Utf8Path>;
+
+    let mounts = ExecutionContext::default()
+      .mount(Mount::hardened("/data", Some("/srv/data")).unwrap())
+      .mounts;
+
+    assert_eq!(
+      mounts.iter().map(ToString::to_string).collect::<Vec<_>>(),
+      [
+        "box=./box:rw",
+        "bin",
+        "dev:dev",
+        "lib",
+        "lib64:maybe",
+        "proc=proc:fs",
+        "tmp:tmp",
+        "usr",
+        "/data=/srv/data:noexec",
+      ]
+    );
+
+    assert_eq!(
+      Mount::new(
+        "/scratch",
+        host,
+        MountOptions {
+          filesystem: Some("tmpfs".into()),
+          fs_data: Some("size=64k".into()),
+          no_suid: false,
+          read_write: true,
+          ..Default::default()
+        },
+      )
+      .unwrap()
+      .to_string(),
+      "/scratch=tmpfs:rw:suid:fs (size=64k)"
+    );
+  }
+
   #[test]
   fn temporary_with_outside_path() {
     let options = MountOptions {
@@ -241,4 +837,210 @@ mod tests {
       }
     );
   }
+
+  #[test]
+  fn sysfs() {
+    let mount = Mount::sysfs("sys").unwrap();
+
+    assert_eq!(
+      mount.options,
+      MountOptions {
+        filesystem: Some("sysfs".into()),
+        no_exec: true,
+        ..Default::default()
+      }
+    );
+
+    assert_eq!(
+      mount.flags(),
+      MsFlags::MS_RDONLY | MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC
+    );
+
+    assert!(mount.validate().is_ok());
+  }
+
+  #[test]
+  fn filesystem_data() {
+    let options = MountOptions {
+      filesystem: Some("tmpfs".into()),
+      fs_data: Some("size=1m".into()),
+      read_write: true,
+      ..Default::default()
+    };
+
+    let mount = Mount::new("scratch", None::<&Utf8Path>, options.clone()).unwrap();
+
+    assert_eq!(mount.options, options);
+
+    assert!(mount.validate().is_ok());
+
+    let mount = Mount::new(
+      "scratch",
+      None::<&Utf8Path>,
+      MountOptions {
+        fs_data: Some("size=1m".into()),
+        temporary: true,
+        ..Default::default()
+      },
+    )
+    .unwrap();
+
+    assert_matches!(
+      mount.validate(),
+      Err(Error::Mount(message)) if message == "mount data at `scratch` requires a filesystem"
+    );
+  }
+
+  #[test]
+  fn minimal_dev() {
+    let mount = Mount::minimal_dev("dev").unwrap();
+
+    assert!(mount.validate().is_ok());
+
+    assert_eq!(
+      mount.to_string(),
+      "dev=tmpfs:dev:fs (size=16k,mode=755) [null,zero,full,random,urandom,tty]"
+    );
+
+    assert_eq!(mount.flags(), MsFlags::MS_NOSUID | MsFlags::MS_RDONLY);
+
+    for (options, expected) in [
+      (
+        MountOptions {
+          device_nodes: vec!["null".into()],
+          ..Default::default()
+        },
+        "device nodes at `dev` require a filesystem",
+      ),
+      (
+        MountOptions {
+          device_nodes: vec!["../sda".into()],
+          filesystem: Some("tmpfs".into()),
+          ..Default::default()
+        },
+        "invalid device node `../sda`",
+      ),
+    ] {
+      assert_matches!(
+        Mount::new("dev", None::<&Utf8Path>, options).unwrap().validate(),
+        Err(Error::Mount(message)) if message == expected
+      );
+    }
+  }
+
+  #[test]
+  fn overlay() {
+    let directory = TempDir::new().unwrap();
+
+    let path = |name: &str| {
+      let path = Utf8PathBuf::try_from(directory.path().join(name)).unwrap();
+      fs::create_dir_all(&path).unwrap();
+      path
+    };
+
+    let (lower, upper, work) = (path("lower"), path("upper"), path("work"));
+
+    let mount = Mount::overlay("/image", &lower, &upper, &work).unwrap();
+
+    assert_eq!(
+      mount.options,
+      MountOptions {
+        filesystem: Some("overlay".into()),
+        fs_data: Some(format!("lowerdir={lower},upperdir={upper},workdir={work}")),
+        read_write: true,
+        ..Default::default()
+      }
+    );
+
+    assert!(mount.validate().is_ok());
+
+    assert_matches!(
+      Mount::overlay("/image", "lower", &upper, &work),
+      Err(Error::Mount(message)) if message == "overlay lower directory `lower` must be absolute"
+    );
+
+    let missing = lower.join("missing");
+
+    assert_matches!(
+      Mount::overlay("/image", &lower, &missing, &work),
+      Err(Error::Mount(message))
+        if message == format!("overlay upper directory `{missing}` does not exist")
+    );
+
+    let separated = path("a,b");
+
+    assert_matches!(
+      Mount::overlay("/image", &lower, &upper, &separated),
+      Err(Error::Mount(message))
+        if message == format!("overlay work directory `{separated}` must not contain `,` or `:`")
+    );
+  }
+
+  #[test]
+  fn inside_path_is_relative_to_root() {
+    let mount = Mount::read_only("/usr/lib", None::<&Utf8Path>).unwrap();
+
+    assert_eq!(mount.relative_inside_path(), "usr/lib");
+
+    let mount = Mount::read_only("usr/lib", None::<&Utf8Path>).unwrap();
+
+    assert_eq!(mount.relative_inside_path(), "usr/lib");
+  }
+
+  #[test]
+  fn mount_flags() {
+    let flags = Mount::read_only("usr", None::<&Utf8Path>).unwrap().flags();
+
+    assert_eq!(
+      flags,
+      MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_RDONLY
+    );
+
+    let flags = Mount::device("dev", None::<&Utf8Path>).unwrap().flags();
+
+    assert_eq!(flags, MsFlags::MS_NOSUID | MsFlags::MS_RDONLY);
+
+    let flags = Mount::temporary("tmp").unwrap().flags();
+
+    assert_eq!(flags, MsFlags::MS_NOSUID | MsFlags::MS_NODEV);
+
+    let flags = Mount::hardened("data", None::<&Utf8Path>).unwrap().flags();
+
+    assert_eq!(
+      flags,
+      MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC | MsFlags::MS_RDONLY
+    );
+  }
+
+  #[test]
+  fn mount_flags_without_no_suid() {
+    let flags = Mount::new(
+      "opt",
+      None::<&Utf8Path>,
+      MountOptions {
+        no_suid: false,
+        ..Default::default()
+      },
+    )
+    .unwrap()
+    .flags();
+
+    assert_eq!(flags, MsFlags::MS_NODEV | MsFlags::MS_RDONLY);
+  }
+
+  #[test]
+  fn proc_mount_error_hint() {
+    assert_matches!(
+      Mount::filesystem_error("proc", Utf8Path::new("proc"), Errno::EPERM),
+      Error::Mount(message) if message == format!(
+        "failed to mount /proc: {}; is the sandbox running in a restricted container? (mark the mount as optional to proceed without it)",
+        Errno::EPERM
+      )
+    );
+
+    assert_matches!(
+      Mount::filesystem_error("sysfs", Utf8Path::new("sys"), Errno::EPERM),
+      Error::Mount(message) if message == format!("failed to mount /sys: {}", Errno::EPERM)
+    );
+  }
 }
@@ -0,0 +1,112 @@
+use super::*;
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct ResourceLimit {
+  /// The resource being limited.
+  resource: Resource,
+  /// The value used for both the soft and the hard limit.
+  value: rlim_t,
+}
+
+impl Display for ResourceLimit {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{:?}: {}", self.resource, Self::display(self.value))
+  }
+}
+
+impl ResourceLimit {
+  /// Create a limit for `resource`, where `None` means unlimited.
+  pub(crate) fn new(resource: Resource, value: Option<u64>) -> Self {
+    Self {
+      resource,
+      value: value.map_or(RLIM_INFINITY, |value| value as rlim_t),
+    }
+  }
+
+  /// Set both the soft and the hard limit of the calling process.
+  pub(crate) fn apply(&self) -> Result {
+    syscall(
+      &format!("set {:?} to {}", self.resource, Self::display(self.value)),
+      setrlimit(self.resource, self.value, self.value),
+    )
+  }
+
+  /// Set both the soft and the hard limit of the process `pid`.
+  pub(crate) fn apply_to(&self, system: &dyn System, pid: Pid) -> Result {
+    system.prlimit(pid, self.resource, self.value)
+  }
+
+  /// Read the limit back from the kernel and make sure it is the one we asked
+  /// for.
+  ///
+  /// `setrlimit` succeeding is not enough on its own, since a limit applied
+  /// earlier on could have been silently replaced since then.
+  pub(crate) fn verify(&self) -> Result {
+    let actual = syscall(
+      &format!("read back {:?}", self.resource),
+      getrlimit(self.resource),
+    )?;
+
+    self.check(actual)
+  }
+
+  fn check(&self, (soft, hard): (rlim_t, rlim_t)) -> Result {
+    ensure!(
+      soft == self.value && hard == self.value,
+      Error::Internal(format!(
+        "{:?} was not applied (requested {}, found soft {} and hard {})",
+        self.resource,
+        Self::display(self.value),
+        Self::display(soft),
+        Self::display(hard)
+      ))
+    );
+
+    Ok(())
+  }
+
+  fn display(value: rlim_t) -> String {
+    if value == RLIM_INFINITY {
+      "unlimited".into()
+    } else {
+      value.to_string()
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, assert_matches::assert_matches};
+
+  #[test]
+  fn unlimited_when_none() {
+    assert_eq!(
+      ResourceLimit::new(Resource::RLIMIT_AS, None).value,
+      RLIM_INFINITY
+    );
+  }
+
+  #[test]
+  fn check_matching_limits() {
+    let limit = ResourceLimit::new(Resource::RLIMIT_NOFILE, Some(64));
+
+    assert!(limit.check((64, 64)).is_ok());
+  }
+
+  #[test]
+  fn check_mismatched_limits() {
+    let limit = ResourceLimit::new(Resource::RLIMIT_STACK, Some(1024));
+
+    assert_matches!(
+      limit.check((1024, 2048)),
+      Err(Error::Internal(message)) if message == "RLIMIT_STACK was not applied (requested 1024, found soft 1024 and hard 2048)"
+    );
+
+    let limit = ResourceLimit::new(Resource::RLIMIT_CPU, None);
+
+    assert_matches!(
+      limit.check((2, 2)),
+      Err(Error::Internal(message)) if message.contains("requested unlimited")
+    );
+  }
+}
@@ -0,0 +1,99 @@
+use super::*;
+
+/// A snapshot of the resources used by a program that is still running, see
+/// `Sandbox::sample`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResourceSample {
+  /// CPU time used by the program so far in milliseconds.
+  pub cpu_time_ms: f64,
+
+  /// Current memory usage (resident set size) in kilobytes.
+  pub memory_kb: u32,
+}
+
+impl ResourceSample {
+  /// Read the usage of `process` from `/proc`.
+  pub(crate) fn read(system: &dyn System, process: Pid) -> Result<Self> {
+    let status = system.read_file(format!("/proc/{}/status", process).as_ref())?;
+
+    Ok(Self {
+      cpu_time_ms: Watchdog::cpu_time(system, process)?.as_secs_f64() * 1000.0,
+      memory_kb: Self::parse_memory(&status)?,
+    })
+  }
+
+  /// The resident set size from the contents of `/proc/<pid>/status`.
+  ///
+  /// A process that has exited but was not reaped yet has no memory left and
+  /// no `VmRSS` line either.
+  fn parse_memory(status: &str) -> Result<u32> {
+    let Some(line) = status.lines().find_map(|line| line.strip_prefix("VmRSS:")) else {
+      return Ok(0);
+    };
+
+    line
+      .trim()
+      .strip_suffix("kB")
+      .and_then(|kilobytes| kilobytes.trim().parse().ok())
+      .ok_or_else(|| Error::Internal(format!("unexpected process status `VmRSS:{}`", line)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, assert_matches::assert_matches};
+
+  #[test]
+  fn parse_memory() {
+    assert_eq!(
+      ResourceSample::parse_memory("Name:\tsh\nVmPeak:\t    2816 kB\nVmRSS:\t    1024 kB\n")
+        .unwrap(),
+      1024
+    );
+
+    assert_eq!(
+      ResourceSample::parse_memory("Name:\tsh\nState:\tZ (zombie)\n").unwrap(),
+      0
+    );
+
+    assert_matches!(
+      ResourceSample::parse_memory("VmRSS:\t    lots\n"),
+      Err(Error::Internal(message)) if message == "unexpected process status `VmRSS:\t    lots`"
+    );
+  }
+
+  #[test]
+  fn read() {
+    let sample = ResourceSample::read(&MaterialSystem, Pid::this()).unwrap();
+
+    assert!(sample.memory_kb > 0);
+  }
+
+  #[test]
+  fn read_from_system() {
+    let system = InMemorySystem::default();
+
+    for (path, contents) in [
+      (
+        "/proc/42/stat",
+        "42 (sh) S 1 42 42 0 -1 4194304 100 0 0 0 30 20 0 0\n",
+      ),
+      ("/proc/42/status", "Name:\tsh\nVmRSS:\t    2048 kB\n"),
+    ] {
+      system.write_file(path.as_ref(), contents).unwrap();
+    }
+
+    assert_eq!(
+      ResourceSample::read(&system, Pid::from_raw(42)).unwrap(),
+      ResourceSample {
+        cpu_time_ms: 500.0,
+        memory_kb: 2048,
+      }
+    );
+
+    assert_matches!(
+      ResourceSample::read(&system, Pid::from_raw(43)),
+      Err(Error::Io(error)) if error.kind() == io::ErrorKind::NotFound
+    );
+  }
+}
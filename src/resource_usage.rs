@@ -0,0 +1,95 @@
+use super::*;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResourceUsage {
+  /// Peak memory usage of the control group in kilobytes, read from its
+  /// `memory.peak`.
+  ///
+  /// Includes cached data from previous runs in the same sandbox, and for a
+  /// control group that is not managed, see `CgroupConfig::manage`, the
+  /// usage since the group was created. Only reported in control group mode
+  /// on Linux 5.19+.
+  pub cgroup_memory_kb: u32,
+
+  /// Number of involuntary context switches (forced by kernel).
+  pub context_switches_forced: u32,
+
+  /// Number of voluntary context switches (process yielded CPU).
+  pub context_switches_voluntary: u32,
+
+  /// CPU time used by the process in milliseconds, the sum of
+  /// `user_time_ms` and `system_time_ms`.
+  ///
+  /// See also `ExecutionResult::cpu_time_secs`.
+  pub cpu_time_ms: f64,
+
+  /// Peak memory usage (resident set size) in kilobytes.
+  pub peak_memory_kb: u32,
+
+  /// CPU time spent in the kernel on behalf of the process in milliseconds.
+  pub system_time_ms: f64,
+
+  /// CPU time spent running the process itself in milliseconds.
+  pub user_time_ms: f64,
+}
+
+impl ResourceUsage {
+  /// The usage of a reaped process, as reported by `wait4`.
+  ///
+  /// The control group memory usage is not part of it and left at zero.
+  pub(crate) fn from_rusage(usage: &libc::rusage) -> Self {
+    let count = |count: libc::c_long| u32::try_from(count).unwrap_or(u32::MAX);
+
+    let millis = |time: libc::timeval| time.tv_sec as f64 * 1000.0 + time.tv_usec as f64 / 1000.0;
+
+    Self {
+      cgroup_memory_kb: 0,
+      context_switches_forced: count(usage.ru_nivcsw),
+      context_switches_voluntary: count(usage.ru_nvcsw),
+      cpu_time_ms: millis(usage.ru_utime) + millis(usage.ru_stime),
+      // Linux reports the maximum resident set size in kilobytes.
+      peak_memory_kb: count(usage.ru_maxrss),
+      system_time_ms: millis(usage.ru_stime),
+      user_time_ms: millis(usage.ru_utime),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn from_rusage() {
+    // SAFETY: An all-zero `rusage` is a valid value.
+    let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+
+    usage.ru_utime = libc::timeval {
+      tv_sec: 1,
+      tv_usec: 250_000,
+    };
+
+    usage.ru_stime = libc::timeval {
+      tv_sec: 0,
+      tv_usec: 500,
+    };
+
+    usage.ru_maxrss = 2048;
+    usage.ru_nivcsw = 3;
+    usage.ru_nvcsw = -1;
+
+    assert_eq!(
+      ResourceUsage::from_rusage(&usage),
+      ResourceUsage {
+        cgroup_memory_kb: 0,
+        context_switches_forced: 3,
+        context_switches_voluntary: u32::MAX,
+        cpu_time_ms: 1250.5,
+        peak_memory_kb: 2048,
+        system_time_ms: 0.5,
+        user_time_ms: 1250.0,
+      }
+    );
+  }
+}
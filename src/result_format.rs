@@ -0,0 +1,432 @@
+use super::*;
+
+/// Format used by `ExecutionResult::write_to`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ResultFormat {
+  /// `key: value` lines modelled on the meta files of DOMjudge's `runguard`,
+  /// e.g. `cpu-time: 0.012`. The layout follows runguard's source but has
+  /// not been checked against files runguard wrote, so it is not guaranteed
+  /// to be a drop-in replacement for them.
+  ///
+  /// Memory is in bytes and times are in seconds. Like runguard, a program
+  /// terminated by a signal gets a `signal` entry and an `exitcode` of 128
+  /// plus the signal, a program the sandbox killed for running out of time a
+  /// `time-result` of `hard-timelimit`, and an internal error an
+  /// `internal-error` entry.
+  DomJudge,
+  /// A JSON object mirroring the fields of `ExecutionResult`, with the
+  /// resource usage nested under `usage` and timestamps in RFC 3339 format.
+  Json,
+  /// The `key:value` lines of Isolate's meta files, e.g. `time:0.012`.
+  ///
+  /// Times are in seconds, and keys that do not apply to a run (e.g.
+  /// `exitsig` for a program that exited normally) are left out.
+  #[default]
+  Meta,
+}
+
+impl ResultFormat {
+  pub(crate) fn write(self, result: &ExecutionResult, writer: &mut dyn Write) -> io::Result<()> {
+    match self {
+      Self::DomJudge => Self::write_domjudge(result, writer),
+      Self::Json => Self::write_json(result, writer),
+      Self::Meta => Self::write_meta(result, writer),
+    }
+  }
+
+  fn write_domjudge(result: &ExecutionResult, writer: &mut dyn Write) -> io::Result<()> {
+    let usage = &result.usage;
+
+    let memory_kb = if usage.cgroup_memory_kb != 0 {
+      usage.cgroup_memory_kb
+    } else {
+      usage.peak_memory_kb
+    };
+
+    writeln!(writer, "memory-bytes: {}", u64::from(memory_kb) * 1024)?;
+
+    let time_used = match result.status {
+      Status::WallTimeout => "wall-time",
+      _ => "cpu-time",
+    };
+
+    writeln!(writer, "time-used: {}", time_used)?;
+
+    if result.termination_signal != 0 {
+      writeln!(writer, "signal: {}", result.termination_signal)?;
+    }
+
+    let exit_code = result
+      .exit_code
+      .or((result.termination_signal != 0).then(|| 128 + result.termination_signal));
+
+    if let Some(code) = exit_code {
+      writeln!(writer, "exitcode: {}", code)?;
+    }
+
+    writeln!(writer, "wall-time: {:.3}", result.wall_time_secs())?;
+    writeln!(writer, "user-time: {:.3}", usage.user_time_ms / 1000.0)?;
+    writeln!(writer, "sys-time: {:.3}", usage.system_time_ms / 1000.0)?;
+    writeln!(writer, "cpu-time: {:.3}", result.cpu_time_secs())?;
+
+    let time_result = if matches!(
+      result.kill_reason,
+      Some(KillReason::CpuTimeout | KillReason::WallTimeout)
+    ) {
+      "hard-timelimit"
+    } else {
+      ""
+    };
+
+    writeln!(writer, "time-result: {}", time_result)?;
+    writeln!(writer, "stdout-bytes: {}", result.stdout.len())?;
+    writeln!(writer, "stderr-bytes: {}", result.stderr.len())?;
+
+    if matches!(result.status, Status::InternalError) {
+      writeln!(
+        writer,
+        "internal-error: {}",
+        result.status_message.replace('\n', " ")
+      )?;
+    }
+
+    Ok(())
+  }
+
+  fn write_json(result: &ExecutionResult, writer: &mut dyn Write) -> io::Result<()> {
+    let usage = &result.usage;
+
+    writeln!(
+      writer,
+      concat!(
+        "{{\"box_disk_usage_kb\":{},\"core_dumped\":{},\"cpu_time_after_warmup_ms\":{},",
+        "\"exceeded_memory_limit\":{},\"exit_code\":{},",
+        "\"finished_at\":{},\"kill_reason\":{},\"killed_by_oom\":{},\"setup_time_ms\":{},\"started_at\":{},",
+        "\"status\":{},\"status_message\":{},\"stderr\":{},\"stdout\":{},",
+        "\"teardown_time_ms\":{},\"terminated_by_sandbox\":{},\"termination_signal\":{},",
+        "\"usage\":{{\"cgroup_memory_kb\":{},\"context_switches_forced\":{},",
+        "\"context_switches_voluntary\":{},\"cpu_time_ms\":{},",
+        "\"peak_memory_kb\":{},\"system_time_ms\":{},\"user_time_ms\":{}}},",
+        "\"wall_time_after_warmup_ms\":{},\"wall_time_ms\":{}}}"
+      ),
+      result.box_disk_usage_kb,
+      result.core_dumped,
+      Self::json_optional_number(result.cpu_time_after_warmup_ms),
+      result.exceeded_memory_limit,
+      result
+        .exit_code
+        .map_or_else(|| "null".into(), |code| code.to_string()),
+      Self::json_string(&rfc3339::format(result.finished_at)),
+      result
+        .kill_reason
+        .map_or_else(|| "null".into(), |reason| Self::json_string(&reason.to_string())),
+      result.killed_by_oom,
+      Self::json_number(result.setup_time_ms),
+      Self::json_string(&rfc3339::format(result.started_at)),
+      Self::json_string(&result.status.to_string()),
+      Self::json_string(&result.status_message),
+      Self::json_string(&result.stderr),
+      Self::json_string(&result.stdout),
+      Self::json_number(result.teardown_time_ms),
+      result.terminated_by_sandbox,
+      result.termination_signal,
+      usage.cgroup_memory_kb,
+      usage.context_switches_forced,
+      usage.context_switches_voluntary,
+      Self::json_number(usage.cpu_time_ms),
+      usage.peak_memory_kb,
+      Self::json_number(usage.system_time_ms),
+      Self::json_number(usage.user_time_ms),
+      Self::json_optional_number(result.wall_time_after_warmup_ms),
+      Self::json_number(result.wall_time_ms),
+    )
+  }
+
+  fn write_meta(result: &ExecutionResult, writer: &mut dyn Write) -> io::Result<()> {
+    let usage = &result.usage;
+
+    if usage.cgroup_memory_kb != 0 {
+      writeln!(writer, "cg-mem:{}", usage.cgroup_memory_kb)?;
+    }
+
+    if result.killed_by_oom {
+      writeln!(writer, "cg-oom-killed:1")?;
+    }
+
+    writeln!(writer, "csw-forced:{}", usage.context_switches_forced)?;
+    writeln!(writer, "csw-voluntary:{}", usage.context_switches_voluntary)?;
+
+    if let Some(code) = result.exit_code {
+      writeln!(writer, "exitcode:{}", code)?;
+    }
+
+    if result.termination_signal != 0 {
+      writeln!(writer, "exitsig:{}", result.termination_signal)?;
+    }
+
+    if result.terminated_by_sandbox {
+      writeln!(writer, "killed:1")?;
+    }
+
+    writeln!(writer, "max-rss:{}", usage.peak_memory_kb)?;
+
+    if !result.status_message.is_empty() {
+      // A meta file holds one entry per line.
+      writeln!(
+        writer,
+        "message:{}",
+        result.status_message.replace('\n', " ")
+      )?;
+    }
+
+    if !matches!(result.status, Status::Ok) {
+      writeln!(writer, "status:{}", result.status)?;
+    }
+
+    writeln!(writer, "time:{:.3}", result.cpu_time_secs())?;
+    writeln!(writer, "time-wall:{:.3}", result.wall_time_secs())?;
+
+    Ok(())
+  }
+
+  /// JSON has no representation for NaN or infinity.
+  pub(crate) fn json_number(value: f64) -> String {
+    if value.is_finite() {
+      value.to_string()
+    } else {
+      "null".into()
+    }
+  }
+
+  pub(crate) fn json_optional_number(value: Option<f64>) -> String {
+    value.map_or_else(|| "null".into(), Self::json_number)
+  }
+
+  pub(crate) fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+
+    escaped.push('"');
+
+    for character in value.chars() {
+      match character {
+        '"' => escaped.push_str("\\\""),
+        '\\' => escaped.push_str("\\\\"),
+        '\n' => escaped.push_str("\\n"),
+        '\r' => escaped.push_str("\\r"),
+        '\t' => escaped.push_str("\\t"),
+        character if (character as u32) < 0x20 => {
+          escaped.push_str(&format!("\\u{:04x}", character as u32));
+        }
+        character => escaped.push(character),
+      }
+    }
+
+    escaped.push('"');
+
+    escaped
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn result() -> ExecutionResult {
+    ExecutionResult {
+      cpu_time_after_warmup_ms: Some(10.0),
+      exit_code: Some(1),
+      finished_at: UNIX_EPOCH + Duration::from_millis(1_709_251_201_500),
+      setup_time_ms: 3.5,
+      started_at: UNIX_EPOCH + Duration::from_secs(1_709_251_200),
+      status: Status::RuntimeError,
+      status_message: "Exited with error status 1".into(),
+      stdout: "a \"quoted\"\tline\n".into(),
+      teardown_time_ms: 0.25,
+      usage: ResourceUsage {
+        context_switches_forced: 2,
+        context_switches_voluntary: 3,
+        cpu_time_ms: 12.0,
+        peak_memory_kb: 1024,
+        system_time_ms: 2.0,
+        user_time_ms: 10.0,
+        ..Default::default()
+      },
+      wall_time_ms: 1500.0,
+      ..Default::default()
+    }
+  }
+
+  fn write(format: ResultFormat, result: &ExecutionResult) -> String {
+    let mut output = Vec::new();
+
+    format.write(result, &mut output).unwrap();
+
+    String::from_utf8(output).unwrap()
+  }
+
+  #[test]
+  fn meta() {
+    assert_eq!(
+      write(ResultFormat::Meta, &result()),
+      "csw-forced:2\n\
+       csw-voluntary:3\n\
+       exitcode:1\n\
+       max-rss:1024\n\
+       message:Exited with error status 1\n\
+       status:RE\n\
+       time:0.012\n\
+       time-wall:1.500\n"
+    );
+  }
+
+  #[test]
+  fn meta_signaled() {
+    let result = ExecutionResult {
+      kill_reason: Some(KillReason::CpuTimeout),
+      status: Status::Timeout,
+      status_message: "Time limit exceeded".into(),
+      terminated_by_sandbox: true,
+      termination_signal: 9,
+      ..Default::default()
+    };
+
+    let meta = write(ResultFormat::Meta, &result);
+
+    assert!(meta.contains("exitsig:9\n"));
+    assert!(meta.contains("killed:1\n"));
+    assert!(meta.contains("status:TO\n"));
+    assert!(!meta.contains("exitcode"));
+  }
+
+  #[test]
+  fn domjudge() {
+    let entries = |meta: &str| {
+      meta
+        .lines()
+        .map(|line| line.split_once(": ").unwrap().0.to_owned())
+        .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+      write(ResultFormat::DomJudge, &result()),
+      "memory-bytes: 1048576\n\
+       time-used: cpu-time\n\
+       exitcode: 1\n\
+       wall-time: 1.500\n\
+       user-time: 0.010\n\
+       sys-time: 0.002\n\
+       cpu-time: 0.012\n\
+       time-result: \n\
+       stdout-bytes: 16\n\
+       stderr-bytes: 0\n"
+    );
+
+    let result = ExecutionResult {
+      kill_reason: Some(KillReason::CpuTimeout),
+      status: Status::Timeout,
+      terminated_by_sandbox: true,
+      termination_signal: 9,
+      usage: ResourceUsage {
+        cgroup_memory_kb: 2048,
+        peak_memory_kb: 1024,
+        ..Default::default()
+      },
+      ..Default::default()
+    };
+
+    let meta = write(ResultFormat::DomJudge, &result);
+
+    assert!(meta.contains("memory-bytes: 2097152\n"));
+    assert!(meta.contains("signal: 9\nexitcode: 137\n"));
+    assert!(meta.contains("time-result: hard-timelimit\n"));
+
+    let shutdown = write(
+      ResultFormat::DomJudge,
+      &ExecutionResult {
+        kill_reason: Some(KillReason::Shutdown),
+        status: Status::InternalError,
+        terminated_by_sandbox: true,
+        termination_signal: 9,
+        ..Default::default()
+      },
+    );
+
+    assert!(shutdown.contains("time-result: \n"));
+
+    assert_eq!(
+      entries(&meta),
+      [
+        "memory-bytes",
+        "time-used",
+        "signal",
+        "exitcode",
+        "wall-time",
+        "user-time",
+        "sys-time",
+        "cpu-time",
+        "time-result",
+        "stdout-bytes",
+        "stderr-bytes",
+      ]
+    );
+
+    let result = ExecutionResult {
+      status: Status::InternalError,
+      status_message: "failed to create pipe:\nEMFILE".into(),
+      ..Default::default()
+    };
+
+    let meta = write(ResultFormat::DomJudge, &result);
+
+    assert!(meta.ends_with("internal-error: failed to create pipe: EMFILE\n"));
+    assert!(!meta.contains("exitcode"));
+  }
+
+  #[test]
+  fn json() {
+    assert_eq!(
+      write(ResultFormat::Json, &result()),
+      concat!(
+        r#"{"box_disk_usage_kb":0,"core_dumped":false,"cpu_time_after_warmup_ms":10,"#,
+        r#""exceeded_memory_limit":false,"exit_code":1,"#,
+        r#""finished_at":"2024-03-01T00:00:01.500Z","kill_reason":null,"killed_by_oom":false,"#,
+        r#""setup_time_ms":3.5,"started_at":"2024-03-01T00:00:00.000Z","status":"RE","#,
+        r#""status_message":"Exited with error status 1","stderr":"","#,
+        r#""stdout":"a \"quoted\"\tline\n","teardown_time_ms":0.25,"#,
+        r#""terminated_by_sandbox":false,"#,
+        r#""termination_signal":0,"usage":{"cgroup_memory_kb":0,"#,
+        r#""context_switches_forced":2,"context_switches_voluntary":3,"#,
+        r#""cpu_time_ms":12,"peak_memory_kb":1024,"system_time_ms":2,"user_time_ms":10},"#,
+        r#""wall_time_after_warmup_ms":null,"#,
+        r#""wall_time_ms":1500}"#,
+        "\n"
+      )
+    );
+  }
+
+  #[test]
+  fn json_without_exit_code() {
+    let json = write(ResultFormat::Json, &ExecutionResult::default());
+
+    assert!(json.contains(r#""exit_code":null,"#));
+    assert!(json.contains(r#""kill_reason":null,"#));
+  }
+
+  #[test]
+  fn json_with_kill_reason() {
+    let result = ExecutionResult {
+      kill_reason: Some(KillReason::WallTimeout),
+      terminated_by_sandbox: true,
+      ..Default::default()
+    };
+
+    let json = write(ResultFormat::Json, &result);
+
+    assert!(json.contains(r#""kill_reason":"wall_timeout","#));
+  }
+
+  #[test]
+  fn json_string_escapes_control_characters() {
+    assert_eq!(ResultFormat::json_string("\u{1}\\"), r#""\u0001\\""#);
+  }
+}
@@ -0,0 +1,78 @@
+use super::*;
+
+/// How `Sandbox::execute_with_retry` retries executions that fail with a
+/// retryable error, see `Error::is_retryable`.
+///
+/// The delay before each retry doubles, starting at `initial_backoff_ms`,
+/// until it reaches `max_backoff_ms`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+  /// Number of times an execution is tried in total, including the first
+  /// attempt.
+  ///
+  /// Defaults to 3.
+  pub attempts: u32,
+
+  /// Delay before the first retry in milliseconds.
+  ///
+  /// Defaults to 100 milliseconds.
+  pub initial_backoff_ms: f64,
+
+  /// Longest delay between two attempts in milliseconds.
+  ///
+  /// Defaults to 2 seconds.
+  pub max_backoff_ms: f64,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    Self {
+      attempts: 3,
+      initial_backoff_ms: 100.0,
+      max_backoff_ms: 2.0 * 1000.0,
+    }
+  }
+}
+
+impl RetryPolicy {
+  pub fn attempts(self, attempts: u32) -> Self {
+    Self { attempts, ..self }
+  }
+
+  /// The delay before the `retry`th retry, counting from zero.
+  pub(crate) fn backoff(&self, retry: u32) -> Duration {
+    let backoff_ms = self.initial_backoff_ms * 2f64.powi(retry.min(63) as i32);
+
+    Duration::from_secs_f64(backoff_ms.min(self.max_backoff_ms).max(0.0) / 1000.0)
+  }
+
+  pub fn initial_backoff_ms(self, initial_backoff_ms: f64) -> Self {
+    Self {
+      initial_backoff_ms,
+      ..self
+    }
+  }
+
+  pub fn max_backoff_ms(self, max_backoff_ms: f64) -> Self {
+    Self {
+      max_backoff_ms,
+      ..self
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn backoff() {
+    let policy = RetryPolicy::default();
+
+    assert_eq!(policy.backoff(0), Duration::from_millis(100));
+    assert_eq!(policy.backoff(1), Duration::from_millis(200));
+    assert_eq!(policy.backoff(4), Duration::from_millis(1600));
+    assert_eq!(policy.backoff(5), Duration::from_secs(2));
+    assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(2));
+  }
+}
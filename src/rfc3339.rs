@@ -0,0 +1,79 @@
+use super::*;
+
+/// Format `time` as an RFC 3339 timestamp in UTC with millisecond precision,
+/// e.g. `2024-02-29T23:59:59.250Z`.
+///
+/// Times before the Unix epoch are clamped to it.
+pub(crate) fn format(time: SystemTime) -> String {
+  let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+  let seconds = since_epoch.as_secs();
+
+  let (year, month, day) = civil_from_days(seconds / 86400);
+
+  format!(
+    "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+    seconds % 86400 / 3600,
+    seconds % 3600 / 60,
+    seconds % 60,
+    since_epoch.subsec_millis(),
+  )
+}
+
+#[cfg(feature = "serde")]
+pub(crate) fn serialize<S: serde::Serializer>(
+  time: &SystemTime,
+  serializer: S,
+) -> Result<S::Ok, S::Error> {
+  serializer.serialize_str(&format(*time))
+}
+
+/// The proleptic Gregorian date `days` days after 1970-01-01.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+  // Count from 0000-03-01, so leap days fall at the end of a year.
+  let days = days + 719_468;
+
+  let era = days / 146_097;
+  let day_of_era = days % 146_097;
+
+  let year_of_era =
+    (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+
+  let shifted_month = (5 * day_of_year + 2) / 153;
+
+  let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+
+  let month = if shifted_month < 10 {
+    shifted_month + 3
+  } else {
+    shifted_month - 9
+  };
+
+  let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+  (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn format() {
+    let at = |seconds: u64, millis: u64| {
+      super::format(UNIX_EPOCH + Duration::from_millis(seconds * 1000 + millis))
+    };
+
+    assert_eq!(at(0, 0), "1970-01-01T00:00:00.000Z");
+    assert_eq!(at(951_782_400, 0), "2000-02-29T00:00:00.000Z");
+    assert_eq!(at(1_709_251_199, 250), "2024-02-29T23:59:59.250Z");
+    assert_eq!(at(1_735_689_599, 999), "2024-12-31T23:59:59.999Z");
+
+    assert_eq!(
+      super::format(UNIX_EPOCH - Duration::from_secs(1)),
+      "1970-01-01T00:00:00.000Z"
+    );
+  }
+}
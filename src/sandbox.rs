@@ -1,5 +1,19 @@
 use super::*;
 
+/// Size of the stack the child runs on until it executes the program.
+const CHILD_STACK_SIZE: usize = 1024 * 1024;
+
+/// Number of control groups created by this process, to give each run a
+/// control group of its own, see `Sandbox::cgroup_name`.
+static CGROUPS: AtomicU64 = AtomicU64::new(0);
+
+/// The variable that tells the program which file descriptor to signal the
+/// end of its warmup on, see `ExecutionContext::measure_after_warmup`.
+const WARMUP_FD_VARIABLE: &str = "ISOLATE_WARMUP_FD";
+
+/// The capability needed to create the namespaces the program runs in.
+pub(crate) const CAP_SYS_ADMIN: u32 = 21;
+
 #[derive(Debug)]
 #[allow(unused)]
 pub struct Sandbox<'a> {
@@ -11,10 +25,18 @@ pub struct Sandbox<'a> {
   initialized: bool,
   /// Whether the sandbox was invoked by root.
   invoked_by_root: bool,
+  /// The lock on the sandbox, held from initialization until cleanup.
+  lock: Option<Flock<File>>,
   /// Original group id that invoked the sandbox.
   original_gid: Gid,
   /// Original user id that invoked the sandbox.
   original_uid: Uid,
+  /// The pid of the child running the program, or zero if there is none.
+  pid: AtomicI32,
+  /// Whether a program is being executed in the sandbox right now.
+  running: AtomicBool,
+  /// Whether the program being executed was killed with `Sandbox::kill`.
+  shutdown: AtomicBool,
   /// The system to interact with.
   system: &'a dyn System,
 }
@@ -30,13 +52,17 @@ impl<'a> TryFrom<(Config, &'a Environment)> for Sandbox<'a> {
 }
 
 impl<'a> Sandbox<'a> {
-  fn new(config: Config, environment: &'a Environment, system: &'a dyn System) -> Result<Self> {
+  fn new(mut config: Config, environment: &'a Environment, system: &'a dyn System) -> Result<Self> {
     ensure!(system.geteuid().is_root(), Error::NotRoot);
 
+    Self::check_capabilities(system)?;
+
     if system.getegid().as_raw() != 0 {
       system.setegid(0)?;
     }
 
+    environment.validate()?;
+
     ensure!(
       config.sandbox_id.unwrap_or(0) < environment.num_sandboxes,
       Error::Config(format!(
@@ -45,6 +71,16 @@ impl<'a> Sandbox<'a> {
       ))
     );
 
+    if let Some(cgroup) = &mut config.cgroup {
+      cgroup.validate()?;
+
+      if let Some(root) = &environment.cgroup_root {
+        if cgroup.root == CgroupRoot::default() {
+          cgroup.root = root.clone();
+        }
+      }
+    }
+
     let (uid, gid) = (system.getuid(), system.getgid());
 
     let (original_uid, original_gid) = match (config.as_uid, config.as_gid) {
@@ -65,16 +101,49 @@ impl<'a> Sandbox<'a> {
       environment,
       initialized: false,
       invoked_by_root: uid.is_root(),
+      lock: None,
       original_gid,
       original_uid,
+      pid: AtomicI32::new(0),
+      running: AtomicBool::new(false),
+      shutdown: AtomicBool::new(false),
       system,
     })
   }
 
+  /// Fail early when the namespaces the program runs in cannot be created.
+  ///
+  /// Inside a container, the effective user id can be 0 while the capability
+  /// to create namespaces is missing, e.g. in a user namespace or with the
+  /// default capability set of most container runtimes. Without this check,
+  /// every execution would fail halfway through its setup instead.
+  ///
+  /// If the capabilities cannot be read, the check is skipped.
+  fn check_capabilities(system: &dyn System) -> Result {
+    let Ok(capabilities) = system.effective_capabilities() else {
+      return Ok(());
+    };
+
+    if capabilities & (1 << CAP_SYS_ADMIN) != 0 {
+      return Ok(());
+    }
+
+    // The initial user namespace maps the whole range of ids onto itself.
+    let in_user_namespace = system
+      .uid_map()
+      .is_ok_and(|map| map.split_whitespace().collect::<Vec<_>>() != ["0", "0", "4294967295"]);
+
+    Err(Error::Permission(if in_user_namespace {
+      "running inside a user namespace without CAP_SYS_ADMIN; namespace setup will fail".into()
+    } else {
+      "running without CAP_SYS_ADMIN; namespace setup will fail".into()
+    }))
+  }
+
   /// Initialize the sandbox.
   ///
   /// This method should be called before executing any programs in the sandbox.
-  pub fn initialize(&self) -> Result {
+  pub fn initialize(&mut self) -> Result {
     if self.environment.restrict_initialization {
       ensure!(
         self.invoked_by_root,
@@ -82,323 +151,2559 @@ impl<'a> Sandbox<'a> {
       );
     }
 
-    if !self.environment.sandbox_root.exists() {
+    if let Some(cgroup) = &self.config.cgroup {
+      self.verify_cgroup_root(&cgroup.root.resolve_with(self.system)?)?;
+    }
+
+    if self.environment.create_root {
+      self.create_root(&self.environment.sandbox_root)?;
+    } else {
+      ensure!(
+        self
+          .system
+          .status(&self.environment.sandbox_root)?
+          .is_some(),
+        Error::Config("sandbox_root does not exist and create_root is false".into())
+      );
+    }
+
+    if let Some(size_mb) = self.environment.sandbox_root_tmpfs {
       self
         .system
-        .create_directory_with_mode(&self.environment.sandbox_root, 0o700)?;
+        .mount_tmpfs(&self.environment.sandbox_root, size_mb)?;
     }
 
-    for ancestor in self.environment.sandbox_root.ancestors() {
-      let metadata = fs::metadata(ancestor)?;
+    self.verify_root(&self.environment.sandbox_root)?;
 
-      ensure!(
-        metadata.permissions().mode() & 0o022 == 0,
-        Error::Permission(format!(
-          "directory {} must be writable only by root",
-          ancestor
-        ))
-      );
+    self.create_root(&self.environment.lock_root)?;
 
-      ensure!(
-        metadata.is_dir(),
-        Error::Permission(format!("{} must be a directory", ancestor))
-      );
+    self.verify_root(&self.environment.lock_root)?;
+
+    if self.lock.is_none() {
+      self.lock = Some(self.lock()?);
     }
 
+    // Anyone the box is accessible to has to be able to traverse the
+    // sandbox directory to reach it.
     self
       .system
-      .recreate_directory_with_mode(&self.directory(), 0o700)?;
+      .recreate_directory_with_mode(&self.directory(), 0o700 | self.environment.box_mode & 0o011)?;
 
     let sandbox = self.directory().join("box");
 
-    self.system.create_directory_with_mode(&sandbox, 0o700)?;
+    self
+      .system
+      .create_directory_with_mode(&sandbox, self.environment.box_mode)?;
 
+    // The program runs as the sandbox user and has to be able to write to
+    // its own working directory.
     self
       .system
-      .chown(&sandbox, Some(self.original_uid), Some(self.original_gid))?;
+      .chown(&sandbox, Some(self.uid()), Some(self.gid()))?;
+
+    self.initialized = true;
 
     Ok(())
   }
 
-  /// Execute a program in the sandbox.
-  pub fn execute(&self, _ctx: ExecutionContext) -> Result<ExecutionResult> {
-    ensure!(self.initialized, Error::NotInitialized);
+  /// Create `root` with mode 0700 if it does not exist yet.
+  fn create_root(&self, root: &Utf8Path) -> Result {
+    if self.system.status(root)?.is_none() {
+      self.system.create_directory_with_mode(root, 0o700)?;
+    }
 
-    todo!("Execute a specified program in the sandbox");
+    Ok(())
   }
 
-  /// Clean up the sandbox.
-  pub fn cleanup(&mut self) -> Result {
-    ensure!(self.initialized, Error::NotInitialized);
+  /// Make sure that the control group root is on a control group filesystem,
+  /// rather than failing on the first write to a controller file.
+  fn verify_cgroup_root(&self, root: &Utf8Path) -> Result {
+    let filesystem = self.system.filesystem_type(root)?;
+
+    ensure!(
+      filesystem == CGROUP2_SUPER_MAGIC || filesystem == CGROUP_SUPER_MAGIC,
+      Error::Config(format!(
+        "cgroup root `{}` is not on a cgroup filesystem",
+        root
+      ))
+    );
 
-    todo!("Clean up the sandbox");
+    Ok(())
   }
 
-  /// Get the id of the sandbox.
-  pub fn id(&self) -> u32 {
-    self.config.sandbox_id.unwrap_or(0)
+  /// Lock the sandbox, so that no other instance can use it at the same
+  /// time.
+  ///
+  /// Fails with `Error::Busy` if the sandbox is locked already, unless
+  /// `Config::wait` is set, in which case this waits for the lock instead.
+  fn lock(&self) -> Result<Flock<File>> {
+    let lock_path = self.lock_path();
+
+    let file = self.system.open_lock(&lock_path)?;
+
+    let operation = if self.config.wait {
+      FlockArg::LockExclusive
+    } else {
+      FlockArg::LockExclusiveNonblock
+    };
+
+    match Flock::lock(file, operation) {
+      Ok(lock) => Ok(lock),
+      Err((_, Errno::EWOULDBLOCK)) => Err(Error::Busy(self.id())),
+      Err((_, error)) => syscall(&format!("lock `{}`", lock_path), Err(error)),
+    }
   }
 
-  /// Get the group id of the sandbox.
-  pub fn gid(&self) -> Gid {
-    Gid::from_raw(self.environment.first_sandbox_gid + self.id())
+  /// Make sure that `root` and all of its ancestors are directories writable
+  /// only by root, so that nobody else can swap them for a symlink.
+  fn verify_root(&self, root: &Utf8Path) -> Result {
+    for ancestor in root.ancestors() {
+      let status = self.system.status(ancestor)?.ok_or_else(|| {
+        io::Error::new(
+          io::ErrorKind::NotFound,
+          format!("`{}` does not exist", ancestor),
+        )
+      })?;
+
+      ensure!(
+        status.mode & 0o022 == 0,
+        Error::Permission(format!(
+          "directory {} must be writable only by root",
+          ancestor
+        ))
+      );
+
+      ensure!(
+        status.directory,
+        Error::Permission(format!("{} must be a directory", ancestor))
+      );
+    }
+
+    Ok(())
   }
 
-  /// Get the user id of the sandbox.
-  pub fn uid(&self) -> Uid {
-    Uid::from_raw(self.environment.first_sandbox_uid + self.id())
+  /// Execute a program in the sandbox.
+  ///
+  /// Only failures that keep the program from being started at all, e.g. an
+  /// invalid context or a child that could not be created, are returned as
+  /// an error. Everything after that is reported as a result with
+  /// `Status::InternalError` and a description in `status_message`: failures
+  /// inside the child before the program is executed (e.g. a resource limit
+  /// that could not be applied), as well as failures to collect part of the
+  /// result after the program ran (e.g. its output or its exit status), in
+  /// which case the rest of the result is still filled in as far as it was
+  /// collected.
+  ///
+  /// Unless `ExecutionContext::silent` is set, the outcome is also reported
+  /// on stderr, e.g. `OK (0.012 sec real, 0.015 sec wall)`.
+  ///
+  /// A sandbox runs one program at a time, so while one is being executed,
+  /// executing another in the same sandbox fails with `Error::Locked`.
+  /// Running programs in parallel takes one sandbox each.
+  pub fn execute(&self, context: ExecutionContext) -> Result<ExecutionResult> {
+    ensure!(self.initialized, Error::NotInitialized);
+
+    ensure!(
+      self
+        .running
+        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_ok(),
+      Error::Locked(self.id())
+    );
+
+    let record = self
+      .environment
+      .audit_log
+      .is_some()
+      .then(|| AuditRecord::new(self.id(), self.system.getuid(), &context));
+
+    let result = self.run(context);
+
+    self.running.store(false, Ordering::Release);
+
+    if let Some(record) = record {
+      self.audit(&record.finish(&result));
+    }
+
+    result
   }
 
-  /// Get the directory of the sandbox.
-  pub fn directory(&self) -> Utf8PathBuf {
-    self.environment.sandbox_root.join(self.id().to_string())
+  /// Execute the program of `context` once for each of `inputs`, as its
+  /// standard input, like a grader running a solution on every test case.
+  ///
+  /// Every run is a run of its own, with a control group of its own and
+  /// output files that are truncated again, unless they are appended to.
+  /// The box however is reused as it is, so whatever a run writes to it is
+  /// seen by the runs after it, unless
+  /// `ExecutionContext::reset_box_between_runs` is set.
+  ///
+  /// Returns the results in the order of `inputs`, or the first error, in
+  /// which case the remaining inputs are not run.
+  pub fn execute_batch(
+    &self,
+    context: &ExecutionContext,
+    inputs: impl IntoIterator<Item = Stdin>,
+  ) -> Result<Vec<ExecutionResult>> {
+    ensure!(self.initialized, Error::NotInitialized);
+
+    self.run_batch(context, inputs, |context| self.execute(context))
   }
-}
 
-#[cfg(test)]
-mod tests {
-  use {
-    super::*,
-    assert_matches::assert_matches,
-    config::Config,
-    nix::{
-      sys::stat::Mode,
-      unistd::{Gid, Uid},
-    },
-    std::cell::RefCell,
-  };
+  /// Run every input with `run`, resetting the box between runs if
+  /// `ExecutionContext::reset_box_between_runs` is set, see
+  /// `Sandbox::execute_batch`.
+  fn run_batch(
+    &self,
+    context: &ExecutionContext,
+    inputs: impl IntoIterator<Item = Stdin>,
+    mut run: impl FnMut(ExecutionContext) -> Result<ExecutionResult>,
+  ) -> Result<Vec<ExecutionResult>> {
+    let sandbox = self.directory().join("box");
+
+    let snapshot = self.directory().join("snapshot");
 
-  #[derive(Debug)]
-  struct MockSystem {
-    egid: Gid,
-    euid: Uid,
-    gid: Gid,
-    uid: Uid,
-    umask: RefCell<Option<Mode>>,
+    if context.reset_box_between_runs {
+      self.system.recreate_directory_with_mode(&snapshot, 0o700)?;
+      self.system.copy_tree(&sandbox, &snapshot)?;
+    }
+
+    let results = inputs
+      .into_iter()
+      .enumerate()
+      .map(|(index, input)| {
+        if context.reset_box_between_runs && index > 0 {
+          self
+            .system
+            .recreate_directory_with_mode(&sandbox, self.environment.box_mode)?;
+          self.system.copy_tree(&snapshot, &sandbox)?;
+        }
+
+        run(context.clone().stdin(input))
+      })
+      .collect();
+
+    if context.reset_box_between_runs {
+      self.system.remove_directory(&snapshot)?;
+    }
+
+    results
   }
 
-  impl Default for MockSystem {
-    fn default() -> Self {
-      Self {
-        egid: Gid::from_raw(0),
-        euid: Uid::from_raw(0),
-        gid: Gid::from_raw(0),
-        uid: Uid::from_raw(0),
-        umask: RefCell::new(None),
+  /// Execute a program like `Sandbox::execute`, but try again after a delay
+  /// whenever it fails with a retryable error, e.g. because the system was
+  /// temporarily out of processes, as long as `policy` allows.
+  ///
+  /// Returns the result of the first attempt that did not fail with a
+  /// retryable error, or the error of the last attempt. Only errors are
+  /// retried, results with `Status::InternalError` are returned as they are.
+  pub fn execute_with_retry(
+    &self,
+    context: ExecutionContext,
+    policy: &RetryPolicy,
+  ) -> Result<ExecutionResult> {
+    let mut retry = 0;
+
+    loop {
+      match self.execute(context.clone()) {
+        Err(error) if error.is_retryable() && retry + 1 < policy.attempts => {
+          thread::sleep(policy.backoff(retry));
+          retry += 1;
+        }
+        result => return result,
       }
     }
   }
 
-  impl System for MockSystem {
-    fn chown(&self, _path: &Utf8Path, _uid: Option<Uid>, _gid: Option<Gid>) -> Result {
-      Ok(())
+  /// With `Config::verbose`, describe the user and group the program runs
+  /// as, its limits and mounts, and its arguments and environment.
+  fn dump(&self, context: &ExecutionContext) -> Result {
+    if !self.config.verbose {
+      return Ok(());
     }
 
-    fn create_directory_with_mode(&self, _path: &Utf8Path, _mode: u32) -> Result {
-      Ok(())
-    }
+    let log = |message: String| self.report(context, &message);
 
-    fn getegid(&self) -> Gid {
-      self.egid
+    log(format!(
+      "Running as uid {} and gid {}",
+      self.uid(),
+      self.gid()
+    ));
+
+    for limit in context.limits.resource_limits(self.config.cgroup_enabled()) {
+      log(format!("Limit {limit}"));
     }
 
-    fn geteuid(&self) -> Uid {
-      self.euid
+    if let Some(memory_limit_kb) = context
+      .limits
+      .memory_limit_kb
+      .filter(|_| self.config.cgroup_enabled())
+    {
+      log(format!(
+        "Ignoring memory limit of {memory_limit_kb} KB, the control group limits memory instead"
+      ));
     }
 
-    fn getgid(&self) -> Gid {
-      self.gid
+    for (name, limit) in [
+      ("time", context.limits.time_limit_ms),
+      ("extra time", context.limits.extra_time_ms),
+      ("wall time", context.limits.wall_time_limit_ms),
+    ] {
+      if let Some(limit) = limit {
+        log(format!("Limit {name}: {limit} ms"));
+      }
     }
 
-    fn getuid(&self) -> Uid {
-      self.uid
+    let mounts = context.effective_mounts();
+
+    for mount in ExecutionContext::normalize(&mounts)? {
+      log(format!("Mount {mount}"));
     }
 
-    fn recreate_directory_with_mode(&self, _path: &Utf8Path, _mode: u32) -> Result {
-      Ok(())
+    log(format!(
+      "Executing {}",
+      Some(context.program.as_str())
+        .into_iter()
+        .chain(context.arguments.iter().flatten().map(AsRef::as_ref))
+        .map(|argument| format!("{argument:?}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+    ));
+
+    for (key, value) in context.environment() {
+      log(format!("Environment {key}={value}"));
     }
 
-    fn setegid(&self, _gid: u32) -> Result {
-      Ok(())
+    Ok(())
+  }
+
+  /// Append `record` to `Environment::audit_log`, reporting rather than
+  /// returning a failure, so that it does not fail the execution.
+  fn audit(&self, record: &AuditRecord) {
+    let Some(path) = &self.environment.audit_log else {
+      return;
+    };
+
+    if let Err(error) = self.system.append(path, &record.to_json_line()) {
+      self
+        .system
+        .log(&format!("failed to write audit log `{path}`: {error}"));
     }
+  }
 
-    fn umask(&self, mask: Mode) -> Mode {
-      *self.umask.borrow_mut() = Some(mask);
-      Mode::from_bits_truncate(0)
+  /// Write a status message to stderr, unless `ExecutionContext::silent` is
+  /// set.
+  ///
+  /// Only for messages the caller can do without, fatal errors are returned
+  /// instead.
+  fn report(&self, context: &ExecutionContext, message: &str) {
+    if !context.silent {
+      self.system.log(message);
     }
   }
 
-  #[test]
-  fn sandbox_construction_without_root_euid() {
-    let environment = Environment::default();
+  /// Execute a program in the sandbox, see `Sandbox::execute`.
+  fn run(&self, context: ExecutionContext) -> Result<ExecutionResult> {
+    let entered = Instant::now();
+
+    context.validate()?;
+
+    self.validate_redirects(&context)?;
+
+    let programs = context
+      .program_paths()
+      .into_iter()
+      .map(CString::new)
+      .collect::<Result<Vec<_>, _>>()
+      .map_err(|_| Error::Config("program must not contain a nul byte".into()))?;
+
+    let arguments = Some(context.program.as_str())
+      .into_iter()
+      .chain(context.arguments.iter().flatten().map(AsRef::as_ref))
+      .map(CString::new)
+      .collect::<Result<Vec<_>, _>>()
+      .map_err(|_| Error::Config("arguments must not contain a nul byte".into()))?;
+
+    let prelude = context
+      .prelude
+      .as_ref()
+      .map(|prelude| {
+        prelude
+          .iter()
+          .map(|argument| CString::new(argument.as_str()))
+          .collect::<Result<Vec<_>, _>>()
+          .map_err(|_| Error::Config("prelude must not contain a nul byte".into()))
+      })
+      .transpose()?;
+
+    let warmup = context
+      .measure_after_warmup
+      .then(|| syscall("create warmup pipe", pipe2(OFlag::O_CLOEXEC)))
+      .transpose()?;
+
+    let environment = context
+      .environment()
+      .into_iter()
+      .filter(|(key, _)| warmup.is_none() || key != WARMUP_FD_VARIABLE)
+      .chain(
+        warmup
+          .as_ref()
+          .map(|(_, writer)| (WARMUP_FD_VARIABLE.into(), writer.as_raw_fd().to_string())),
+      )
+      .map(|(key, value)| CString::new(format!("{key}={value}")))
+      .collect::<Result<Vec<_>, _>>()
+      .map_err(|_| Error::Config("variables must not contain a nul byte".into()))?;
+
+    self.dump(&context)?;
+
+    let (reader, writer) = syscall("create pipe", pipe2(OFlag::O_CLOEXEC))?;
+
+    let watchdog = Watchdog::new(&context);
+
+    let capture = context
+      .capture_output
+      .then(|| Capture::new(&context))
+      .transpose()?;
+
+    let cgroup = self
+      .config
+      .cgroup
+      .as_ref()
+      .map(|cgroup| self.prepare_cgroup(cgroup))
+      .transpose()?;
+
+    // The counters of a control group that is not managed carry over from
+    // earlier runs, so only their increase counts.
+    let baseline = cgroup
+      .as_deref()
+      .map(|cgroup| CgroupStats::read(self.system, cgroup))
+      .transpose()?;
+
+    let mut namespaces = CloneFlags::CLONE_NEWIPC
+      | CloneFlags::CLONE_NEWNS
+      | CloneFlags::CLONE_NEWPID
+      | CloneFlags::CLONE_NEWUTS;
+
+    if !context.share_net {
+      namespaces |= CloneFlags::CLONE_NEWNET;
+    }
 
-    let config = Config::default();
+    let sandbox = self.directory().join("box");
 
-    let mock = MockSystem {
-      euid: Uid::from_raw(1000),
-      ..Default::default()
-    };
+    // The previous run may have handed the box over to the original user.
+    if self.config.chown_output_to_invoker {
+      self.chown_tree(&sandbox, self.uid(), self.gid())?;
+    }
 
-    let result = Sandbox::new(config, &environment, &mock);
+    if context.synthetic_passwd {
+      self.write_synthetic_passwd(&context)?;
+    }
 
-    assert!(matches!(result, Err(Error::NotRoot)));
-  }
+    let initial_sizes = Self::file_sizes(&sandbox)?;
+
+    // Restored when dropped, see below.
+    let terminal = context.tty_hack.then(Terminal::acquire).transpose()?;
+
+    let mut stack = vec![0; CHILD_STACK_SIZE];
+
+    let started_at = SystemTime::now();
+
+    // SAFETY: The child gets its own copy of the address space and only runs
+    // the setup sequence below before it either replaces itself with the
+    // program or exits.
+    let child = syscall("create child", unsafe {
+      clone(
+        Box::new(|| {
+          let error = self.setup_child(
+            &context,
+            cgroup.as_deref(),
+            capture.as_ref(),
+            terminal.as_ref(),
+            warmup.as_ref().map(|(_, writer)| writer.as_raw_fd()),
+            prelude.as_deref(),
+            &programs,
+            &arguments,
+            &environment,
+          );
+          // The error crosses the pipe as text, so the cause of a control
+          // group error, which is not part of its message, is appended.
+          let message = match &error {
+            Error::Cgroup(_, source) => format!("{error}: {source}"),
+            _ => error.to_string(),
+          };
+          let _ = write(&writer, message.as_bytes());
+          1
+        }),
+        &mut stack,
+        namespaces,
+        Some(Signal::SIGCHLD as i32),
+      )
+    })?;
+
+    let child = Child::new(child)?;
+
+    self.shutdown.store(false, Ordering::Release);
+    self.pid.store(child.pid().as_raw(), Ordering::Release);
+
+    drop(writer);
+
+    let warmup = warmup.map(|(reader, _)| reader);
+
+    type Waited = (
+      String,
+      Option<KillReason>,
+      Option<Split>,
+      WaitStatus,
+      ResourceUsage,
+      Vec<TracedSyscall>,
+    );
 
-  #[test]
-  fn sandbox_construction_as_uid_as_gid_non_root_original() {
-    let environment = Environment::default();
+    // When the pipe is closed, the child either executed the program or
+    // failed to set it up.
+    let executed = Cell::new(None);
 
-    let config = Config {
-      as_uid: Some(2000),
-      as_gid: Some(2000),
-      ..Default::default()
-    };
+    // Dropped once the setup is over, which stops the setup timer.
+    let (setup_over, setup_running) = mpsc::channel::<()>();
 
-    let mock = MockSystem {
-      uid: Uid::from_raw(1000),
-      ..Default::default()
-    };
+    let setup_timed_out = AtomicBool::new(false);
 
-    let result = Sandbox::new(config, &environment, &mock);
+    let wait = || -> Result<Waited> {
+      let mut setup_error = String::new();
 
-    assert_matches!(
-      result,
-      Err(Error::Permission(message)) if message.contains("you must be root to use `as_uid` or `as_gid`")
-    );
-  }
+      File::from(reader).read_to_string(&mut setup_error)?;
 
-  #[test]
-  fn sandbox_construction_as_uid_without_as_gid() {
-    let (mock, environment) = (MockSystem::default(), Environment::default());
+      drop(setup_over);
 
-    let config = Config {
-      as_uid: Some(2000),
-      ..Default::default()
-    };
+      // The limits only apply to the program, not to its setup.
+      let executed_at = Instant::now();
 
-    let result = Sandbox::new(config, &environment, &mock);
+      executed.set(Some(executed_at));
 
-    assert_matches!(
-      result,
-      Err(Error::Config(message)) if message.contains("`as_uid` and `as_gid` must be used either both or none")
-    );
-  }
+      if !setup_error.is_empty() {
+        let (status, usage) = child.wait(self.system)?;
+        return Ok((setup_error, None, None, status, usage, Vec::new()));
+      }
 
-  #[test]
-  fn sandbox_construction_valid_no_as() {
-    let (mock, environment) = (MockSystem::default(), Environment::default());
+      // The child stops on every system call until this thread, which
+      // created it, resumes it, so the watchdog runs on its own thread.
+      #[cfg(feature = "trace")]
+      if context.trace_syscalls {
+        let (timeout, traced) = thread::scope(|scope| {
+          let timeout = scope.spawn(|| {
+            watchdog.watch(
+              self.system,
+              &child,
+              executed_at,
+              warmup.as_ref().map(AsFd::as_fd),
+            )
+          });
 
-    let config = Config::default();
+          let traced = child.trace(self.system);
 
-    let sandbox =
-      Sandbox::new(config, &environment, &mock).expect("Sandbox creation should succeed");
+          if traced.is_err() {
+            let _ = child.kill();
+          }
 
-    // With no as_uid/as_gid, the sandbox takes the original uid/gid from the system.
-    assert_eq!(sandbox.original_gid, 0.into());
-    assert_eq!(sandbox.original_uid, 0.into());
+          (timeout.join(), traced)
+        });
 
-    assert_eq!(
-      mock.umask.borrow().unwrap(),
-      Mode::from_bits_truncate(0o022)
-    );
-  }
+        let (status, usage, syscalls) = traced?;
 
-  #[test]
-  fn sandbox_construction_valid_with_as() {
-    let (mock, environment) = (MockSystem::default(), Environment::default());
+        let (timeout, split) =
+          timeout.map_err(|_| Error::Internal("watchdog panicked".into()))??;
 
-    let config = Config {
-      as_uid: Some(2000),
-      as_gid: Some(2000),
-      ..Default::default()
-    };
+        return Ok((setup_error, timeout, split, status, usage, syscalls));
+      }
 
-    let sandbox =
-      Sandbox::new(config, &environment, &mock).expect("Sandbox creation should succeed");
+      let (timeout, split) = watchdog.watch(
+        self.system,
+        &child,
+        executed_at,
+        warmup.as_ref().map(AsFd::as_fd),
+      )?;
 
-    // When as_uid/as_gid are provided and allowed, the sandbox's id are set to those values.
-    assert_eq!(sandbox.original_uid, 2000.into());
-    assert_eq!(sandbox.original_gid, 2000.into());
-  }
+      let (status, usage) = child.wait(self.system)?;
 
-  #[test]
-  fn sandbox_construction_credentials_setup() {
-    let environment = Environment {
-      sandbox_root: Utf8PathBuf::from("/tmp/isolate_test"),
-      first_sandbox_uid: 10000,
-      first_sandbox_gid: 20000,
-      num_sandboxes: 10,
-      ..Default::default()
+      Ok((setup_error, timeout, split, status, usage, Vec::new()))
     };
 
-    let config = Config {
-      sandbox_id: Some(5),
-      ..Default::default()
-    };
+    // Killing the child closes its end of the pipe, as well as the ones of
+    // the output it would have sent back, so the run comes to an end.
+    let (waited, output) = thread::scope(|scope| {
+      if let Some(timeout) = self.config.setup_timeout_ms {
+        let (child, setup_timed_out) = (&child, &setup_timed_out);
+
+        scope.spawn(move || {
+          if let Err(RecvTimeoutError::Timeout) =
+            setup_running.recv_timeout(Duration::from_millis(timeout))
+          {
+            setup_timed_out.store(true, Ordering::Relaxed);
+            let _ = child.kill();
+          }
+        });
+      }
 
-    let mock = MockSystem::default();
+      match capture {
+        Some(capture) => capture.collect(wait),
+        None => (wait(), Ok(Output::default())),
+      }
+    });
 
-    let sandbox =
-      Sandbox::new(config, &environment, &mock).expect("Sandbox creation should succeed");
+    let reaped = Instant::now();
 
-    assert_eq!(
-      sandbox.directory(),
-      Utf8PathBuf::from("/tmp/isolate_test").join("5")
-    );
+    self.pid.store(0, Ordering::Release);
 
-    assert_eq!(sandbox.gid(), (20000 + 5).into());
-    assert_eq!(sandbox.id(), 5);
-    assert_eq!(sandbox.uid(), (10000 + 5).into());
-  }
+    let shut_down = self.shutdown.swap(false, Ordering::AcqRel);
 
-  #[test]
-  fn sandbox_construction_id_out_of_range() {
-    let environment = Environment {
-      sandbox_root: Utf8PathBuf::from("/tmp/isolate_test"),
-      first_sandbox_gid: 20000,
-      first_sandbox_uid: 10000,
-      num_sandboxes: 10, // Valid box id's are between 0 and 9 (inclusive).
+    drop(terminal);
+
+    let cgroup_stats = cgroup
+      .as_deref()
+      .zip(baseline)
+      .map(|(cgroup, baseline)| {
+        CgroupStats::read(self.system, cgroup).map(|stats| stats.since(baseline))
+      })
+      .transpose();
+
+    // Removing the control group fails if the kernel has not finished
+    // tearing down the program yet, in which case `Sandbox::cleanup` removes
+    // it.
+    if let Some(cgroup) = cgroup.as_ref().filter(|_| {
+      self
+        .config
+        .cgroup
+        .as_ref()
+        .is_some_and(|cgroup| cgroup.manage)
+    }) {
+      let _ = self.system.remove_empty_directory(cgroup);
+    }
+
+    let mut result = ExecutionResult {
+      finished_at: SystemTime::now(),
+      setup_time_ms: executed
+        .get()
+        .map_or(0.0, |executed| (executed - entered).as_secs_f64() * 1000.0),
+      started_at,
+      wall_time_ms: executed
+        .get()
+        .map_or(0.0, |executed| (reaped - executed).as_secs_f64() * 1000.0),
       ..Default::default()
     };
 
-    let config = Config {
-      sandbox_id: Some(10),
-      ..Default::default()
+    let swept = if context.special_files {
+      Ok(())
+    } else {
+      Self::remove_special_files(&sandbox)
     };
 
-    let mock = MockSystem::default();
+    let measured = Self::file_sizes(&sandbox).map(|sizes| {
+      let written = sizes
+        .iter()
+        .map(|(path, size)| size.saturating_sub(initial_sizes.get(path).copied().unwrap_or(0)))
+        .sum::<u64>();
 
-    let result = Sandbox::new(config, &environment, &mock);
+      result.box_disk_usage_kb = u32::try_from(written.div_ceil(1024)).unwrap_or(u32::MAX);
+    });
 
-    assert_matches!(
-      result,
-      Err(Error::Config(message)) if message.contains("sandbox id out of range")
-    );
-  }
+    let chowned = if self.config.chown_output_to_invoker {
+      self.chown_tree(&sandbox, self.original_uid, self.original_gid)
+    } else {
+      Ok(())
+    };
 
-  #[test]
-  fn sandbox_initialization_restricted_by_environment() {
-    let environment = Environment {
+    let collected = output
+      .map(|output| {
+        result.stderr = output.stderr;
+        result.stdout = output.stdout;
+      })
+      .and(
+        waited.map(|(setup_error, timeout, split, status, usage, syscalls)| {
+          result.syscalls = syscalls;
+          result.usage = usage;
+
+          let limit_reached = matches!(&cgroup_stats, Ok(Some(stats)) if stats.limit_hits > 0);
+
+          if let Ok(Some(stats)) = &cgroup_stats {
+            result.killed_by_oom = stats.oom_kills > 0;
+            result.usage.cgroup_memory_kb = stats.peak_memory_kb;
+          }
+
+          // The program may have exited by itself before `Sandbox::kill`
+          // reached it.
+          let killed = matches!(status, WaitStatus::Signaled(_, Signal::SIGKILL, _));
+
+          Self::conclude(
+            &mut result,
+            &context,
+            self.config.cgroup.as_ref(),
+            limit_reached,
+            setup_error,
+            timeout.or((shut_down && killed).then_some(KillReason::Shutdown)),
+            status,
+          );
+
+          if let Some(split) = split {
+            result.cpu_time_after_warmup_ms =
+              Some((result.usage.cpu_time_ms - split.cpu_time.as_secs_f64() * 1000.0).max(0.0));
+            result.wall_time_after_warmup_ms =
+              Some((result.wall_time_ms - split.elapsed.as_secs_f64() * 1000.0).max(0.0));
+          }
+        }),
+      )
+      .and(cgroup_stats.map(drop))
+      .and(swept)
+      .and(measured)
+      .and(chowned);
+
+    if let Err(error) = collected {
+      result.status = Status::InternalError;
+      result.status_message = format!("failed to collect result: {}", error);
+    }
+
+    // The program never ran, but the box is torn down and the run reported
+    // like any other before failing.
+    let setup_timed_out = setup_timed_out.load(Ordering::Relaxed);
+
+    if setup_timed_out {
+      result.status = Status::InternalError;
+      result.status_message = "sandbox setup timed out".into();
+    }
+
+    result.teardown_time_ms = reaped.elapsed().as_secs_f64() * 1000.0;
+
+    self.report(&context, &result.summary());
+
+    ensure!(!setup_timed_out, Error::Timeout(result.status_message));
+
+    Ok(result)
+  }
+
+  /// Fill in the status of `result` from the outcome of the run, in the
+  /// control group configured by `cgroup`, if any, which reached its memory
+  /// limit if `limit_reached` is set.
+  fn conclude(
+    result: &mut ExecutionResult,
+    context: &ExecutionContext,
+    cgroup: Option<&CgroupConfig>,
+    limit_reached: bool,
+    setup_error: String,
+    kill_reason: Option<KillReason>,
+    status: WaitStatus,
+  ) {
+    if !setup_error.is_empty() {
+      result.status = Status::InternalError;
+      result.status_message = setup_error;
+      return;
+    }
+
+    let (classified, exit_code, signal, core_dumped) = Status::classify(
+      status,
+      matches!(
+        kill_reason,
+        Some(KillReason::CpuTimeout | KillReason::WallTimeout)
+      ),
+    );
+
+    // The OOM killer may only have killed one of the program's children, in
+    // which case the program itself was not killed for its memory.
+    let kill_reason = kill_reason.or(
+      (result.killed_by_oom && matches!(status, WaitStatus::Signaled(_, Signal::SIGKILL, _)))
+        .then_some(KillReason::Memory),
+    );
+
+    result.core_dumped = core_dumped;
+    result.exit_code = exit_code;
+    result.kill_reason = kill_reason;
+    result.terminated_by_sandbox = kill_reason.is_some();
+    result.termination_signal = signal.unwrap_or(0);
+
+    result.status_message = match (kill_reason, &classified) {
+      (Some(kill_reason), _) => kill_reason.message().into(),
+      (None, Status::RuntimeError) => {
+        format!("Exited with error status {}", exit_code.unwrap_or(0))
+      }
+      (None, Status::SignalError) => format!("Caught fatal signal {}", signal.unwrap_or(0)),
+      (None, Status::InternalError) => format!("unexpected wait status: {:?}", status),
+      _ => String::new(),
+    };
+
+    result.status = kill_reason.map_or(classified, KillReason::status);
+
+    result.detect_memory_limit(context, cgroup, limit_reached);
+  }
+
+  /// Prepare the child and replace it with the program.
+  ///
+  /// Only returns if something went wrong, in which case the error is
+  /// reported back to the parent.
+  #[allow(clippy::too_many_arguments)]
+  fn setup_child(
+    &self,
+    context: &ExecutionContext,
+    cgroup: Option<&Utf8Path>,
+    capture: Option<&Capture>,
+    terminal: Option<&Terminal>,
+    warmup: Option<RawFd>,
+    prelude: Option<&[CString]>,
+    programs: &[CString],
+    arguments: &[CString],
+    environment: &[CString],
+  ) -> Error {
+    let setup = || -> Result {
+      // Everything the program does, including the setup below, counts
+      // towards the limits of the control group.
+      if let Some(cgroup) = cgroup {
+        let procs = cgroup.join("cgroup.procs");
+
+        self
+          .system
+          .write_file(&procs, "0")
+          .map_err(|error| Error::Cgroup(format!("failed to write `{procs}`"), error))?;
+      }
+
+      // The sandbox's root need not contain `/dev/null`, so it is opened
+      // before switching to it.
+      let null = Self::open(Utf8Path::new("/dev/null"), OFlag::O_RDWR | OFlag::O_CLOEXEC)?;
+
+      self.setup_root(context)?;
+
+      if let Some(hostname) = &context.hostname {
+        syscall("set hostname", sethostname(hostname))?;
+      }
+
+      let working_directory = context.effective_working_directory();
+
+      self.system.chdir(working_directory).map_err(|error| {
+        Error::Config(format!(
+          "failed to change directory to `{}`: {}",
+          working_directory, error
+        ))
+      })?;
+
+      if let Some(prelude) = prelude {
+        self.run_prelude(context, prelude, environment, null)?;
+      }
+
+      let limits = context.limits.resource_limits(self.config.cgroup_enabled());
+
+      for limit in &limits {
+        limit.apply()?;
+      }
+
+      for limit in &limits {
+        limit.verify()?;
+      }
+
+      let persona = syscall("read personality", personality::get())?;
+
+      if context.persona(persona) != persona {
+        syscall(
+          "set personality",
+          personality::set(context.persona(persona)),
+        )?;
+      }
+
+      self.drop_privileges(context)?;
+
+      if let Some(terminal) = terminal {
+        terminal.hand_over()?;
+      }
+
+      Self::redirect_standard_streams(context, capture, null)?;
+
+      if !context.inherit_fds {
+        Self::close_inherited_fds()?;
+      }
+
+      if let Some(warmup) = warmup {
+        syscall(
+          "pass warmup pipe",
+          fcntl(warmup, FcntlArg::F_SETFD(FdFlag::empty())),
+        )?;
+      }
+
+      Self::reset_signals()?;
+
+      #[cfg(feature = "trace")]
+      if context.trace_syscalls {
+        syscall("start tracing", ptrace::traceme())?;
+      }
+
+      Ok(())
+    };
+
+    if let Err(error) = setup() {
+      return error;
+    }
+
+    // Like `execvp(3)`, keep looking after a program that does not exist or
+    // cannot be executed, but report the latter over the former.
+    let mut error = Errno::ENOENT;
+
+    for program in programs {
+      match execve(program, arguments, environment) {
+        Ok(infallible) => match infallible {},
+        Err(Errno::EACCES) => error = Errno::EACCES,
+        Err(errno @ (Errno::ENOENT | Errno::ENOTDIR)) if error != Errno::EACCES => error = errno,
+        Err(Errno::ENOENT | Errno::ENOTDIR) => {}
+        Err(errno) => {
+          error = errno;
+          break;
+        }
+      }
+    }
+
+    // The kernel also fails with `ENOENT` when the interpreter of a program
+    // that exists does not, e.g. that of a script whose shebang names a
+    // directory which is not mounted, which is easily mistaken for the
+    // program itself missing.
+    if matches!(error, Errno::ENOENT | Errno::ENOEXEC)
+      && programs.iter().any(|program| {
+        stat(program.as_c_str()).is_ok_and(|status| status.st_mode & libc::S_IFMT == libc::S_IFREG)
+      })
+    {
+      return Error::Config(format!(
+        "failed to execute `{}`: interpreter not found in sandbox (check shebang)",
+        context.program
+      ));
+    }
+
+    syscall::<()>(&format!("execute `{}`", context.program), Err(error)).unwrap_err()
+  }
+
+  /// The user and group the program runs as.
+  fn credentials(&self, context: &ExecutionContext) -> (Uid, Gid) {
+    if context.run_as_invoking_user {
+      (self.original_uid, self.original_gid)
+    } else {
+      (self.uid(), self.gid())
+    }
+  }
+
+  /// Write the files mounted by `ExecutionContext::synthetic_passwd` to the
+  /// sandbox directory.
+  fn write_synthetic_passwd(&self, context: &ExecutionContext) -> Result {
+    let (uid, gid) = self.credentials(context);
+
+    let etc = self.directory().join("etc");
+
+    fs::create_dir_all(&etc)?;
+
+    fs::write(
+      etc.join("passwd"),
+      format!("sandbox:x:{uid}:{gid}:sandbox:/box:/bin/sh\n"),
+    )?;
+
+    fs::write(etc.join("group"), format!("sandbox:x:{gid}:\n"))?;
+
+    Ok(())
+  }
+
+  /// Switch to the user the program runs as.
+  fn drop_privileges(&self, context: &ExecutionContext) -> Result {
+    let (uid, gid) = self.credentials(context);
+
+    syscall("drop groups", setgroups(&[]))?;
+
+    syscall("setresgid", setresgid(gid, gid, gid))?;
+
+    syscall("setresuid", setresuid(uid, uid, uid))
+  }
+
+  /// Run the prelude command in a process of its own and wait for it,
+  /// failing unless it exits with status 0.
+  ///
+  /// The process has its standard streams connected to `null` and exits with
+  /// status 127 if the command cannot be executed.
+  fn run_prelude(
+    &self,
+    context: &ExecutionContext,
+    prelude: &[CString],
+    environment: &[CString],
+    null: RawFd,
+  ) -> Result {
+    // SAFETY: The child is single-threaded, so the forked process can safely
+    // run arbitrary code before it executes the command or exits.
+    match syscall("fork prelude", unsafe { fork() })? {
+      ForkResult::Child => {
+        let _ = (|| -> Result {
+          self.drop_privileges(context)?;
+
+          for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+            syscall("redirect prelude", dup2(null, fd))?;
+          }
+
+          Self::reset_signals()?;
+
+          match syscall("execute prelude", execve(&prelude[0], prelude, environment))? {}
+        })();
+
+        // SAFETY: Exiting right away skips the parent's exit handlers.
+        unsafe { libc::_exit(127) }
+      }
+      ForkResult::Parent { child } => loop {
+        return match waitpid(child, None) {
+          Err(Errno::EINTR) => continue,
+          Ok(WaitStatus::Exited(_, 0)) => Ok(()),
+          Ok(WaitStatus::Exited(_, code)) => {
+            Err(Error::Prelude(format!("exited with error status {}", code)))
+          }
+          Ok(WaitStatus::Signaled(_, signal, _)) => Err(Error::Prelude(format!(
+            "caught fatal signal {}",
+            signal as i32
+          ))),
+          result => Err(Error::Internal(format!(
+            "unexpected prelude wait status: {:?}",
+            result
+          ))),
+        };
+      },
+    }
+  }
+
+  /// Mark every file descriptor but the standard streams close-on-exec, so
+  /// that none the caller opened without `O_CLOEXEC` leaks into the program.
+  ///
+  /// They are not closed right away, since the child still reports errors
+  /// through a pipe until the program is executed. Uses `close_range(2)`,
+  /// falling back to the descriptors in `/proc/self/fd` on kernels older
+  /// than 5.11, which lack it or its `CLOSE_RANGE_CLOEXEC` flag.
+  fn close_inherited_fds() -> Result {
+    // SAFETY: Only sets the close-on-exec flag of descriptors.
+    let result = unsafe {
+      libc::syscall(
+        libc::SYS_close_range,
+        3,
+        libc::c_uint::MAX,
+        libc::CLOSE_RANGE_CLOEXEC,
+      )
+    };
+
+    match Errno::result(result) {
+      Ok(_) => return Ok(()),
+      Err(Errno::ENOSYS | Errno::EINVAL) => {}
+      Err(error) => return syscall("close inherited file descriptors", Err(error)),
+    }
+
+    let fds = fs::read_dir("/proc/self/fd")?
+      .map(|entry| Ok(entry?.file_name().to_string_lossy().parse::<RawFd>().ok()))
+      .collect::<io::Result<Vec<_>>>()?;
+
+    for fd in fds
+      .into_iter()
+      .flatten()
+      .filter(|fd| *fd > libc::STDERR_FILENO)
+    {
+      match fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)) {
+        // The descriptor of `/proc/self/fd` itself is gone by now.
+        Ok(_) | Err(Errno::EBADF) => {}
+        Err(error) => return syscall("close inherited file descriptors", Err(error)),
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Restore the default disposition of every signal and unblock them all.
+  ///
+  /// Ignored signals and the signal mask survive `execve`, so anything the
+  /// embedding process changed would leak into the program. Most notably,
+  /// the Rust runtime ignores `SIGPIPE`, which makes programs writing to a
+  /// closed pipe get `EPIPE` instead of being killed.
+  fn reset_signals() -> Result {
+    for signal in 1..=libc::SIGRTMAX() {
+      if matches!(signal, libc::SIGKILL | libc::SIGSTOP) {
+        continue;
+      }
+
+      // SAFETY: Restoring the default disposition installs no handler.
+      match Errno::result(unsafe { libc::signal(signal, libc::SIG_DFL) }) {
+        // The C library reserves a few real-time signals for itself.
+        Ok(_) | Err(Errno::EINVAL) => {}
+        Err(error) => syscall(&format!("reset signal {}", signal), Err(error))?,
+      }
+    }
+
+    syscall(
+      "unblock signals",
+      sigprocmask(SigmaskHow::SIG_SETMASK, Some(&SigSet::empty()), None),
+    )
+  }
+
+  /// Connect the standard streams to the files requested in the context, or
+  /// to the capture pipes when the output is captured. `null` is a
+  /// descriptor of `/dev/null`.
+  ///
+  /// This runs after dropping privileges, so only files the sandbox user can
+  /// access can be used.
+  fn redirect_standard_streams(
+    context: &ExecutionContext,
+    capture: Option<&Capture>,
+    null: RawFd,
+  ) -> Result {
+    match &context.stdin {
+      Stdin::File(stdin) => Self::redirect(stdin, OFlag::O_RDONLY, libc::STDIN_FILENO)?,
+      Stdin::Null if !context.tty_hack => {
+        syscall(
+          "redirect stdin from `/dev/null`",
+          dup2(null, libc::STDIN_FILENO),
+        )?;
+      }
+      _ => {}
+    }
+
+    if let Some(capture) = capture {
+      return capture.attach(context);
+    }
+
+    if let Some(stdout) = &context.stdout {
+      Self::redirect_output(stdout, context.stdout_append, libc::STDOUT_FILENO)?;
+    }
+
+    if let Some(stderr) = &context.stderr {
+      Self::redirect_output(stderr, context.stderr_append, libc::STDERR_FILENO)?;
+    } else if context.stderr_to_stdout {
+      syscall(
+        "redirect stderr to stdout",
+        dup2(libc::STDOUT_FILENO, libc::STDERR_FILENO),
+      )?;
+    }
+
+    Ok(())
+  }
+
+  /// Fail early if the file standard input is redirected from, or the
+  /// directory of a file standard output or error is redirected to, does not
+  /// exist inside the sandbox.
+  ///
+  /// The paths are looked up on the host through the mounts they are on. The
+  /// files are still opened inside the sandbox, so this is only a check and
+  /// never grants access to anything. Paths on a `filesystem` or temporary
+  /// mount, or with a `..` component, cannot be looked up this way and are
+  /// left to the child.
+  fn validate_redirects(&self, context: &ExecutionContext) -> Result {
+    let mounts = context.effective_mounts();
+
+    let mounts = ExecutionContext::normalize(&mounts)?;
+
+    let working_directory = Utf8Path::new("/").join(context.effective_working_directory());
+
+    // `None` if the path cannot be looked up, and `Some(None)` if it is not
+    // on any mount, i.e. does not exist.
+    let lookup = |path: &Utf8Path| -> Option<Option<Utf8PathBuf>> {
+      let path = working_directory.join(path);
+
+      if path
+        .components()
+        .any(|component| component == Utf8Component::ParentDir)
+      {
+        return None;
+      }
+
+      let relative = path.strip_prefix("/").ok()?;
+
+      // Deeper mounts come last and shadow the ones they are nested in.
+      let Some(mount) = mounts
+        .iter()
+        .rev()
+        .find(|mount| relative.starts_with(mount.relative_inside_path()))
+      else {
+        return Some(None);
+      };
+
+      if mount.is_temporary() {
+        return None;
+      }
+
+      let source = mount.source(&self.directory())?;
+
+      Some(Some(source.join(
+        relative.strip_prefix(mount.relative_inside_path()).ok()?,
+      )))
+    };
+
+    if let Stdin::File(stdin) = &context.stdin {
+      if let Some(host) = lookup(stdin) {
+        ensure!(
+          host.is_some_and(|host| host.exists()),
+          Error::Config(format!(
+            "stdin `{}` does not exist inside the sandbox",
+            stdin
+          ))
+        );
+      }
+    }
+
+    for (name, output) in [("stdout", &context.stdout), ("stderr", &context.stderr)] {
+      let Some(output) = output else {
+        continue;
+      };
+
+      if let Some(host) = lookup(output) {
+        ensure!(
+          host.is_some_and(|host| host.parent().is_some_and(|parent| parent.is_dir())),
+          Error::Config(format!(
+            "directory of {} `{}` does not exist inside the sandbox",
+            name, output
+          ))
+        );
+      }
+    }
+
+    Ok(())
+  }
+
+  fn redirect(path: &Utf8Path, flags: OFlag, fd: RawFd) -> Result {
+    let file = Self::open(path, flags)?;
+
+    Self::replace(path, file, fd)
+  }
+
+  fn redirect_output(path: &Utf8Path, append: bool, fd: RawFd) -> Result {
+    let file = Self::open_output(path, append)?;
+
+    Self::replace(path, file, fd)
+  }
+
+  /// Open a file the program's output is written to, truncating it unless
+  /// `append` is set.
+  pub(crate) fn open_output(path: &Utf8Path, append: bool) -> Result<RawFd> {
+    Self::open(
+      path,
+      OFlag::O_WRONLY
+        | OFlag::O_CREAT
+        | if append {
+          OFlag::O_APPEND
+        } else {
+          OFlag::O_TRUNC
+        },
+    )
+  }
+
+  fn open(path: &Utf8Path, flags: OFlag) -> Result<RawFd> {
+    open(path.as_std_path(), flags, Mode::from_bits_truncate(0o666))
+      .map_err(|error| Error::Config(format!("failed to open `{}`: {}", path, error)))
+  }
+
+  fn replace(path: &Utf8Path, file: RawFd, fd: RawFd) -> Result {
+    if file != fd {
+      syscall(&format!("redirect `{}`", path), dup2(file, fd))?;
+      syscall(&format!("close `{}`", path), close(file))?;
+    }
+
+    Ok(())
+  }
+
+  /// Assemble the sandbox's root filesystem from the mounts and switch the
+  /// child into it.
+  ///
+  /// The root is switched with `pivot_root`, which detaches the host's
+  /// filesystem from the child's mount namespace entirely. Unlike with
+  /// `chroot`, there is nothing left to escape to, so `chroot` is only used
+  /// when `pivot_root` fails and `allow_chroot_fallback` is set.
+  fn setup_root(&self, context: &ExecutionContext) -> Result {
+    // Keep the mounts below from propagating back to the host.
+    self
+      .system
+      .mount(
+        None,
+        Utf8Path::new("/"),
+        None,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        None,
+      )
+      .map_err(|error| Error::Mount(format!("failed to make mounts private: {}", error)))?;
+
+    let root = self.directory().join("root");
+
+    fs::create_dir_all(&root)?;
+
+    self
+      .system
+      .mount(
+        Some("isolate"),
+        &root,
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        Some("mode=755"),
+      )
+      .map_err(|error| Error::Mount(format!("failed to mount root: {}", error)))?;
+
+    let mounts = context.effective_mounts();
+
+    let mounts = ExecutionContext::normalize(&mounts)?;
+
+    for (index, mount) in mounts.iter().enumerate() {
+      mount.apply(self.system, &root, &self.directory(), &mounts[..index])?;
+    }
+
+    self
+      .system
+      .chdir(&root)
+      .map_err(|error| Error::Mount(format!("failed to enter root: {}", error)))?;
+
+    let here = Utf8Path::new(".");
+
+    // Stacking the old root on top of the new one and detaching it afterwards
+    // avoids needing a directory to put it in.
+    match self.system.pivot_root(here, here) {
+      Ok(()) => self
+        .system
+        .umount(here, MntFlags::MNT_DETACH)
+        .map_err(|error| Error::Mount(format!("failed to detach old root: {}", error)))?,
+      Err(error) if context.allow_chroot_fallback => {
+        self.system.chroot(here).map_err(|chroot_error| {
+          Error::Mount(format!(
+            "failed to pivot_root ({}) and to chroot ({})",
+            error, chroot_error
+          ))
+        })?
+      }
+      Err(error) => {
+        return Err(Error::Mount(format!(
+          "failed to pivot_root: {} (see `allow_chroot_fallback`)",
+          error
+        )))
+      }
+    }
+
+    self
+      .system
+      .chdir(Utf8Path::new("/"))
+      .map_err(|error| Error::Mount(format!("failed to enter root: {}", error)))
+  }
+
+  /// Clean up the sandbox.
+  ///
+  /// This removes the sandbox directory, including the box and everything
+  /// the programs left in it, as well as the control groups of its runs that
+  /// are left over, and releases the lock on the sandbox.
+  pub fn cleanup(&mut self) -> Result {
+    ensure!(self.initialized, Error::NotInitialized);
+
+    self.remove_cgroups()?;
+
+    fs::remove_dir_all(self.directory())?;
+
+    self.initialized = false;
+
+    self.lock = None;
+
+    Ok(())
+  }
+
+  /// Clean up the sandbox like `Sandbox::cleanup`, but leave the box on disk
+  /// for inspection, e.g. to debug a failed run.
+  ///
+  /// Everything in the box is handed over to the user the sandbox acts on
+  /// behalf of. The box is only removed when the sandbox is initialized
+  /// again, so the caller is responsible for removing it eventually.
+  ///
+  /// Returns the path of the box.
+  pub fn cleanup_keeping_box(&mut self) -> Result<Utf8PathBuf> {
+    ensure!(self.initialized, Error::NotInitialized);
+
+    self.remove_cgroups()?;
+
+    let sandbox = self.directory().join("box");
+
+    self.chown_tree(&sandbox, self.original_uid, self.original_gid)?;
+
+    for entry in fs::read_dir(self.directory())? {
+      let entry = entry?;
+
+      if entry.file_name() == "box" {
+        continue;
+      }
+
+      if entry.file_type()?.is_dir() {
+        fs::remove_dir_all(entry.path())?;
+      } else {
+        fs::remove_file(entry.path())?;
+      }
+    }
+
+    self.initialized = false;
+
+    self.lock = None;
+
+    Ok(sandbox)
+  }
+
+  /// The name of the control group of the next run, unique among all runs of
+  /// all sandboxes on the host: `box-<id>-<pid>-<counter>`, where `id` is the
+  /// id of the sandbox, `pid` the process id of the caller and `counter` the
+  /// number of control groups it created before.
+  fn cgroup_name(&self) -> String {
+    format!(
+      "box-{}-{}-{}",
+      self.id(),
+      process::id(),
+      CGROUPS.fetch_add(1, Ordering::Relaxed)
+    )
+  }
+
+  /// Create the control group of a run below the root of `cgroup` and apply
+  /// its limits, or only apply them to the root if `cgroup` is not managed.
+  fn prepare_cgroup(&self, cgroup: &CgroupConfig) -> Result<Utf8PathBuf> {
+    let root = cgroup.root.resolve_with(self.system)?;
+
+    if !cgroup.manage {
+      cgroup.apply_with(self.system, &root)?;
+      return Ok(root);
+    }
+
+    let path = root.join(self.cgroup_name());
+
+    self
+      .system
+      .create_directory(&path)
+      .map_err(|error| Error::Cgroup(format!("failed to create control group `{path}`"), error))?;
+
+    if let Err(error) = cgroup.apply_with(self.system, &path) {
+      let _ = self.system.remove_empty_directory(&path);
+      return Err(error);
+    }
+
+    Ok(path)
+  }
+
+  /// Remove the control groups the runs in this sandbox left behind, e.g.
+  /// because the caller was killed while a program was running.
+  fn remove_cgroups(&self) -> Result {
+    let Some(cgroup) = self.config.cgroup.as_ref().filter(|cgroup| cgroup.manage) else {
+      return Ok(());
+    };
+
+    let root = cgroup.root.resolve_with(self.system)?;
+
+    let prefix = format!("box-{}-", self.id());
+
+    let entries = self.system.list_directory(&root).map_err(|error| {
+      Error::Cgroup(format!("failed to read control group root `{root}`"), error)
+    })?;
+
+    for entry in entries {
+      if entry
+        .file_name()
+        .is_some_and(|name| name.starts_with(&prefix))
+      {
+        self
+          .system
+          .remove_empty_directory(&entry)
+          .map_err(|error| {
+            Error::Cgroup(format!("failed to remove control group `{entry}`"), error)
+          })?;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Remove everything below `directory` that is neither a regular file nor
+  /// a directory, e.g. fifos, sockets, device nodes and symbolic links,
+  /// without following symbolic links.
+  fn remove_special_files(directory: &Utf8Path) -> Result {
+    for entry in directory.read_dir_utf8()? {
+      let entry = entry?;
+
+      let file_type = entry.file_type()?;
+
+      if file_type.is_dir() {
+        Self::remove_special_files(entry.path())?;
+      } else if !file_type.is_file() {
+        fs::remove_file(entry.path())?;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// The sizes of the regular files below `directory`, without following
+  /// symbolic links.
+  fn file_sizes(directory: &Utf8Path) -> Result<BTreeMap<Utf8PathBuf, u64>> {
+    let mut sizes = BTreeMap::new();
+
+    for entry in directory.read_dir_utf8()? {
+      let entry = entry?;
+
+      let metadata = entry.metadata()?;
+
+      if metadata.is_dir() {
+        sizes.extend(Self::file_sizes(entry.path())?);
+      } else if metadata.is_file() {
+        sizes.insert(entry.path().to_owned(), metadata.len());
+      }
+    }
+
+    Ok(sizes)
+  }
+
+  /// Give `path` and everything below it to `uid` and `gid`, without
+  /// following symbolic links, which could point anywhere on the host.
+  fn chown_tree(&self, path: &Utf8Path, uid: Uid, gid: Gid) -> Result {
+    lchown(path, Some(uid.as_raw()), Some(gid.as_raw()))?;
+
+    if fs::symlink_metadata(path)?.is_dir() {
+      for entry in path.read_dir_utf8()? {
+        self.chown_tree(entry?.path(), uid, gid)?;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Sample the resources used so far by the program that is being executed
+  /// in the sandbox, e.g. from another thread while `Sandbox::execute` runs.
+  ///
+  /// Fails with `Error::NotRunning` if no program is being executed. The
+  /// program may also exit while it is being sampled, in which case this
+  /// fails with an I/O error, or reports it as using no memory.
+  pub fn sample(&self) -> Result<ResourceSample> {
+    let pid = self.pid.load(Ordering::Acquire);
+
+    ensure!(pid != 0, Error::NotRunning(self.id()));
+
+    ResourceSample::read(self.system, Pid::from_raw(pid))
+  }
+
+  /// Kill the program that is being executed in the sandbox, e.g. from
+  /// another thread while `Sandbox::execute` runs, when the caller shuts
+  /// down.
+  ///
+  /// `Sandbox::execute` then returns with `ExecutionResult::kill_reason` set
+  /// to `KillReason::Shutdown`. Fails with `Error::NotRunning` if no program
+  /// is being executed.
+  pub fn kill(&self) -> Result {
+    let pid = self.pid.load(Ordering::Acquire);
+
+    ensure!(pid != 0, Error::NotRunning(self.id()));
+
+    self.shutdown.store(true, Ordering::Release);
+
+    self.system.kill(Pid::from_raw(pid), Signal::SIGKILL)
+  }
+
+  /// Replace the resource limits of the program that is being executed in
+  /// the sandbox with the ones of `limits`, e.g. from another thread while
+  /// `Sandbox::execute` runs.
+  ///
+  /// The limits are normally set by the child itself, after the prelude and
+  /// right before it executes the program, so they do not apply to the
+  /// prelude. This sets them from the outside instead, and only for the
+  /// process the program was executed in, not for processes it already
+  /// started. Limits that are enforced by the sandbox rather than the kernel,
+  /// i.e. the time limits apart from `RLIMIT_CPU`, are not affected.
+  ///
+  /// Since the program runs as the sandbox user, this requires
+  /// `CAP_SYS_RESOURCE`. Fails with `Error::NotRunning` if no program is being executed.
+  pub fn apply_limits(&self, limits: &Limits) -> Result {
+    let pid = self.pid.load(Ordering::Acquire);
+
+    ensure!(pid != 0, Error::NotRunning(self.id()));
+
+    for limit in limits.resource_limits(self.config.cgroup_enabled()) {
+      limit.apply_to(self.system, Pid::from_raw(pid))?;
+    }
+
+    Ok(())
+  }
+
+  /// Get the id of the sandbox.
+  pub fn id(&self) -> u32 {
+    self.config.sandbox_id.unwrap_or(0)
+  }
+
+  /// Get the group id of the sandbox.
+  pub fn gid(&self) -> Gid {
+    Gid::from_raw(self.environment.first_sandbox_gid + self.id())
+  }
+
+  /// Get the user id of the sandbox.
+  pub fn uid(&self) -> Uid {
+    Uid::from_raw(self.environment.first_sandbox_uid + self.id())
+  }
+
+  /// Get the directory of the sandbox.
+  pub fn directory(&self) -> Utf8PathBuf {
+    self.environment.sandbox_root.join(self.id().to_string())
+  }
+
+  /// Get the lock file of the sandbox.
+  pub fn lock_path(&self) -> Utf8PathBuf {
+    self.environment.lock_root.join(self.id().to_string())
+  }
+
+  /// Get a snapshot of the static facts about the sandbox.
+  pub fn metadata(&self) -> SandboxMetadata {
+    SandboxMetadata {
+      cgroup_root: self
+        .config
+        .cgroup
+        .as_ref()
+        .and_then(|cgroup| cgroup.root.resolve_with(self.system).ok()),
+      directory: self.directory(),
+      gid: self.gid().as_raw(),
+      id: self.id(),
+      initialized: self.initialized,
+      lock_path: self.lock_path(),
+      uid: self.uid().as_raw(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    assert_matches::assert_matches,
+    config::Config,
+    in_memory_system::Directory,
+    nix::{sys::stat::Mode, unistd::Uid},
+    std::collections::BTreeMap,
+  };
+
+  #[test]
+  fn sandbox_construction_without_root_euid() {
+    let environment = Environment::default();
+
+    let config = Config::default();
+
+    let mock = InMemorySystem {
+      euid: Uid::from_raw(1000),
+      ..Default::default()
+    };
+
+    let result = Sandbox::new(config, &environment, &mock);
+
+    assert!(matches!(result, Err(Error::NotRoot)));
+  }
+
+  #[test]
+  fn sandbox_construction_as_uid_as_gid_non_root_original() {
+    let environment = Environment::default();
+
+    let config = Config {
+      as_uid: Some(2000),
+      as_gid: Some(2000),
+      ..Default::default()
+    };
+
+    let mock = InMemorySystem {
+      uid: Uid::from_raw(1000),
+      ..Default::default()
+    };
+
+    let result = Sandbox::new(config, &environment, &mock);
+
+    assert_matches!(
+      result,
+      Err(Error::Permission(message)) if message.contains("you must be root to use `as_uid` or `as_gid`")
+    );
+  }
+
+  #[test]
+  fn sandbox_construction_without_cap_sys_admin() {
+    let environment = Environment::default();
+
+    let mock = InMemorySystem {
+      capabilities: 0,
+      ..Default::default()
+    };
+
+    assert_matches!(
+      Sandbox::new(Config::default(), &environment, &mock),
+      Err(Error::Permission(message)) if message == "running without CAP_SYS_ADMIN; namespace setup will fail"
+    );
+
+    let mock = InMemorySystem {
+      capabilities: 0,
+      uid_map: "0 1000 1\n".into(),
+      ..Default::default()
+    };
+
+    assert_matches!(
+      Sandbox::new(Config::default(), &environment, &mock),
+      Err(Error::Permission(message)) if message == "running inside a user namespace without CAP_SYS_ADMIN; namespace setup will fail"
+    );
+  }
+
+  #[test]
+  fn sandbox_construction_as_uid_without_as_gid() {
+    let (mock, environment) = (InMemorySystem::default(), Environment::default());
+
+    let config = Config {
+      as_uid: Some(2000),
+      ..Default::default()
+    };
+
+    let result = Sandbox::new(config, &environment, &mock);
+
+    assert_matches!(
+      result,
+      Err(Error::Config(message)) if message.contains("`as_uid` and `as_gid` must be used either both or none")
+    );
+  }
+
+  #[test]
+  fn sandbox_construction_valid_no_as() {
+    let (mock, environment) = (InMemorySystem::default(), Environment::default());
+
+    let config = Config::default();
+
+    let sandbox =
+      Sandbox::new(config, &environment, &mock).expect("Sandbox creation should succeed");
+
+    // With no as_uid/as_gid, the sandbox takes the original uid/gid from the system.
+    assert_eq!(sandbox.original_gid, 0.into());
+    assert_eq!(sandbox.original_uid, 0.into());
+
+    assert_eq!(
+      mock.umask.lock().unwrap().unwrap(),
+      Mode::from_bits_truncate(0o022)
+    );
+  }
+
+  #[test]
+  fn sandbox_construction_valid_with_as() {
+    let (mock, environment) = (InMemorySystem::default(), Environment::default());
+
+    let config = Config {
+      as_uid: Some(2000),
+      as_gid: Some(2000),
+      ..Default::default()
+    };
+
+    let sandbox =
+      Sandbox::new(config, &environment, &mock).expect("Sandbox creation should succeed");
+
+    // When as_uid/as_gid are provided and allowed, the sandbox's id are set to those values.
+    assert_eq!(sandbox.original_uid, 2000.into());
+    assert_eq!(sandbox.original_gid, 2000.into());
+  }
+
+  #[test]
+  fn sandbox_construction_credentials_setup() {
+    let environment = Environment {
+      sandbox_root: Utf8PathBuf::from("/tmp/isolate_test"),
+      first_sandbox_uid: 10000,
+      first_sandbox_gid: 20000,
+      num_sandboxes: 10,
+      ..Default::default()
+    };
+
+    let config = Config {
+      sandbox_id: Some(5),
+      ..Default::default()
+    };
+
+    let mock = InMemorySystem::default();
+
+    let sandbox =
+      Sandbox::new(config, &environment, &mock).expect("Sandbox creation should succeed");
+
+    assert_eq!(
+      sandbox.directory(),
+      Utf8PathBuf::from("/tmp/isolate_test").join("5")
+    );
+
+    assert_eq!(sandbox.gid(), (20000 + 5).into());
+    assert_eq!(sandbox.id(), 5);
+    assert_eq!(sandbox.uid(), (10000 + 5).into());
+  }
+
+  #[test]
+  fn sandbox_construction_id_out_of_range() {
+    let environment = Environment {
+      sandbox_root: Utf8PathBuf::from("/tmp/isolate_test"),
+      first_sandbox_gid: 20000,
+      first_sandbox_uid: 10000,
+      num_sandboxes: 10, // Valid box id's are between 0 and 9 (inclusive).
+      ..Default::default()
+    };
+
+    let config = Config {
+      sandbox_id: Some(10),
+      ..Default::default()
+    };
+
+    let mock = InMemorySystem::default();
+
+    let result = Sandbox::new(config, &environment, &mock);
+
+    assert_matches!(
+      result,
+      Err(Error::Config(message)) if message.contains("sandbox id out of range")
+    );
+  }
+
+  #[test]
+  fn sandbox_construction_uid_overflow() {
+    let environment = Environment {
+      first_sandbox_uid: u32::MAX - 1,
+      num_sandboxes: 10,
+      ..Default::default()
+    };
+
+    let config = Config {
+      sandbox_id: Some(5),
+      ..Default::default()
+    };
+
+    let mock = InMemorySystem::default();
+
+    assert_matches!(
+      Sandbox::new(config, &environment, &mock),
+      Err(Error::Config(message)) if message == "sandbox uid/gid range overflows"
+    );
+  }
+
+  #[test]
+  fn sandbox_initialization_restricted_by_environment() {
+    let environment = Environment {
       num_sandboxes: 10,
       restrict_initialization: true,
       ..Default::default()
     };
 
     let config = Config {
-      sandbox_id: Some(0),
+      sandbox_id: Some(0),
+      ..Default::default()
+    };
+
+    let mock = InMemorySystem {
+      uid: Uid::from_raw(1000),
+      ..Default::default()
+    };
+
+    let mut sandbox = Sandbox::new(config, &environment, &mock).unwrap();
+
+    assert_matches!(
+      sandbox.initialize(),
+      Err(Error::Permission(message)) if message.contains("you must be root to initialize the sandbox")
+    );
+  }
+
+  #[test]
+  fn sandbox_batch_reset() {
+    let (environment, system) = (Environment::default(), InMemorySystem::default());
+
+    let mut sandbox = Sandbox::new(
+      Config {
+        sandbox_id: Some(4),
+        ..Default::default()
+      },
+      &environment,
+      &system,
+    )
+    .unwrap();
+
+    sandbox.initialize().unwrap();
+
+    let sandbox_box = sandbox.directory().join("box");
+
+    system
+      .write_file(&sandbox_box.join("main"), "compiled")
+      .unwrap();
+
+    let mut seen = Vec::new();
+
+    let results = sandbox
+      .run_batch(
+        &ExecutionContext::default().reset_box_between_runs(true),
+        [Stdin::Null, Stdin::File("input".into())],
+        |context| {
+          seen.push((
+            context.stdin,
+            system
+              .files
+              .lock()
+              .unwrap()
+              .keys()
+              .filter_map(|path| path.strip_prefix(&sandbox_box).ok())
+              .map(Utf8Path::to_string)
+              .collect::<Vec<_>>(),
+          ));
+
+          system
+            .write_file(&sandbox_box.join("output"), "written")
+            .unwrap();
+
+          Ok(ExecutionResult::default())
+        },
+      )
+      .unwrap();
+
+    assert_eq!(results.len(), 2);
+
+    assert_eq!(
+      seen,
+      [
+        (Stdin::Null, vec!["main".to_owned()]),
+        (Stdin::File("input".into()), vec!["main".to_owned()]),
+      ]
+    );
+
+    assert_eq!(
+      system.directory(&sandbox_box),
+      Some(Directory {
+        gid: sandbox.gid(),
+        mode: 0o700,
+        uid: sandbox.uid(),
+      })
+    );
+
+    assert_eq!(system.directory(sandbox.directory().join("snapshot")), None);
+  }
+
+  #[test]
+  fn sandbox_cgroups() {
+    let root = Utf8Path::new("/sys/fs/cgroup/isolate");
+
+    let (environment, mock) = (Environment::default(), InMemorySystem::default());
+
+    mock.create_directory_with_mode(root, 0o755).unwrap();
+
+    let sandbox = Sandbox::new(
+      Config {
+        cgroup: Some(CgroupConfig {
+          root: CgroupRoot::Manual(root.into()),
+          ..Default::default()
+        }),
+        sandbox_id: Some(3),
+        ..Default::default()
+      },
+      &environment,
+      &mock,
+    )
+    .unwrap();
+
+    let cgroup = sandbox
+      .prepare_cgroup(sandbox.config.cgroup.as_ref().unwrap())
+      .unwrap();
+
+    assert_eq!(cgroup.parent(), Some(root));
+
+    let name = cgroup.file_name().unwrap();
+
+    assert!(name.starts_with(&format!("box-3-{}-", process::id())));
+
+    assert_eq!(
+      mock.file(cgroup.join("memory.max")).as_deref(),
+      Some("1073741824")
+    );
+
+    assert_ne!(sandbox.cgroup_name(), name);
+
+    for name in ["box-3-1-0", "box-3-2-7", "box-30-1-0", "box-4-1-0"] {
+      mock.create_directory(&root.join(name)).unwrap();
+    }
+
+    sandbox.remove_cgroups().unwrap();
+
+    assert_eq!(
+      mock.list_directory(root).unwrap(),
+      [root.join("box-30-1-0"), root.join("box-4-1-0")]
+    );
+
+    assert_eq!(mock.file(cgroup.join("memory.max")), None);
+  }
+
+  #[test]
+  fn sandbox_unmanaged_cgroup() {
+    let root = Utf8Path::new("/sys/fs/cgroup/isolate");
+
+    let (environment, mock) = (Environment::default(), InMemorySystem::default());
+
+    mock.create_directory_with_mode(root, 0o755).unwrap();
+
+    let sandbox = Sandbox::new(
+      Config {
+        cgroup: Some(CgroupConfig {
+          manage: false,
+          memory_limit: Some(1024),
+          root: CgroupRoot::Manual(root.into()),
+          ..Default::default()
+        }),
+        sandbox_id: Some(3),
+        ..Default::default()
+      },
+      &environment,
+      &mock,
+    )
+    .unwrap();
+
+    let cgroup = sandbox
+      .prepare_cgroup(sandbox.config.cgroup.as_ref().unwrap())
+      .unwrap();
+
+    assert_eq!(cgroup, root);
+
+    assert_eq!(
+      mock.file(root.join("memory.max")).as_deref(),
+      Some("1048576")
+    );
+
+    mock.create_directory(&root.join("box-3-1-0")).unwrap();
+
+    sandbox.remove_cgroups().unwrap();
+
+    assert!(mock.directory(root.join("box-3-1-0")).is_some());
+  }
+
+  #[test]
+  fn sandbox_construction_cgroup_root_from_environment() {
+    let environment = Environment {
+      cgroup_root: Some(CgroupRoot::Manual(Utf8PathBuf::from(
+        "/sys/fs/cgroup/fleet",
+      ))),
+      ..Default::default()
+    };
+
+    let mock = InMemorySystem::default();
+
+    let sandbox =
+      Sandbox::new(Config::default().with_default_cgroup(), &environment, &mock).unwrap();
+
+    assert_eq!(
+      sandbox.metadata().cgroup_root,
+      Some(Utf8PathBuf::from("/sys/fs/cgroup/fleet"))
+    );
+
+    let config = Config {
+      cgroup: Some(CgroupConfig {
+        root: CgroupRoot::Manual(Utf8PathBuf::from("/sys/fs/cgroup/run")),
+        ..Default::default()
+      }),
       ..Default::default()
     };
 
-    let mock = MockSystem {
-      uid: Uid::from_raw(1000),
+    let sandbox = Sandbox::new(config, &environment, &mock).unwrap();
+
+    assert_eq!(
+      sandbox.metadata().cgroup_root,
+      Some(Utf8PathBuf::from("/sys/fs/cgroup/run"))
+    );
+  }
+
+  #[test]
+  fn sandbox_metadata() {
+    let environment = Environment {
+      sandbox_root: Utf8PathBuf::from("/tmp/isolate_test"),
+      lock_root: Utf8PathBuf::from("/tmp/isolate_locks"),
+      first_sandbox_uid: 10000,
+      first_sandbox_gid: 20000,
+      num_sandboxes: 10,
       ..Default::default()
     };
 
+    let config = Config {
+      sandbox_id: Some(3),
+      cgroup: Some(CgroupConfig {
+        root: CgroupRoot::Manual(Utf8PathBuf::from("/sys/fs/cgroup/isolate")),
+        ..Default::default()
+      }),
+      ..Default::default()
+    };
+
+    let mock = InMemorySystem::default();
+
     let sandbox = Sandbox::new(config, &environment, &mock).unwrap();
 
+    assert_eq!(
+      sandbox.metadata(),
+      SandboxMetadata {
+        cgroup_root: Some(Utf8PathBuf::from("/sys/fs/cgroup/isolate")),
+        directory: Utf8PathBuf::from("/tmp/isolate_test/3"),
+        gid: 20003,
+        id: 3,
+        initialized: false,
+        lock_path: Utf8PathBuf::from("/tmp/isolate_locks/3"),
+        uid: 10003,
+      }
+    );
+  }
+
+  #[test]
+  fn sandbox_setup_root() {
+    let directory = tempfile::tempdir().unwrap();
+
+    let environment = Environment {
+      sandbox_root: Utf8Path::from_path(directory.path()).unwrap().into(),
+      ..Default::default()
+    };
+
+    let context = ExecutionContext::new("/bin/true".into(), None).mounts(vec![
+      Mount::read_only("usr/bin", Some("/usr/bin")).unwrap(),
+      Mount::read_write("box", Some("./box")).unwrap(),
+      Mount::filesystem("proc", "proc").unwrap(),
+    ]);
+
+    let system = InMemorySystem::default();
+
+    let sandbox = Sandbox::new(Config::default(), &environment, &system).unwrap();
+
+    sandbox.setup_root(&context).unwrap();
+
+    let root = sandbox.directory().join("root");
+
+    assert_eq!(
+      *system.calls.lock().unwrap(),
+      [
+        "mount - / - MsFlags(MS_REC | MS_PRIVATE) -".into(),
+        format!("mount isolate {root} tmpfs MsFlags(MS_NOSUID | MS_NODEV) mode=755"),
+        format!(
+          "mount {}/./box {root}/box - MsFlags(MS_BIND | MS_REC) -",
+          sandbox.directory()
+        ),
+        format!(
+          "mount - {root}/box - MsFlags(MS_NOSUID | MS_NODEV | MS_REMOUNT | MS_BIND) -"
+        ),
+        format!("mount proc {root}/proc proc MsFlags(MS_RDONLY | MS_NOSUID | MS_NODEV) -"),
+        format!("mount /usr/bin {root}/usr/bin - MsFlags(MS_BIND | MS_REC) -"),
+        format!(
+          "mount - {root}/usr/bin - MsFlags(MS_RDONLY | MS_NOSUID | MS_NODEV | MS_REMOUNT | MS_BIND) -"
+        ),
+        format!("chdir {root}"),
+        "pivot_root . .".into(),
+        "umount . MntFlags(MNT_DETACH)".into(),
+        "chdir /".into(),
+      ]
+    );
+  }
+
+  #[test]
+  fn sandbox_apply_limits() {
+    let environment = Environment::default();
+
+    let system = InMemorySystem::default();
+
+    let sandbox = Sandbox::new(Config::default(), &environment, &system).unwrap();
+
+    let limits = Limits::default().process_limit(4).stack_limit_kb(8);
+
+    assert_matches!(sandbox.apply_limits(&limits), Err(Error::NotRunning(0)));
+
+    sandbox.pid.store(42, Ordering::Release);
+
+    sandbox.apply_limits(&limits).unwrap();
+
+    let calls = system.calls.lock().unwrap();
+
+    assert_eq!(calls.len(), limits.resource_limits(false).len());
+
+    for call in ["prlimit 42 RLIMIT_NPROC 4", "prlimit 42 RLIMIT_STACK 8192"] {
+      assert!(calls.iter().any(|c| c == call), "{calls:?}");
+    }
+  }
+
+  #[test]
+  fn sandbox_conclude() {
+    let context = ExecutionContext::default();
+
+    let killed = WaitStatus::Signaled(Pid::from_raw(1), Signal::SIGKILL, false);
+
+    let conclude = |killed_by_oom, kill_reason, status| {
+      let mut result = ExecutionResult {
+        killed_by_oom,
+        ..Default::default()
+      };
+
+      Sandbox::conclude(
+        &mut result,
+        &context,
+        None,
+        false,
+        String::new(),
+        kill_reason,
+        status,
+      );
+
+      result
+    };
+
+    let result = conclude(true, None, killed);
+    assert_eq!(result.kill_reason, Some(KillReason::Memory));
+    assert_eq!(result.status, Status::MemoryLimit);
+    assert_eq!(result.status_message, "Memory limit exceeded");
+    assert!(result.terminated_by_sandbox);
+
+    let result = conclude(true, None, WaitStatus::Exited(Pid::from_raw(1), 0));
+    assert_eq!(result.kill_reason, None);
+    assert_eq!(result.status, Status::Ok);
+
+    let result = conclude(false, Some(KillReason::Shutdown), killed);
+    assert_eq!(result.kill_reason, Some(KillReason::Shutdown));
+    assert_eq!(result.status, Status::InternalError);
+    assert_eq!(result.status_message, "Killed by the sandbox on shutdown");
+
+    let result = conclude(false, Some(KillReason::CpuTimeout), killed);
+    assert_eq!(result.status, Status::Timeout);
+    assert_eq!(result.status_message, "Time limit exceeded");
+  }
+
+  #[test]
+  fn sandbox_kill() {
+    let environment = Environment::default();
+
+    let system = InMemorySystem::default();
+
+    let sandbox = Sandbox::new(Config::default(), &environment, &system).unwrap();
+
+    assert_matches!(sandbox.kill(), Err(Error::NotRunning(0)));
+
+    sandbox.pid.store(42, Ordering::Release);
+
+    sandbox.kill().unwrap();
+
+    assert!(sandbox.shutdown.load(Ordering::Acquire));
+
+    assert_eq!(*system.calls.lock().unwrap(), ["kill 42 SIGKILL"]);
+  }
+
+  #[test]
+  fn sandbox_audit() {
+    let environment = Environment {
+      audit_log: Some("/var/log/isolate/audit.jsonl".into()),
+      ..Default::default()
+    };
+
+    let system = InMemorySystem::default();
+
+    let sandbox = Sandbox::new(Config::default(), &environment, &system).unwrap();
+
+    let record = AuditRecord::new(
+      0,
+      system.getuid(),
+      &ExecutionContext::new("/bin/true".into(), None),
+    )
+    .finish(&Err(Error::NotInitialized));
+
+    sandbox.audit(&record);
+
+    assert_eq!(
+      *system.calls.lock().unwrap(),
+      [format!(
+        "append /var/log/isolate/audit.jsonl {}",
+        record.to_json_line().trim_end()
+      )]
+    );
+  }
+
+  #[test]
+  fn sandbox_dump() {
+    let environment = Environment::default();
+
+    let context = || {
+      ExecutionContext::new("/bin/echo".into(), Some(vec!["hello world"]))
+        .process_limit(4)
+        .set_variables([("LANG", "C")])
+    };
+
+    let dump = |config: Config, context: ExecutionContext| {
+      let system = InMemorySystem::default();
+
+      Sandbox::new(config, &environment, &system)
+        .unwrap()
+        .dump(&context)
+        .unwrap();
+
+      system.log.into_inner().unwrap()
+    };
+
+    let verbose = || Config {
+      verbose: true,
+      ..Default::default()
+    };
+
+    let log = dump(verbose(), context());
+
+    for line in [
+      "Running as uid 60000 and gid 60000",
+      "Limit RLIMIT_NPROC: 4",
+      "Mount box=./box:rw",
+      r#"Executing "/bin/echo" "hello world""#,
+      "Environment LANG=C",
+    ] {
+      assert!(log.iter().any(|logged| logged == line), "{line}: {log:?}");
+    }
+
+    assert_eq!(
+      dump(verbose(), context().silent(true)),
+      Vec::<String>::new()
+    );
+
+    assert_eq!(dump(Config::default(), context()), Vec::<String>::new());
+
+    let ignored = "Ignoring memory limit of 256000 KB, the control group limits memory instead";
+
+    assert!(!log.iter().any(|logged| logged == ignored));
+
+    let log = dump(
+      Config {
+        cgroup: Some(CgroupConfig::default()),
+        ..verbose()
+      },
+      context(),
+    );
+
+    assert!(log.iter().any(|logged| logged == ignored), "{log:?}");
+  }
+
+  #[test]
+  fn sandbox_report() {
+    let environment = Environment::default();
+
+    let report = |context: ExecutionContext| {
+      let system = InMemorySystem::default();
+
+      Sandbox::new(Config::default(), &environment, &system)
+        .unwrap()
+        .report(&context, "OK (0.000 sec real, 0.001 sec wall)");
+
+      system.log.into_inner().unwrap()
+    };
+
+    let context = || ExecutionContext::new("/bin/true".into(), None);
+
+    assert_eq!(report(context()), ["OK (0.000 sec real, 0.001 sec wall)"]);
+
+    assert_eq!(report(context().silent(true)), Vec::<String>::new());
+  }
+
+  #[test]
+  fn sandbox_execution_while_running() {
+    let system = InMemorySystem::default();
+
+    let environment = Environment::default();
+
+    let mut sandbox = Sandbox::new(Config::default(), &environment, &system).unwrap();
+
+    sandbox.initialized = true;
+
+    sandbox.running = AtomicBool::new(true);
+
+    assert_matches!(
+      sandbox.execute(ExecutionContext::new("/bin/true".into(), None)),
+      Err(Error::Locked(0))
+    );
+  }
+
+  #[test]
+  fn sandbox_initialization_verifies_cgroup_root() {
+    let environment = Environment::default();
+
+    let config = || Config {
+      cgroup: Some(CgroupConfig {
+        root: CgroupRoot::Manual(Utf8PathBuf::from("/sys/fs/cgroup/isolate")),
+        ..Default::default()
+      }),
+      ..Default::default()
+    };
+
+    let mut system = InMemorySystem::default();
+
+    system
+      .create_directory_with_mode("/sys/fs/cgroup/isolate".into(), 0o755)
+      .unwrap();
+
+    assert_matches!(
+      Sandbox::new(config(), &environment, &system).unwrap().initialize(),
+      Err(Error::Config(message))
+        if message == "cgroup root `/sys/fs/cgroup/isolate` is not on a cgroup filesystem"
+    );
+
+    system
+      .filesystems
+      .insert("/sys/fs/cgroup".into(), CGROUP2_SUPER_MAGIC);
+
+    assert!(Sandbox::new(config(), &environment, &system)
+      .unwrap()
+      .initialize()
+      .is_ok());
+  }
+
+  #[test]
+  fn sandbox_execution_with_retry() {
+    let system = InMemorySystem::default();
+
+    let environment = Environment::default();
+
+    let mut sandbox = Sandbox::new(Config::default(), &environment, &system).unwrap();
+
+    sandbox.initialized = true;
+
+    sandbox.running = AtomicBool::new(true);
+
+    let start = Instant::now();
+
+    assert_matches!(
+      sandbox.execute_with_retry(
+        ExecutionContext::new("/bin/true".into(), None),
+        &RetryPolicy::default().initial_backoff_ms(5.0),
+      ),
+      Err(Error::Locked(0))
+    );
+
+    assert!(start.elapsed() >= Duration::from_millis(15));
+
+    sandbox.running = AtomicBool::new(false);
+
+    let start = Instant::now();
+
+    assert_matches!(
+      sandbox.execute_with_retry(
+        ExecutionContext::default(),
+        &RetryPolicy::default().initial_backoff_ms(1000.0),
+      ),
+      Err(Error::Config(_))
+    );
+
+    assert!(start.elapsed() < Duration::from_secs(1));
+  }
+
+  #[test]
+  fn sandbox_initialization() {
+    let environment = Environment {
+      sandbox_root_tmpfs: Some(64),
+      ..Default::default()
+    };
+
+    let config = Config {
+      sandbox_id: Some(2),
+      ..Default::default()
+    };
+
+    let system = InMemorySystem::default();
+
+    let mut sandbox = Sandbox::new(config, &environment, &system).unwrap();
+
+    sandbox.initialize().unwrap();
+
+    assert!(sandbox.initialized);
+
+    assert_eq!(
+      system.directory("/var/local/lib/isolate").unwrap().mode,
+      0o700
+    );
+
+    assert_eq!(system.directory("/run/isolate/locks").unwrap().mode, 0o700);
+
+    assert_eq!(
+      *system.mounts.lock().unwrap(),
+      BTreeMap::from([(Utf8PathBuf::from("/var/local/lib/isolate"), 64)])
+    );
+
+    assert_eq!(
+      system.directory("/var/local/lib/isolate/2/box"),
+      Some(Directory {
+        gid: sandbox.gid(),
+        mode: 0o700,
+        uid: sandbox.uid(),
+      })
+    );
+  }
+
+  #[test]
+  fn sandbox_initialization_with_box_mode() {
+    let environment = Environment {
+      box_mode: 0o750,
+      ..Default::default()
+    };
+
+    let config = Config {
+      sandbox_id: Some(2),
+      ..Default::default()
+    };
+
+    let system = InMemorySystem::default();
+
+    let mut sandbox = Sandbox::new(config, &environment, &system).unwrap();
+
+    sandbox.initialize().unwrap();
+
+    assert_eq!(
+      system.directory("/var/local/lib/isolate/2").unwrap().mode,
+      0o710
+    );
+
+    assert_eq!(
+      system
+        .directory("/var/local/lib/isolate/2/box")
+        .unwrap()
+        .mode,
+      0o750
+    );
+  }
+
+  #[test]
+  fn sandbox_initialization_without_creating_root() {
+    let environment = Environment {
+      create_root: false,
+      ..Default::default()
+    };
+
+    let system = InMemorySystem::default();
+
+    let mut sandbox = Sandbox::new(Config::default(), &environment, &system).unwrap();
+
     assert_matches!(
       sandbox.initialize(),
-      Err(Error::Permission(message)) if message.contains("you must be root to initialize the sandbox")
+      Err(Error::Config(message)) if message == "sandbox_root does not exist and create_root is false"
+    );
+
+    assert!(system.directory("/var/local/lib/isolate").is_none());
+
+    system
+      .create_directory_with_mode("/var/local/lib/isolate".into(), 0o755)
+      .unwrap();
+
+    sandbox.initialize().unwrap();
+
+    // Left as it was provisioned.
+    assert_eq!(
+      system.directory("/var/local/lib/isolate").unwrap().mode,
+      0o755
+    );
+  }
+
+  #[test]
+  fn sandbox_initialization_recreates_directory() {
+    let environment = Environment::default();
+
+    let system = InMemorySystem::default();
+
+    system
+      .create_directory_with_mode("/var/local/lib/isolate/0/box/stale".into(), 0o755)
+      .unwrap();
+
+    let mut sandbox = Sandbox::new(Config::default(), &environment, &system).unwrap();
+
+    sandbox.initialize().unwrap();
+
+    assert!(system.directory("/var/local/lib/isolate/0/box").is_some());
+
+    assert!(system
+      .directory("/var/local/lib/isolate/0/box/stale")
+      .is_none());
+  }
+
+  #[test]
+  fn sandbox_initialization_with_writable_ancestor() {
+    let environment = Environment::default();
+
+    let system = InMemorySystem::default();
+
+    system
+      .create_directory_with_mode("/var/local".into(), 0o777)
+      .unwrap();
+
+    let mut sandbox = Sandbox::new(Config::default(), &environment, &system).unwrap();
+
+    assert_matches!(
+      sandbox.initialize(),
+      Err(Error::Permission(message)) if message == "directory /var/local must be writable only by root"
     );
+
+    assert!(!sandbox.initialized);
   }
 }
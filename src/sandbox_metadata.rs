@@ -0,0 +1,29 @@
+use super::*;
+
+/// A snapshot of the static facts about a sandbox, as returned by
+/// `Sandbox::metadata`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, PartialEq)]
+pub struct SandboxMetadata {
+  /// The resolved control group root, if control groups are enabled and the
+  /// root could be resolved.
+  pub cgroup_root: Option<Utf8PathBuf>,
+
+  /// The directory of the sandbox.
+  pub directory: Utf8PathBuf,
+
+  /// The group id programs run as.
+  pub gid: u32,
+
+  /// The id of the sandbox.
+  pub id: u32,
+
+  /// Whether the sandbox has been initialized.
+  pub initialized: bool,
+
+  /// The lock file of the sandbox.
+  pub lock_path: Utf8PathBuf,
+
+  /// The user id programs run as.
+  pub uid: u32,
+}
@@ -0,0 +1,37 @@
+use super::*;
+
+/// Where the program's standard input comes from, see
+/// `ExecutionContext::stdin`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Stdin {
+  /// Read from a file inside the sandbox.
+  ///
+  /// The file has to be accessible inside the sandbox (which means that the
+  /// sandboxed program can manipulate it arbitrarily). The path is a path
+  /// inside the sandbox, see `ExecutionContext::stdout`, and executing fails
+  /// with `Error::Config` if the file does not exist there.
+  File(Utf8PathBuf),
+  /// Inherit standard input from the parent process.
+  ///
+  /// Beware that a program reading from an inherited terminal or pipe that
+  /// never delivers any input waits until it runs out of wall time.
+  Inherit,
+  /// Read from `/dev/null`, so that the program sees the end of its input
+  /// right away.
+  ///
+  /// With `ExecutionContext::tty_hack`, standard input is inherited instead.
+  #[default]
+  Null,
+}
+
+impl From<Utf8PathBuf> for Stdin {
+  fn from(path: Utf8PathBuf) -> Self {
+    Self::File(path)
+  }
+}
+
+impl From<Option<Utf8PathBuf>> for Stdin {
+  fn from(path: Option<Utf8PathBuf>) -> Self {
+    path.map_or(Self::Null, Self::File)
+  }
+}
@@ -0,0 +1,55 @@
+use super::*;
+
+/// Attach the name of the operation to the error of a system call, mapping
+/// errors caused by missing privileges to `Error::Permission`, errors that
+/// may go away when trying again to `Error::Transient` and everything else
+/// to `Error::Internal`.
+pub(crate) fn syscall<T>(operation: &str, result: nix::Result<T>) -> Result<T> {
+  result.map_err(|error| match error {
+    Errno::EACCES | Errno::EPERM => {
+      Error::Permission(format!("failed to {}: {}", operation, error))
+    }
+    Errno::EAGAIN | Errno::EBUSY | Errno::EINTR => {
+      Error::Transient(format!("failed to {}: {}", operation, error))
+    }
+    _ => Error::Internal(format!("failed to {}: {}", operation, error)),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, assert_matches::assert_matches};
+
+  #[test]
+  fn permission_errors() {
+    for errno in [Errno::EACCES, Errno::EPERM] {
+      assert_matches!(
+        syscall::<()>("setresuid", Err(errno)),
+        Err(Error::Permission(message)) if message == format!("failed to setresuid: {}", errno)
+      );
+    }
+  }
+
+  #[test]
+  fn transient_errors() {
+    for errno in [Errno::EAGAIN, Errno::EBUSY, Errno::EINTR] {
+      assert_matches!(
+        syscall::<()>("create child", Err(errno)),
+        Err(Error::Transient(message)) if message == format!("failed to create child: {}", errno)
+      );
+    }
+  }
+
+  #[test]
+  fn internal_errors() {
+    assert_matches!(
+      syscall::<()>("create pipe", Err(Errno::EMFILE)),
+      Err(Error::Internal(message)) if message.starts_with("failed to create pipe: EMFILE")
+    );
+  }
+
+  #[test]
+  fn success() {
+    assert_eq!(syscall("getpid", Ok(1)).unwrap(), 1);
+  }
+}
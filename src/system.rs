@@ -1,24 +1,139 @@
 use super::*;
 
-pub trait System: std::fmt::Debug {
+/// The parts of the metadata of a file that the sandbox checks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FileStatus {
+  pub directory: bool,
+  pub mode: u32,
+}
+
+/// The system calls and filesystem operations of the sandbox, so that its
+/// logic can be tested without root, see `InMemorySystem`.
+///
+/// The operations that set up the root of the sandbox return the raw error
+/// of the system call, which the caller turns into a message that says what
+/// it was trying to do. Likewise, reading and writing files, e.g. the
+/// controller files of control groups and the files in `/proc`, and creating,
+/// listing and removing single directories, e.g. control groups, returns the
+/// raw I/O error.
+pub trait System: std::fmt::Debug + Sync {
+  fn append(&self, path: &Utf8Path, contents: &str) -> Result;
+  fn chdir(&self, path: &Utf8Path) -> nix::Result<()>;
   fn chown(&self, path: &Utf8Path, uid: Option<Uid>, gid: Option<Gid>) -> Result;
+  fn chroot(&self, path: &Utf8Path) -> nix::Result<()>;
+  fn copy_tree(&self, source: &Utf8Path, destination: &Utf8Path) -> Result;
+  fn create_directory(&self, path: &Utf8Path) -> io::Result<()>;
   fn create_directory_with_mode(&self, path: &Utf8Path, mode: u32) -> Result;
+  fn effective_capabilities(&self) -> Result<u64>;
+  fn filesystem_type(&self, path: &Utf8Path) -> Result<FsType>;
   fn getegid(&self) -> Gid;
   fn geteuid(&self) -> Uid;
   fn getgid(&self) -> Gid;
   fn getuid(&self) -> Uid;
+  fn kill(&self, pid: Pid, signal: Signal) -> Result;
+  fn list_directory(&self, path: &Utf8Path) -> io::Result<Vec<Utf8PathBuf>>;
+  fn log(&self, message: &str);
+  fn mount(
+    &self,
+    source: Option<&str>,
+    target: &Utf8Path,
+    filesystem: Option<&str>,
+    flags: MsFlags,
+    data: Option<&str>,
+  ) -> nix::Result<()>;
+  fn mount_tmpfs(&self, path: &Utf8Path, size_mb: u32) -> Result;
+  fn open_lock(&self, path: &Utf8Path) -> Result<File>;
+  fn pivot_root(&self, new_root: &Utf8Path, put_old: &Utf8Path) -> nix::Result<()>;
+  fn prlimit(&self, pid: Pid, resource: Resource, limit: rlim_t) -> Result;
+  fn read_file(&self, path: &Utf8Path) -> io::Result<String>;
   fn recreate_directory_with_mode(&self, path: &Utf8Path, mode: u32) -> Result;
+  fn remove_directory(&self, path: &Utf8Path) -> Result;
+  fn remove_empty_directory(&self, path: &Utf8Path) -> io::Result<()>;
   fn setegid(&self, gid: u32) -> Result;
+  fn status(&self, path: &Utf8Path) -> Result<Option<FileStatus>>;
+  fn uid_map(&self) -> Result<String>;
   fn umask(&self, mask: Mode) -> Mode;
+  fn umount(&self, path: &Utf8Path, flags: MntFlags) -> nix::Result<()>;
+  fn unmount(&self, path: &Utf8Path) -> Result;
+  fn wait4(&self, pid: Pid) -> Result<(WaitStatus, ResourceUsage)>;
+  fn write_file(&self, path: &Utf8Path, contents: &str) -> io::Result<()>;
 }
 
 #[derive(Debug)]
 pub struct MaterialSystem;
 
 impl System for MaterialSystem {
+  /// Append `contents` with `O_APPEND`, so that lines appended by concurrent
+  /// sandboxes end up one after the other rather than overwriting each other.
+  fn append(&self, path: &Utf8Path, contents: &str) -> Result {
+    fs::OpenOptions::new()
+      .append(true)
+      .create(true)
+      .mode(0o600)
+      .open(path)?
+      .write_all(contents.as_bytes())?;
+
+    Ok(())
+  }
+
+  fn chdir(&self, path: &Utf8Path) -> nix::Result<()> {
+    chdir(path.as_std_path())
+  }
+
   fn chown(&self, path: &Utf8Path, uid: Option<Uid>, gid: Option<Gid>) -> Result {
-    chown(&PathBuf::from(path), uid, gid)
-      .map_err(|error| Error::Permission(format!("failed to chown `{}`: {}", path, error)))
+    syscall(
+      &format!("chown `{}`", path),
+      chown(&PathBuf::from(path), uid, gid),
+    )
+  }
+
+  fn chroot(&self, path: &Utf8Path) -> nix::Result<()> {
+    chroot(path.as_std_path())
+  }
+
+  /// Copy the contents of `source` into the existing directory `destination`,
+  /// keeping the owners and permissions of the files, directories and
+  /// symbolic links in it and leaving out everything else, e.g. fifos.
+  ///
+  /// `destination` itself gets the owner and permissions of `source`.
+  fn copy_tree(&self, source: &Utf8Path, destination: &Utf8Path) -> Result {
+    let metadata = fs::symlink_metadata(source)?;
+
+    fs::set_permissions(destination, metadata.permissions())?;
+
+    lchown(destination, Some(metadata.uid()), Some(metadata.gid()))?;
+
+    for entry in source.read_dir_utf8()? {
+      let entry = entry?;
+
+      let target = destination.join(entry.file_name());
+
+      let file_type = entry.file_type()?;
+
+      if file_type.is_dir() {
+        fs::create_dir(&target)?;
+        self.copy_tree(entry.path(), &target)?;
+        continue;
+      }
+
+      if file_type.is_file() {
+        fs::copy(entry.path(), &target)?;
+      } else if file_type.is_symlink() {
+        symlink(fs::read_link(entry.path())?, &target)?;
+      } else {
+        continue;
+      }
+
+      let metadata = fs::symlink_metadata(entry.path())?;
+
+      lchown(&target, Some(metadata.uid()), Some(metadata.gid()))?;
+    }
+
+    Ok(())
+  }
+
+  fn create_directory(&self, path: &Utf8Path) -> io::Result<()> {
+    fs::create_dir(path)
   }
 
   fn create_directory_with_mode(&self, path: &Utf8Path, mode: u32) -> Result {
@@ -27,6 +142,24 @@ impl System for MaterialSystem {
     Ok(())
   }
 
+  fn effective_capabilities(&self) -> Result<u64> {
+    let status = fs::read_to_string("/proc/self/status")?;
+
+    status
+      .lines()
+      .find_map(|line| line.strip_prefix("CapEff:"))
+      .and_then(|capabilities| u64::from_str_radix(capabilities.trim(), 16).ok())
+      .ok_or_else(|| Error::Internal("failed to read effective capabilities".into()))
+  }
+
+  fn filesystem_type(&self, path: &Utf8Path) -> Result<FsType> {
+    Ok(
+      statfs(path.as_std_path())
+        .map_err(|error| Error::Mount(format!("failed to stat `{}`: {}", path, error)))?
+        .filesystem_type(),
+    )
+  }
+
   fn getegid(&self) -> Gid {
     getegid()
   }
@@ -43,6 +176,85 @@ impl System for MaterialSystem {
     getuid()
   }
 
+  fn kill(&self, pid: Pid, signal: Signal) -> Result {
+    syscall(
+      &format!("send {signal} to process {pid}"),
+      kill(pid, signal),
+    )
+  }
+
+  fn list_directory(&self, path: &Utf8Path) -> io::Result<Vec<Utf8PathBuf>> {
+    path
+      .read_dir_utf8()?
+      .map(|entry| Ok(entry?.into_path()))
+      .collect()
+  }
+
+  fn log(&self, message: &str) {
+    eprintln!("{message}");
+  }
+
+  fn mount(
+    &self,
+    source: Option<&str>,
+    target: &Utf8Path,
+    filesystem: Option<&str>,
+    flags: MsFlags,
+    data: Option<&str>,
+  ) -> nix::Result<()> {
+    mount(source, target.as_std_path(), filesystem, flags, data)
+  }
+
+  fn mount_tmpfs(&self, path: &Utf8Path, size_mb: u32) -> Result {
+    if is_mount_point(path)? && self.filesystem_type(path)? == TMPFS_MAGIC {
+      return Ok(());
+    }
+
+    mount(
+      Some("isolate"),
+      path.as_std_path(),
+      Some("tmpfs"),
+      MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+      Some(format!("size={}m,mode=700", size_mb).as_str()),
+    )
+    .map_err(|error| Error::Mount(format!("failed to mount tmpfs at `{}`: {}", path, error)))
+  }
+
+  fn open_lock(&self, path: &Utf8Path) -> Result<File> {
+    Ok(
+      fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .mode(0o600)
+        .open(path)?,
+    )
+  }
+
+  fn pivot_root(&self, new_root: &Utf8Path, put_old: &Utf8Path) -> nix::Result<()> {
+    pivot_root(new_root.as_std_path(), put_old.as_std_path())
+  }
+
+  fn prlimit(&self, pid: Pid, resource: Resource, limit: rlim_t) -> Result {
+    let limit = libc::rlimit {
+      rlim_cur: limit,
+      rlim_max: limit,
+    };
+
+    // SAFETY: `limit` is a valid `rlimit` and the old limit is not read.
+    let result =
+      unsafe { libc::prlimit(pid.as_raw(), resource as _, &limit, std::ptr::null_mut()) };
+
+    syscall(
+      &format!("set {:?} of process {}", resource, pid),
+      Errno::result(result).map(drop),
+    )
+  }
+
+  fn read_file(&self, path: &Utf8Path) -> io::Result<String> {
+    fs::read_to_string(path)
+  }
+
   fn recreate_directory_with_mode(&self, path: &Utf8Path, mode: u32) -> Result {
     if path.exists() {
       fs::remove_dir_all(path)?;
@@ -51,12 +263,85 @@ impl System for MaterialSystem {
     self.create_directory_with_mode(path, mode)
   }
 
+  fn remove_directory(&self, path: &Utf8Path) -> Result {
+    fs::remove_dir_all(path)?;
+    Ok(())
+  }
+
+  fn remove_empty_directory(&self, path: &Utf8Path) -> io::Result<()> {
+    fs::remove_dir(path)
+  }
+
   fn setegid(&self, gid: u32) -> Result {
-    setegid(Gid::from_raw(gid))
-      .map_err(|error| Error::Permission(format!("failed to setegid: {}", error)))
+    syscall("setegid", setegid(Gid::from_raw(gid)))
+  }
+
+  fn status(&self, path: &Utf8Path) -> Result<Option<FileStatus>> {
+    match fs::metadata(path) {
+      Ok(metadata) => Ok(Some(FileStatus {
+        directory: metadata.is_dir(),
+        mode: metadata.permissions().mode(),
+      })),
+      Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+      Err(error) => Err(error.into()),
+    }
+  }
+
+  fn uid_map(&self) -> Result<String> {
+    Ok(fs::read_to_string("/proc/self/uid_map")?)
   }
 
   fn umask(&self, mask: Mode) -> Mode {
     umask(mask)
   }
+
+  fn umount(&self, path: &Utf8Path, flags: MntFlags) -> nix::Result<()> {
+    umount2(path.as_std_path(), flags)
+  }
+
+  fn unmount(&self, path: &Utf8Path) -> Result {
+    if !is_mount_point(path)? {
+      return Ok(());
+    }
+
+    umount2(path.as_std_path(), MntFlags::MNT_DETACH)
+      .map_err(|error| Error::Mount(format!("failed to unmount `{}`: {}", path, error)))
+  }
+
+  fn wait4(&self, pid: Pid) -> Result<(WaitStatus, ResourceUsage)> {
+    let mut status = 0;
+
+    // SAFETY: An all-zero `rusage` is a valid value.
+    let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+
+    loop {
+      // SAFETY: `status` and `usage` are valid for writes.
+      match Errno::result(unsafe { libc::wait4(pid.as_raw(), &mut status, 0, &mut usage) }) {
+        Err(Errno::EINTR) => continue,
+        result => {
+          syscall("wait for child", result)?;
+          break;
+        }
+      }
+    }
+
+    Ok((
+      syscall("wait for child", WaitStatus::from_raw(pid, status))?,
+      ResourceUsage::from_rusage(&usage),
+    ))
+  }
+
+  fn write_file(&self, path: &Utf8Path, contents: &str) -> io::Result<()> {
+    fs::write(path, contents)
+  }
+}
+
+/// Whether `path` is the root of a mounted filesystem, i.e. lives on a
+/// different device than its parent.
+fn is_mount_point(path: &Utf8Path) -> Result<bool> {
+  let Some(parent) = path.parent() else {
+    return Ok(true);
+  };
+
+  Ok(fs::metadata(path)?.dev() != fs::metadata(parent)?.dev())
 }
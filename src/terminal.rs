@@ -0,0 +1,89 @@
+use super::*;
+
+/// The controlling terminal of the caller, handed over to the program for
+/// the duration of a run with `ExecutionContext::tty_hack`.
+///
+/// The foreground process group and the attributes of the terminal are saved
+/// when it is acquired and restored when it is dropped, so they are restored
+/// however the run ends, even if it panics, and a program that mangles the
+/// terminal settings does not leave them mangled for the caller.
+#[derive(Debug)]
+pub(crate) struct Terminal {
+  /// The attributes of the terminal before the run.
+  attributes: Termios,
+  /// The controlling terminal.
+  file: File,
+  /// The foreground process group before the run.
+  foreground: Pid,
+}
+
+impl Terminal {
+  /// Open the controlling terminal of the calling process and save its state.
+  pub(crate) fn acquire() -> Result<Self> {
+    let file = File::options()
+      .read(true)
+      .write(true)
+      .open("/dev/tty")
+      .map_err(|error| {
+        Error::Config(format!(
+          "`tty_hack` requires a controlling terminal: {}",
+          error
+        ))
+      })?;
+
+    Ok(Self {
+      attributes: syscall("read terminal attributes", tcgetattr(&file))?,
+      foreground: syscall("read foreground process group", tcgetpgrp(&file))?,
+      file,
+    })
+  }
+
+  /// Move the calling process into a process group of its own and make that
+  /// the foreground process group of the terminal.
+  ///
+  /// Called in the child before it executes the program, so the program
+  /// never runs in the background.
+  pub(crate) fn hand_over(&self) -> Result {
+    syscall(
+      "create process group",
+      setpgid(Pid::from_raw(0), Pid::from_raw(0)),
+    )?;
+
+    Self::without_sigttou(|| syscall("hand over terminal", tcsetpgrp(&self.file, getpgrp())))
+  }
+
+  /// Run `f` with `SIGTTOU` blocked in the calling thread, since changing the
+  /// terminal from a background process group stops the process otherwise.
+  fn without_sigttou<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let mut blocked = SigSet::empty();
+
+    blocked.add(Signal::SIGTTOU);
+
+    let previous = syscall(
+      "block SIGTTOU",
+      blocked.thread_swap_mask(SigmaskHow::SIG_BLOCK),
+    )?;
+
+    let result = f();
+
+    syscall("restore signal mask", previous.thread_set_mask())?;
+
+    result
+  }
+}
+
+impl Drop for Terminal {
+  fn drop(&mut self) {
+    let _ = Self::without_sigttou(|| {
+      syscall(
+        "restore foreground process group",
+        tcsetpgrp(&self.file, self.foreground),
+      )?;
+
+      syscall(
+        "restore terminal attributes",
+        tcsetattr(&self.file, SetArg::TCSANOW, &self.attributes),
+      )
+    });
+  }
+}
@@ -0,0 +1,16 @@
+/// A system call made by the program, see `ExecutionContext::trace_syscalls`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct TracedSyscall {
+  /// The arguments the system call was made with.
+  pub arguments: [u64; 6],
+
+  /// The number of the system call on the architecture of the host, e.g.
+  /// `libc::SYS_openat`.
+  pub number: u64,
+
+  /// The value the system call returned, which is a negated `errno` if it
+  /// failed, or `None` if it never returned, e.g. because the program was
+  /// killed during the call or the call was `exit_group`.
+  pub result: Option<i64>,
+}
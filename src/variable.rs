@@ -54,6 +54,29 @@ impl Variable {
   }
 }
 
+impl FromStr for Variable {
+  type Err = Error;
+
+  /// Parse a variable rule: `KEY=VALUE` sets `KEY`, `KEY` inherits it from
+  /// the parent and `-KEY` clears it.
+  fn from_str(s: &str) -> Result<Self> {
+    let variable = match s.split_once('=') {
+      Some((key, value)) => Self::with_set_value(key, value),
+      None => match s.strip_prefix('-') {
+        Some(key) => Self::new(key, Action::Clear),
+        None => Self::new(s, Action::Inherit),
+      },
+    };
+
+    ensure!(
+      !variable.key.is_empty() && !variable.key.starts_with('-'),
+      Error::Config(format!("invalid variable `{}`", s))
+    );
+
+    Ok(variable)
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -107,6 +130,33 @@ mod tests {
     assert_eq!(set_var.get_value(), Some("value"));
   }
 
+  #[test]
+  fn from_str() {
+    assert_eq!(
+      "KEY=a=b".parse::<Variable>().unwrap(),
+      Variable::with_set_value("KEY", "a=b")
+    );
+
+    assert_eq!(
+      "KEY=".parse::<Variable>().unwrap(),
+      Variable::with_set_value("KEY", "")
+    );
+
+    assert_eq!(
+      "KEY".parse::<Variable>().unwrap(),
+      Variable::new("KEY", Action::Inherit)
+    );
+
+    assert_eq!(
+      "-KEY".parse::<Variable>().unwrap(),
+      Variable::new("KEY", Action::Clear)
+    );
+
+    for invalid in ["", "=value", "-", "-KEY=value", "--KEY"] {
+      assert!(invalid.parse::<Variable>().is_err(), "{invalid}");
+    }
+  }
+
   #[test]
   fn variable_equality() {
     let var1 = Variable::with_set_value("KEY", "value");
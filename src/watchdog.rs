@@ -0,0 +1,273 @@
+use super::*;
+
+/// How far the program had got when it signalled the end of its warmup, see
+/// `ExecutionContext::measure_after_warmup`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Split {
+  /// CPU time the program had used, not counting its children.
+  pub(crate) cpu_time: Duration,
+  /// Wall-clock time since the program was executed.
+  pub(crate) elapsed: Duration,
+}
+
+/// Kills the program once it runs out of time.
+///
+/// Rather than sleeping for a fixed interval, the watchdog waits on a pidfd of
+/// the program and a timerfd at the same time. It wakes up as soon as the
+/// program exits, on every `poll_interval_ms` tick to check the CPU time, and
+/// exactly at the wall-time deadline, so short wall-time limits are not
+/// overshot by up to a whole interval.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Watchdog {
+  /// CPU time after which the program is killed.
+  cpu_time_limit: Option<Duration>,
+  /// Time between two checks of the CPU time.
+  poll_interval: Duration,
+  /// Wall-clock time after which the program is killed.
+  wall_time_limit: Option<Duration>,
+}
+
+impl Watchdog {
+  /// The watchdog for a context that passed `ExecutionContext::validate`.
+  pub(crate) fn new(context: &ExecutionContext) -> Self {
+    let duration = |ms: f64| Duration::from_secs_f64(ms.max(0.0) / 1000.0);
+
+    Self {
+      cpu_time_limit: context
+        .limits
+        .time_limit_ms
+        .map(|time| duration(time + context.limits.extra_time_ms.unwrap_or(0.0))),
+      poll_interval: duration(context.poll_interval_ms),
+      wall_time_limit: context.limits.wall_time_limit_ms.map(duration),
+    }
+  }
+
+  /// Watch `child` until it exits, killing it if it exceeds a limit, where
+  /// wall-clock time counts from `start`, when the program was executed.
+  ///
+  /// Returns the limit the child was killed for, if it was, and the `Split`
+  /// of its warmup, if `warmup` became readable first. The child is not
+  /// reaped, so the caller still has to wait for it.
+  ///
+  /// Only the CPU time of `child` itself is checked, the CPU time of any
+  /// processes it spawned is limited by `RLIMIT_CPU` alone.
+  ///
+  /// Without a pidfd, the exit of the child is only noticed on the next tick.
+  pub(crate) fn watch(
+    &self,
+    system: &dyn System,
+    child: &Child,
+    start: Instant,
+    mut warmup: Option<BorrowedFd>,
+  ) -> Result<(Option<KillReason>, Option<Split>)> {
+    let timer = syscall(
+      "create watchdog timer",
+      TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::TFD_CLOEXEC),
+    )?;
+
+    let mut split = None;
+
+    loop {
+      let elapsed = start.elapsed();
+
+      if let Some(timeout) = self.check(elapsed, Self::cpu_time(system, child.pid())?) {
+        child.kill()?;
+        return Ok((Some(timeout), split));
+      }
+
+      syscall(
+        "arm watchdog timer",
+        timer.set(
+          Expiration::OneShot(TimeSpec::from_duration(self.next_wakeup(elapsed))),
+          TimerSetTimeFlags::empty(),
+        ),
+      )?;
+
+      let mut fds = vec![PollFd::new(timer.as_fd(), PollFlags::POLLIN)];
+
+      let pidfd = child.pidfd().map(|pidfd| {
+        fds.push(PollFd::new(pidfd, PollFlags::POLLIN));
+        fds.len() - 1
+      });
+
+      let warmup_index = warmup.map(|warmup| {
+        fds.push(PollFd::new(warmup, PollFlags::POLLIN));
+        fds.len() - 1
+      });
+
+      match poll(&mut fds, PollTimeout::NONE) {
+        Err(Errno::EINTR) => continue,
+        result => syscall("wait for child or timer", result)?,
+      };
+
+      // The pipe is only watched until the program writes to it, or until
+      // it is closed without the program doing so.
+      if let Some(index) = warmup_index {
+        let events = fds[index].revents().unwrap_or(PollFlags::empty());
+
+        if events.contains(PollFlags::POLLIN) {
+          split = Some(Split {
+            cpu_time: Self::cpu_time(system, child.pid())?,
+            elapsed: start.elapsed(),
+          });
+        }
+
+        if !events.is_empty() {
+          warmup = None;
+        }
+      }
+
+      let exited = match pidfd {
+        Some(index) => fds[index].any().unwrap_or(false),
+        None => child.exited()?,
+      };
+
+      if exited {
+        return Ok((None, split));
+      }
+    }
+  }
+
+  /// The limit the program should be killed for after running for `elapsed`
+  /// and using `cpu_time`, if any.
+  fn check(&self, elapsed: Duration, cpu_time: Duration) -> Option<KillReason> {
+    if self.wall_time_limit.is_some_and(|limit| elapsed >= limit) {
+      return Some(KillReason::WallTimeout);
+    }
+
+    if self.cpu_time_limit.is_some_and(|limit| cpu_time > limit) {
+      return Some(KillReason::CpuTimeout);
+    }
+
+    None
+  }
+
+  /// How long to sleep for after running for `elapsed`.
+  fn next_wakeup(&self, elapsed: Duration) -> Duration {
+    let next = match self.wall_time_limit {
+      Some(limit) => self.poll_interval.min(limit.saturating_sub(elapsed)),
+      None => self.poll_interval,
+    };
+
+    // A zero expiration would disarm the timer instead of firing right away.
+    next.max(Duration::from_nanos(1))
+  }
+
+  /// The CPU time used by `process` so far, read from `/proc`.
+  pub(crate) fn cpu_time(system: &dyn System, process: Pid) -> Result<Duration> {
+    let stat = system.read_file(format!("/proc/{}/stat", process).as_ref())?;
+
+    Self::parse_cpu_time(
+      &stat,
+      syscall("read clock ticks", sysconf(SysconfVar::CLK_TCK))?,
+    )
+  }
+
+  fn parse_cpu_time(stat: &str, ticks_per_second: Option<i64>) -> Result<Duration> {
+    let invalid = || Error::Internal(format!("unexpected process stat `{}`", stat.trim()));
+
+    // The command name may contain spaces and parentheses, so the fields are
+    // counted from the last closing parenthesis, after which comes the third
+    // field (the state). User and system time are the 14th and 15th fields.
+    let fields = stat[stat.rfind(')').ok_or_else(invalid)? + 1..]
+      .split_whitespace()
+      .skip(11)
+      .take(2)
+      .map(|field| field.parse::<u64>().map_err(|_| invalid()))
+      .collect::<Result<Vec<_>>>()?;
+
+    ensure!(fields.len() == 2, invalid());
+
+    let ticks_per_second = ticks_per_second
+      .filter(|ticks| *ticks > 0)
+      .ok_or_else(|| Error::Internal("clock ticks per second are unknown".into()))?;
+
+    Ok(Duration::from_secs_f64(
+      (fields[0] + fields[1]) as f64 / ticks_per_second as f64,
+    ))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, assert_matches::assert_matches};
+
+  fn watchdog() -> Watchdog {
+    Watchdog::new(
+      &ExecutionContext::default()
+        .time_limit_ms(100.0)
+        .extra_time_ms(50.0)
+        .wall_time_limit_ms(500.0)
+        .poll_interval_ms(20.0),
+    )
+  }
+
+  #[test]
+  fn limits() {
+    assert_eq!(
+      watchdog(),
+      Watchdog {
+        cpu_time_limit: Some(Duration::from_millis(150)),
+        poll_interval: Duration::from_millis(20),
+        wall_time_limit: Some(Duration::from_millis(500)),
+      }
+    );
+  }
+
+  #[test]
+  fn check() {
+    let watchdog = watchdog();
+
+    assert_eq!(
+      watchdog.check(Duration::from_millis(100), Duration::from_millis(150)),
+      None
+    );
+
+    assert_eq!(
+      watchdog.check(Duration::from_millis(100), Duration::from_millis(151)),
+      Some(KillReason::CpuTimeout)
+    );
+
+    assert_eq!(
+      watchdog.check(Duration::from_millis(500), Duration::ZERO),
+      Some(KillReason::WallTimeout)
+    );
+  }
+
+  #[test]
+  fn next_wakeup() {
+    let watchdog = watchdog();
+
+    assert_eq!(
+      watchdog.next_wakeup(Duration::ZERO),
+      Duration::from_millis(20)
+    );
+
+    assert_eq!(
+      watchdog.next_wakeup(Duration::from_millis(495)),
+      Duration::from_millis(5)
+    );
+
+    assert_eq!(
+      watchdog.next_wakeup(Duration::from_millis(600)),
+      Duration::from_nanos(1)
+    );
+  }
+
+  #[test]
+  fn parse_cpu_time() {
+    assert_eq!(
+      Watchdog::parse_cpu_time(
+        "42 (a) b) R 1 42 42 0 -1 4194304 100 0 0 0 150 50 0 0 20 0 1 0",
+        Some(100)
+      )
+      .unwrap(),
+      Duration::from_secs(2)
+    );
+
+    assert_matches!(
+      Watchdog::parse_cpu_time("42 (sh) R 1", Some(100)),
+      Err(Error::Internal(message)) if message.contains("unexpected process stat")
+    );
+  }
+}
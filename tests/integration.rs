@@ -3,12 +3,138 @@
 use {
   assert_matches::assert_matches,
   camino::Utf8PathBuf,
-  isolate::{Config, Environment, Error, Sandbox},
-  nix::unistd::{geteuid, seteuid, Uid},
-  std::{fs, os::unix::fs::PermissionsExt, path::PathBuf},
+  isolate::{
+    Config, Environment, Error, ExecutionContext, ExecutionResult, KillReason, Mount, MountOptions,
+    Sandbox, Status, Stdin,
+  },
+  nix::{
+    libc,
+    pty::openpty,
+    sys::{
+      signal::Signal,
+      statfs::{statfs, TMPFS_MAGIC},
+      termios::{tcgetattr, LocalFlags},
+      wait::{waitpid, WaitStatus},
+    },
+    unistd::{
+      chown, close, dup2, fork, geteuid, getgid, getpgrp, getuid, seteuid, setsid, tcgetpgrp,
+      ttyname, ForkResult, Uid,
+    },
+  },
+  std::{
+    fs,
+    os::{
+      fd::AsRawFd,
+      unix::fs::{MetadataExt, PermissionsExt},
+    },
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime},
+  },
   tempfile::TempDir,
 };
 
+/// Make `path` and its ancestors writable only by root while keeping them
+/// traversable by the sandbox user, returning the original permissions.
+fn restrict_ancestors(path: &Path) -> Vec<(PathBuf, fs::Permissions)> {
+  let ancestor_permissions = path
+    .ancestors()
+    .map(|path| {
+      (
+        path.to_path_buf(),
+        fs::metadata(path).unwrap().permissions(),
+      )
+    })
+    .collect();
+
+  for ancestor in path.ancestors() {
+    fs::set_permissions(ancestor, fs::Permissions::from_mode(0o755)).unwrap();
+  }
+
+  ancestor_permissions
+}
+
+fn restore_ancestors(ancestor_permissions: Vec<(PathBuf, fs::Permissions)>) {
+  for (path, permissions) in ancestor_permissions {
+    fs::set_permissions(path, permissions).unwrap();
+  }
+}
+
+fn with_sandbox<T>(config: Config, f: impl FnOnce(&mut Sandbox) -> T) -> T {
+  let temp_dir = TempDir::new().unwrap();
+
+  let ancestor_permissions = restrict_ancestors(temp_dir.path());
+
+  let environment = Environment {
+    lock_root: Utf8PathBuf::from_path_buf(temp_dir.path().join("locks")).unwrap(),
+    sandbox_root: Utf8PathBuf::from_path_buf(temp_dir.path().join("sandbox_root")).unwrap(),
+    ..Default::default()
+  };
+
+  let mut sandbox = Sandbox::try_from((config, &environment)).unwrap();
+
+  sandbox.initialize().unwrap();
+
+  let value = f(&mut sandbox);
+
+  restore_ancestors(ancestor_permissions);
+
+  value
+}
+
+fn execute(context: ExecutionContext) -> ExecutionResult {
+  with_sandbox(Config::default(), |sandbox| {
+    sandbox.execute(context).unwrap()
+  })
+}
+
+#[test]
+fn execute_with_audit_log() {
+  let temp_dir = TempDir::new().unwrap();
+
+  let ancestor_permissions = restrict_ancestors(temp_dir.path());
+
+  let audit_log = Utf8PathBuf::from_path_buf(temp_dir.path().join("audit.jsonl")).unwrap();
+
+  let environment = Environment {
+    audit_log: Some(audit_log.clone()),
+    lock_root: Utf8PathBuf::from_path_buf(temp_dir.path().join("locks")).unwrap(),
+    sandbox_root: Utf8PathBuf::from_path_buf(temp_dir.path().join("sandbox_root")).unwrap(),
+    ..Default::default()
+  };
+
+  let mut sandbox = Sandbox::try_from((Config::default(), &environment)).unwrap();
+
+  sandbox.initialize().unwrap();
+
+  for script in ["exit 0", "exit 1"] {
+    sandbox
+      .execute(ExecutionContext::new(
+        "/bin/sh".into(),
+        Some(vec!["-c", script]),
+      ))
+      .unwrap();
+  }
+
+  restore_ancestors(ancestor_permissions);
+
+  let log = fs::read_to_string(&audit_log).unwrap();
+
+  let lines = log.lines().collect::<Vec<_>>();
+
+  assert_eq!(lines.len(), 2, "{log}");
+
+  assert!(lines[0].starts_with(r#"{"arguments":["-c","exit 0"],"limits":{"#));
+  assert!(lines[0].contains(r#""program":"/bin/sh","sandbox_id":0,"status":"OK""#));
+  assert!(lines[1].contains(r#""status":"RE""#));
+
+  assert_eq!(
+    fs::metadata(&audit_log).unwrap().permissions().mode() & 0o777,
+    0o600
+  );
+}
+
 #[test]
 fn sandbox_construction_as_non_root() {
   let original_euid = geteuid();
@@ -77,6 +203,7 @@ fn sandbox_initialization_creates_sandbox_directories() {
   let sandbox_root = Utf8PathBuf::from_path_buf(temp_dir.path().join("sandbox_root")).unwrap();
 
   let environment = Environment {
+    lock_root: Utf8PathBuf::from_path_buf(temp_dir.path().join("locks")).unwrap(),
     sandbox_root: sandbox_root.clone(),
     ..Default::default()
   };
@@ -86,7 +213,7 @@ fn sandbox_initialization_creates_sandbox_directories() {
     ..Default::default()
   };
 
-  let sandbox = Sandbox::try_from((config, &environment)).unwrap();
+  let mut sandbox = Sandbox::try_from((config, &environment)).unwrap();
 
   sandbox.initialize().unwrap();
 
@@ -103,12 +230,12 @@ fn sandbox_initialization_creates_sandbox_directories() {
 
   assert!(sandbox.directory().join("box").exists());
 
-  let metadata = fs::metadata(sandbox.directory().join("box"))
-    .unwrap()
-    .permissions()
-    .mode();
+  let metadata = fs::metadata(sandbox.directory().join("box")).unwrap();
 
-  assert_eq!(metadata & 0o777, 0o700);
+  assert_eq!(metadata.permissions().mode() & 0o777, 0o700);
+
+  assert_eq!(metadata.uid(), sandbox.uid().as_raw());
+  assert_eq!(metadata.gid(), sandbox.gid().as_raw());
 
   for (path, permissions) in ancestor_permissions {
     fs::set_permissions(path, permissions).unwrap();
@@ -122,6 +249,7 @@ fn sandbox_initialization_fails_on_bad_ancestor_permissions() {
   let sandbox_root = Utf8PathBuf::from_path_buf(temp_dir.path().join("sandbox_root")).unwrap();
 
   let environment = Environment {
+    lock_root: Utf8PathBuf::from_path_buf(temp_dir.path().join("locks")).unwrap(),
     sandbox_root: sandbox_root.clone(),
     ..Default::default()
   };
@@ -131,10 +259,1466 @@ fn sandbox_initialization_fails_on_bad_ancestor_permissions() {
     ..Default::default()
   };
 
-  let sandbox = Sandbox::try_from((config, &environment)).unwrap();
+  let mut sandbox = Sandbox::try_from((config, &environment)).unwrap();
 
   assert!(matches!(
     sandbox.initialize(),
     Err(Error::Permission(msg)) if msg.contains("must be writable only by root")
   ));
 }
+
+#[test]
+fn sandbox_initialization_creates_lock_root() {
+  let temp_dir = TempDir::new().unwrap();
+
+  let ancestor_permissions = restrict_ancestors(temp_dir.path());
+
+  let lock_root = Utf8PathBuf::from_path_buf(temp_dir.path().join("run/locks")).unwrap();
+
+  let environment = Environment {
+    lock_root: lock_root.clone(),
+    sandbox_root: Utf8PathBuf::from_path_buf(temp_dir.path().join("sandbox_root")).unwrap(),
+    ..Default::default()
+  };
+
+  let mut sandbox = Sandbox::try_from((Config::default(), &environment)).unwrap();
+
+  sandbox.initialize().unwrap();
+
+  let mode = fs::metadata(&lock_root).unwrap().permissions().mode();
+
+  assert_eq!(mode & 0o777, 0o700);
+
+  restore_ancestors(ancestor_permissions);
+}
+
+#[test]
+fn sandbox_initialization_fails_on_bad_lock_root_ancestor_permissions() {
+  let temp_dir = TempDir::new().unwrap();
+
+  let ancestor_permissions = restrict_ancestors(temp_dir.path());
+
+  let shared = temp_dir.path().join("shared");
+
+  fs::create_dir(&shared).unwrap();
+
+  fs::set_permissions(&shared, fs::Permissions::from_mode(0o777)).unwrap();
+
+  let environment = Environment {
+    lock_root: Utf8PathBuf::from_path_buf(shared.join("locks")).unwrap(),
+    sandbox_root: Utf8PathBuf::from_path_buf(temp_dir.path().join("sandbox_root")).unwrap(),
+    ..Default::default()
+  };
+
+  let mut sandbox = Sandbox::try_from((Config::default(), &environment)).unwrap();
+
+  assert_matches!(
+    sandbox.initialize(),
+    Err(Error::Permission(message)) if message.contains("shared must be writable only by root")
+  );
+
+  restore_ancestors(ancestor_permissions);
+}
+
+#[test]
+fn execute_successful_program() {
+  let result = execute(ExecutionContext::new("/bin/true".into(), None));
+
+  assert_matches!(result.status, Status::Ok);
+  assert_eq!(result.exit_code, Some(0));
+}
+
+#[test]
+fn execute_failing_program() {
+  let result = execute(ExecutionContext::new("/bin/false".into(), None));
+
+  assert_matches!(result.status, Status::RuntimeError);
+  assert_eq!(result.exit_code, Some(1));
+}
+
+#[test]
+fn execute_reports_resource_limit_failures() {
+  // The kernel refuses an open files limit above `fs.nr_open`.
+  let result = execute(ExecutionContext::new("/bin/true".into(), None).open_files_limit(u32::MAX));
+
+  assert_matches!(result.status, Status::InternalError);
+  assert!(result.status_message.contains("RLIMIT_NOFILE"));
+}
+
+#[test]
+fn sandbox_initialization_mounts_tmpfs_sandbox_root() {
+  let temp_dir = TempDir::new().unwrap();
+
+  let ancestor_permissions = restrict_ancestors(temp_dir.path());
+
+  let sandbox_root = Utf8PathBuf::from_path_buf(temp_dir.path().join("sandbox_root")).unwrap();
+
+  let environment = Environment {
+    lock_root: Utf8PathBuf::from_path_buf(temp_dir.path().join("locks")).unwrap(),
+    sandbox_root: sandbox_root.clone(),
+    ..Default::default()
+  }
+  .with_tmpfs_sandbox_root(16);
+
+  let mut sandbox = Sandbox::try_from((Config::default(), &environment)).unwrap();
+
+  sandbox.initialize().unwrap();
+
+  assert_eq!(
+    statfs(sandbox_root.as_std_path())
+      .unwrap()
+      .filesystem_type(),
+    TMPFS_MAGIC
+  );
+
+  assert!(sandbox.directory().join("box").exists());
+
+  environment.teardown().unwrap();
+
+  assert!(!sandbox.directory().exists());
+
+  restore_ancestors(ancestor_permissions);
+}
+
+#[test]
+fn execute_in_sandbox_root() {
+  let result = execute(ExecutionContext::new(
+    "/bin/sh".into(),
+    Some(vec!["-c", "test -d /box && test ! -e /root"]),
+  ));
+
+  assert_matches!(result.status, Status::Ok);
+}
+
+#[test]
+fn execute_with_writable_tmp() {
+  let result = execute(ExecutionContext::new(
+    "/bin/sh".into(),
+    Some(vec!["-c", ": > /tmp/file"]),
+  ));
+
+  assert_matches!(result.status, Status::Ok);
+}
+
+#[test]
+fn execute_with_strict_readonly() {
+  let result = execute(
+    ExecutionContext::new("/bin/sh".into(), Some(vec!["-c", "test ! -e /tmp"]))
+      .strict_readonly(true),
+  );
+
+  assert_matches!(result.status, Status::Ok);
+}
+
+#[test]
+fn execute_appending_to_stdout() {
+  with_sandbox(Config::default(), |sandbox| {
+    for _ in 0..2 {
+      let context = ExecutionContext::new("/bin/sh".into(), Some(vec!["-c", "echo run"]))
+        .stdout(Some("/tmp/out".into()))
+        .stdout_append(true);
+
+      assert_matches!(sandbox.execute(context).unwrap().status, Status::Ok);
+    }
+
+    let out = sandbox.directory().join("temporary/tmp/out");
+
+    assert_eq!(fs::read_to_string(&out).unwrap(), "run\nrun\n");
+
+    let context = ExecutionContext::new("/bin/sh".into(), Some(vec!["-c", "echo run"]))
+      .stdout(Some("/tmp/out".into()));
+
+    assert_matches!(sandbox.execute(context).unwrap().status, Status::Ok);
+
+    assert_eq!(fs::read_to_string(&out).unwrap(), "run\n");
+  });
+}
+
+#[test]
+fn execute_in_box_by_default() {
+  with_sandbox(Config::default(), |sandbox| {
+    fs::write(sandbox.directory().join("box/input"), "input\n").unwrap();
+
+    let result = sandbox
+      .execute(ExecutionContext::new("/bin/cat".into(), Some(vec!["input"])).capture_output(true))
+      .unwrap();
+
+    assert_matches!(result.status, Status::Ok, "{}", result.stderr);
+    assert_eq!(result.stdout, "input\n");
+  });
+}
+
+#[test]
+fn execute_batch() {
+  with_sandbox(Config::default(), |sandbox| {
+    for (name, input) in [("first", "1\n"), ("second", "2\n")] {
+      fs::write(sandbox.directory().join("box").join(name), input).unwrap();
+    }
+
+    let context = ExecutionContext::new(
+      "/bin/sh".into(),
+      Some(vec!["-c", "read -r n; echo $n >> seen; cat seen"]),
+    )
+    .capture_output(true)
+    .process_limit(4);
+
+    let results = sandbox
+      .execute_batch(
+        &context,
+        [
+          Stdin::File("first".into()),
+          Stdin::File("second".into()),
+          Stdin::Null,
+        ],
+      )
+      .unwrap();
+
+    for result in &results {
+      assert_matches!(result.status, Status::Ok, "{}", result.stderr);
+    }
+
+    assert_eq!(
+      results
+        .iter()
+        .map(|result| result.stdout.as_str())
+        .collect::<Vec<_>>(),
+      ["1\n", "1\n2\n", "1\n2\n\n"]
+    );
+  });
+}
+
+#[test]
+fn execute_batch_resetting_box() {
+  with_sandbox(Config::default(), |sandbox| {
+    let context = |script| {
+      ExecutionContext::new("/bin/sh".into(), Some(vec!["-c", script]))
+        .capture_output(true)
+        .process_limit(4)
+    };
+
+    let compiled = sandbox
+      .execute(context("echo compiled > artifact"))
+      .unwrap();
+
+    assert_matches!(compiled.status, Status::Ok, "{}", compiled.stderr);
+
+    let results = sandbox
+      .execute_batch(
+        &context("cat artifact; echo run >> seen; cat seen").reset_box_between_runs(true),
+        [Stdin::Null, Stdin::Null, Stdin::Null],
+      )
+      .unwrap();
+
+    for result in &results {
+      assert_matches!(result.status, Status::Ok, "{}", result.stderr);
+      assert_eq!(result.stdout, "compiled\nrun\n");
+    }
+
+    assert_eq!(results.len(), 3);
+
+    assert!(!sandbox.directory().join("snapshot").exists());
+  });
+}
+
+#[test]
+fn execute_with_redirects_inside_sandbox() {
+  with_sandbox(Config::default(), |sandbox| {
+    fs::write(sandbox.directory().join("box/input"), "input\n").unwrap();
+
+    let context =
+      || ExecutionContext::new("/bin/cat".into(), None).working_directory(Some("/box".into()));
+
+    let result = sandbox
+      .execute(
+        context()
+          .stdin(Utf8PathBuf::from("input"))
+          .stdout(Some("output".into())),
+      )
+      .unwrap();
+
+    assert_matches!(result.status, Status::Ok, "{}", result.status_message);
+
+    assert_eq!(
+      fs::read_to_string(sandbox.directory().join("box/output")).unwrap(),
+      "input\n"
+    );
+
+    // The host's `/etc/passwd` is not mounted into the sandbox.
+    assert!(Path::new("/etc/passwd").exists());
+
+    for (context, message) in [
+      (
+        context().stdin(Utf8PathBuf::from("/etc/passwd")),
+        "stdin `/etc/passwd` does not exist inside the sandbox",
+      ),
+      (
+        context().stdin(Utf8PathBuf::from("missing")),
+        "stdin `missing` does not exist inside the sandbox",
+      ),
+      (
+        context().stdout(Some("missing/output".into())),
+        "directory of stdout `missing/output` does not exist inside the sandbox",
+      ),
+      (
+        context().stderr(Some("/etc/error".into())),
+        "directory of stderr `/etc/error` does not exist inside the sandbox",
+      ),
+    ] {
+      assert_matches!(
+        sandbox.execute(context),
+        Err(Error::Config(actual)) if actual == message
+      );
+    }
+  });
+}
+
+#[test]
+fn execute_rejects_append_without_redirect() {
+  with_sandbox(Config::default(), |sandbox| {
+    let context = ExecutionContext::new("/bin/true".into(), None).stdout_append(true);
+
+    assert_matches!(
+      sandbox.execute(context),
+      Err(Error::Config(message)) if message.contains("`stdout_append` requires `stdout`")
+    );
+  });
+}
+
+#[test]
+fn execute_capturing_output() {
+  let result = execute(
+    ExecutionContext::new("/bin/sh".into(), Some(vec!["-c", "echo out; echo err >&2"]))
+      .capture_output(true),
+  );
+
+  assert_matches!(result.status, Status::Ok);
+
+  assert_eq!(result.stdout, "out\n");
+  assert_eq!(result.stderr, "err\n");
+}
+
+#[test]
+fn execute_capturing_output_into_file() {
+  with_sandbox(Config::default(), |sandbox| {
+    let context = ExecutionContext::new("/bin/sh".into(), Some(vec!["-c", "echo out"]))
+      .capture_output(true)
+      .stdout(Some("/tmp/out".into()));
+
+    let result = sandbox.execute(context).unwrap();
+
+    assert_matches!(result.status, Status::Ok);
+
+    assert_eq!(result.stdout, "out\n");
+
+    assert_eq!(
+      fs::read_to_string(sandbox.directory().join("temporary/tmp/out")).unwrap(),
+      "out\n"
+    );
+  });
+}
+
+#[test]
+fn execute_past_wall_time_limit() {
+  let result = execute(
+    ExecutionContext::new("/bin/sleep".into(), Some(vec!["5"]))
+      .wall_time_limit_ms(100.0)
+      .poll_interval_ms(5.0),
+  );
+
+  assert_matches!(result.status, Status::WallTimeout);
+
+  assert_eq!(result.status_message, "Time limit exceeded (wall clock)");
+
+  assert!(result.terminated_by_sandbox);
+
+  assert_eq!(result.kill_reason, Some(KillReason::WallTimeout));
+
+  assert!(result.wall_time_ms < 1000.0, "{}", result.wall_time_ms);
+}
+
+#[test]
+fn execute_past_time_limit() {
+  let result = execute(
+    ExecutionContext::new("/bin/sh".into(), Some(vec!["-c", "while :; do :; done"]))
+      .time_limit_ms(100.0)
+      .extra_time_ms(0.0),
+  );
+
+  assert_matches!(result.status, Status::Timeout);
+
+  assert_eq!(result.status_message, "Time limit exceeded");
+
+  assert_eq!(result.kill_reason, Some(KillReason::CpuTimeout));
+}
+
+#[test]
+fn execute_from_no_exec_mount() {
+  let binaries = TempDir::new().unwrap();
+
+  fs::set_permissions(binaries.path(), fs::Permissions::from_mode(0o755)).unwrap();
+
+  fs::copy("/bin/true", binaries.path().join("true")).unwrap();
+
+  let binaries = binaries.path().to_str().unwrap();
+
+  let run = |no_exec| {
+    execute(
+      ExecutionContext::new("/binaries/true".into(), None).mount(
+        Mount::new(
+          "/binaries",
+          Some(binaries),
+          MountOptions {
+            no_exec,
+            ..Default::default()
+          },
+        )
+        .unwrap(),
+      ),
+    )
+  };
+
+  assert_matches!(run(false).status, Status::Ok);
+
+  let result = run(true);
+
+  assert_matches!(result.status, Status::InternalError);
+
+  assert!(
+    result.status_message.contains("EACCES"),
+    "{}",
+    result.status_message
+  );
+}
+
+#[test]
+fn execute_crashing_program_without_core() {
+  with_sandbox(Config::default(), |sandbox| {
+    // The program is the init process of its PID namespace, which ignores
+    // signals sent by itself, so it has to crash for real by overflowing a
+    // tiny stack.
+    let result = sandbox
+      .execute(ExecutionContext::new("/bin/true".into(), None).stack_limit_kb(4))
+      .unwrap();
+
+    assert_matches!(result.status, Status::SignalError);
+
+    assert_eq!(result.termination_signal, 11);
+
+    assert_eq!(result.exit_code, None);
+
+    assert!(!result.core_dumped);
+
+    assert_eq!(
+      fs::read_dir(sandbox.directory().join("box"))
+        .unwrap()
+        .count(),
+      0
+    );
+  });
+}
+
+#[test]
+fn execute_crashing_program_with_core() {
+  with_sandbox(Config::default(), |sandbox| {
+    let result = sandbox
+      .execute(
+        ExecutionContext::new("/bin/true".into(), None)
+          .core_size_limit_kb(64 * 1024)
+          .stack_limit_kb(4)
+          .working_directory(Some("/box".into())),
+      )
+      .unwrap();
+
+    assert_matches!(result.status, Status::SignalError);
+
+    assert_eq!(result.termination_signal, 11);
+
+    assert!(result.core_dumped);
+
+    assert!(sandbox.directory().join("box/core").exists());
+  });
+}
+
+#[test]
+fn execute_with_default_signal_dispositions() {
+  let result = execute(
+    ExecutionContext::new(
+      "/bin/sh".into(),
+      Some(vec![
+        "-c",
+        "while read -r key value; do case $key in SigBlk:|SigIgn:) echo $value;; esac; done < /proc/self/status",
+      ]),
+    )
+    .capture_output(true),
+  );
+
+  assert_matches!(result.status, Status::Ok);
+
+  let masks = result
+    .stdout
+    .lines()
+    .map(|mask| u64::from_str_radix(mask, 16).unwrap())
+    .collect::<Vec<_>>();
+
+  let (blocked, ignored) = (masks[0], masks[1]);
+
+  assert_eq!(blocked, 0);
+
+  assert_eq!(ignored & (1 << (Signal::SIGPIPE as u64 - 1)), 0);
+}
+
+#[test]
+fn environment_allocates_free_sandboxes() {
+  let temp_dir = TempDir::new().unwrap();
+
+  let ancestor_permissions = restrict_ancestors(temp_dir.path());
+
+  let environment = Environment {
+    lock_root: Utf8PathBuf::from_path_buf(temp_dir.path().join("locks")).unwrap(),
+    num_sandboxes: 2,
+    sandbox_root: Utf8PathBuf::from_path_buf(temp_dir.path().join("sandbox_root")).unwrap(),
+    ..Default::default()
+  };
+
+  let first = environment.allocate_sandbox().unwrap();
+
+  let mut second = environment.allocate_sandbox().unwrap();
+
+  assert_eq!((first.id(), second.id()), (0, 1));
+
+  assert_matches!(
+    environment.allocate_sandbox(),
+    Err(Error::Config(message)) if message == "no free sandbox available"
+  );
+
+  let mut busy = Sandbox::try_from((
+    Config {
+      sandbox_id: Some(1),
+      ..Default::default()
+    },
+    &environment,
+  ))
+  .unwrap();
+
+  assert_matches!(busy.initialize(), Err(Error::Busy(1)));
+
+  second.cleanup().unwrap();
+
+  assert_eq!(environment.allocate_sandbox().unwrap().id(), 1);
+
+  restore_ancestors(ancestor_permissions);
+}
+
+#[test]
+fn cleanup_keeping_box() {
+  with_sandbox(Config::default(), |sandbox| {
+    let sandbox_box = sandbox.directory().join("box");
+
+    fs::write(sandbox_box.join("output"), "output").unwrap();
+
+    chown(
+      sandbox_box.join("output").as_std_path(),
+      Some(sandbox.uid()),
+      None,
+    )
+    .unwrap();
+
+    assert_eq!(sandbox.cleanup_keeping_box().unwrap(), sandbox_box);
+
+    assert_eq!(
+      fs::read_dir(sandbox.directory())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect::<Vec<_>>(),
+      ["box"]
+    );
+
+    assert_eq!(
+      fs::metadata(sandbox_box.join("output")).unwrap().uid(),
+      geteuid().as_raw()
+    );
+
+    assert_matches!(sandbox.cleanup(), Err(Error::NotInitialized));
+  });
+}
+
+#[test]
+fn execute_without_aslr() {
+  let personality = |disable_aslr| {
+    execute(
+      ExecutionContext::new(
+        "/bin/sh".into(),
+        Some(vec![
+          "-c",
+          "read -r persona < /proc/self/personality; echo $persona",
+        ]),
+      )
+      .capture_output(true)
+      .disable_aslr(disable_aslr),
+    )
+    .stdout
+  };
+
+  assert_eq!(personality(false), "00000000\n");
+  assert_eq!(personality(true), "00040000\n");
+}
+
+#[test]
+fn execute_as_invoking_user() {
+  with_sandbox(Config::default(), |sandbox| {
+    let id = |run_as_invoking_user| {
+      sandbox
+        .execute(
+          ExecutionContext::new("/bin/sh".into(), Some(vec!["-c", "id -u; id -g"]))
+            .capture_output(true)
+            .process_limit(4)
+            .run_as_invoking_user(run_as_invoking_user),
+        )
+        .unwrap()
+        .stdout
+    };
+
+    assert_eq!(id(false), format!("{}\n{}\n", sandbox.uid(), sandbox.gid()));
+
+    assert_eq!(id(true), format!("{}\n{}\n", getuid(), getgid()));
+  });
+}
+
+#[test]
+fn execute_hands_output_to_invoker() {
+  let temp_dir = TempDir::new().unwrap();
+
+  let ancestor_permissions = restrict_ancestors(temp_dir.path());
+
+  let environment = Environment {
+    lock_root: Utf8PathBuf::from_path_buf(temp_dir.path().join("locks")).unwrap(),
+    sandbox_root: Utf8PathBuf::from_path_buf(temp_dir.path().join("sandbox_root")).unwrap(),
+    ..Default::default()
+  };
+
+  let config = Config {
+    as_gid: Some(1234),
+    as_uid: Some(1234),
+    ..Default::default()
+  };
+
+  let mut sandbox = Sandbox::try_from((config, &environment)).unwrap();
+
+  sandbox.initialize().unwrap();
+
+  let write = || {
+    sandbox
+      .execute(
+        ExecutionContext::new(
+          "/bin/sh".into(),
+          Some(vec!["-c", "echo hello >> /box/output"]),
+        )
+        .process_limit(4),
+      )
+      .unwrap()
+  };
+
+  assert_matches!(write().status, Status::Ok);
+
+  // The sandbox user gets the box back for the next run.
+  assert_matches!(write().status, Status::Ok);
+
+  for path in ["box", "box/output"] {
+    let metadata = fs::metadata(sandbox.directory().join(path)).unwrap();
+
+    assert_eq!((metadata.uid(), metadata.gid()), (1234, 1234));
+  }
+
+  assert_eq!(
+    fs::read_to_string(sandbox.directory().join("box/output")).unwrap(),
+    "hello\nhello\n"
+  );
+
+  restore_ancestors(ancestor_permissions);
+}
+
+#[test]
+fn stdin_defaults_to_dev_null() {
+  let result = execute(
+    ExecutionContext::new(
+      "/usr/bin/stat".into(),
+      Some(vec!["-L", "-c", "%t:%T", "/proc/self/fd/0"]),
+    )
+    .capture_output(true),
+  );
+
+  assert_eq!(result.stdout, "1:3\n");
+}
+
+#[test]
+fn prelude() {
+  let result = execute(
+    ExecutionContext::new(
+      "/bin/sh".into(),
+      Some(vec!["-c", "read -r line < /box/data; echo $line"]),
+    )
+    .capture_output(true)
+    .prelude(["/bin/sh", "-c", "echo noise; echo fixture > /box/data"]),
+  );
+
+  assert!(result.succeeded());
+
+  assert_eq!(result.stdout, "fixture\n");
+}
+
+#[test]
+fn execute_with_hung_setup() {
+  let config = Config {
+    setup_timeout_ms: Some(200),
+    ..Default::default()
+  };
+
+  with_sandbox(config, |sandbox| {
+    let context = || ExecutionContext::new("/bin/true".into(), None).capture_output(true);
+
+    let start = Instant::now();
+
+    let result = sandbox.execute(context().prelude(["/bin/sh", "-c", "mkfifo fifo && sleep 10"]));
+
+    let elapsed = start.elapsed();
+
+    assert_matches!(result, Err(Error::Timeout(message)) if message == "sandbox setup timed out");
+    assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+
+    // The box was torn down as after any run.
+    let r#box = sandbox.directory().join("box");
+
+    assert!(!r#box.join("fifo").exists());
+    assert_eq!(fs::metadata(&r#box).unwrap().uid(), 0);
+
+    assert_matches!(
+      sandbox
+        .execute(context().prelude(["/bin/true"]))
+        .unwrap()
+        .status,
+      Status::Ok
+    );
+  });
+}
+
+#[test]
+fn prelude_does_not_count_towards_wall_time_limit() {
+  let result = execute(
+    ExecutionContext::new("/bin/true".into(), None)
+      .prelude(["/bin/sleep", "0.5"])
+      .wall_time_limit_ms(300.0),
+  );
+
+  assert_matches!(result.status, Status::Ok, "{}", result.status_message);
+}
+
+#[test]
+fn prelude_failure() {
+  let result = execute(
+    ExecutionContext::new("/bin/sh".into(), Some(vec!["-c", "echo program"]))
+      .capture_output(true)
+      .prelude(["/bin/sh", "-c", "exit 3"]),
+  );
+
+  assert_matches!(result.status, Status::InternalError);
+
+  assert_eq!(
+    result.status_message,
+    "prelude failed: exited with error status 3"
+  );
+
+  assert_eq!(result.stdout, "");
+}
+
+#[test]
+fn resource_usage() {
+  let result = execute(ExecutionContext::new(
+    "/bin/sh".into(),
+    Some(vec![
+      "-c",
+      "i=0; while [ $i -lt 200000 ]; do i=$((i + 1)); done",
+    ]),
+  ));
+
+  assert!(result.succeeded());
+
+  assert!(result.usage.cpu_time_ms > 0.0);
+  assert!(result.usage.cpu_time_ms <= result.wall_time_ms);
+  assert!(result.usage.peak_memory_kb > 0);
+}
+
+#[test]
+fn execute_with_owned_arguments() {
+  let arguments = vec!["-c".to_string(), format!("echo {}", 6 * 7)];
+
+  let result = execute(ExecutionContext::owned("/bin/sh".into(), arguments).capture_output(true));
+
+  assert_eq!(result.stdout, "42\n");
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn execute_with_traced_syscalls() {
+  let result = execute(
+    ExecutionContext::new("/bin/sh".into(), Some(vec!["-c", "exit 3"])).trace_syscalls(true),
+  );
+
+  assert_eq!(result.exit_code, Some(3));
+
+  let last = result.syscalls.last().unwrap();
+
+  assert_eq!(last.number, nix::libc::SYS_exit_group as u64);
+
+  assert_eq!(last.arguments[0], 3);
+
+  assert_eq!(last.result, None);
+
+  assert!(result
+    .syscalls
+    .iter()
+    .any(|syscall| syscall.result.is_some()));
+}
+
+#[test]
+fn execute_without_traced_syscalls() {
+  let result = execute(ExecutionContext::new("/bin/true".into(), None));
+
+  assert!(result.syscalls.is_empty());
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn execute_with_traced_syscalls_and_timeout() {
+  let result = execute(
+    ExecutionContext::new("/bin/sleep".into(), Some(vec!["5"]))
+      .trace_syscalls(true)
+      .wall_time_limit_ms(100.0),
+  );
+
+  assert_matches!(result.status, Status::WallTimeout);
+}
+
+#[test]
+fn execute_with_filesystem_data() {
+  let run = |mount, script| {
+    execute(
+      ExecutionContext::new("/bin/sh".into(), Some(vec!["-c", script]))
+        .capture_output(true)
+        .mount(mount)
+        .process_limit(4),
+    )
+  };
+
+  let filesystem = |inside_path, filesystem: &str, fs_data: Option<&str>| {
+    Mount::new(
+      inside_path,
+      None::<&str>,
+      MountOptions {
+        filesystem: Some(filesystem.into()),
+        fs_data: fs_data.map(Into::into),
+        read_write: true,
+        ..Default::default()
+      },
+    )
+    .unwrap()
+  };
+
+  let result = run(
+    filesystem("/scratch", "tmpfs", Some("size=64k")),
+    "head -c 65536 /dev/zero > /scratch/fits && ! head -c 4096 /dev/zero > /scratch/overflows",
+  );
+
+  assert_matches!(result.status, Status::Ok, "{}", result.stderr);
+
+  let result = run(
+    filesystem("/dev/pts", "devpts", Some("newinstance,ptmxmode=0666")),
+    "stat -f -c %T /dev/pts && stat -c %a /dev/pts/ptmx",
+  );
+
+  assert_eq!(result.stdout, "devpts\n666\n", "{}", result.stderr);
+
+  let result = run(filesystem("/sys", "sysfs", None), "stat -f -c %T /sys");
+
+  assert_eq!(result.stdout, "sysfs\n", "{}", result.stderr);
+}
+
+#[test]
+fn execute_records_timestamps() {
+  let before = SystemTime::now();
+
+  let result = execute(ExecutionContext::new(
+    "/bin/sleep".into(),
+    Some(vec!["0.1"]),
+  ));
+
+  let after = SystemTime::now();
+
+  assert_matches!(result.status, Status::Ok, "{}", result.status_message);
+
+  assert!(before <= result.started_at);
+  assert!(result.finished_at <= after);
+
+  let elapsed = result
+    .finished_at
+    .duration_since(result.started_at)
+    .unwrap();
+
+  assert!(elapsed >= Duration::from_millis(100), "{elapsed:?}");
+}
+
+#[test]
+fn execute_measures_overhead() {
+  with_sandbox(Config::default(), |sandbox| {
+    let before = Instant::now();
+
+    let result = sandbox
+      .execute(
+        ExecutionContext::new("/bin/sleep".into(), Some(vec!["0.1"]))
+          .prelude(["/bin/sleep", "0.2"]),
+      )
+      .unwrap();
+
+    let elapsed = before.elapsed().as_secs_f64() * 1000.0;
+
+    assert_matches!(result.status, Status::Ok, "{}", result.status_message);
+
+    assert!(result.setup_time_ms >= 200.0, "{result:?}");
+    assert!(result.teardown_time_ms > 0.0);
+
+    // The prelude counts towards the setup rather than the program.
+    assert!((100.0..200.0).contains(&result.wall_time_ms), "{result:?}");
+
+    let phases = result.setup_time_ms + result.wall_time_ms + result.teardown_time_ms;
+
+    assert!(phases <= elapsed, "{phases} > {elapsed}");
+    assert!(elapsed - phases < 10.0, "{phases} < {elapsed}");
+  });
+}
+
+#[test]
+fn execute_with_dns() {
+  let result = execute(
+    ExecutionContext::new(
+      "/bin/sh".into(),
+      Some(vec!["-c", "cat /etc/hosts && ! echo > /etc/resolv.conf"]),
+    )
+    .capture_output(true)
+    .process_limit(4)
+    .share_net(true)
+    .with_dns(),
+  );
+
+  assert_matches!(result.status, Status::Ok, "{}", result.stderr);
+  assert_eq!(result.stdout, fs::read_to_string("/etc/hosts").unwrap());
+}
+
+#[test]
+fn execute_with_hostname() {
+  let hostname = |context: ExecutionContext| {
+    let result = execute(context.capture_output(true));
+    assert_matches!(result.status, Status::Ok, "{}", result.stderr);
+    result.stdout
+  };
+
+  let context = || ExecutionContext::new("/bin/uname".into(), Some(vec!["-n"]));
+
+  assert_eq!(hostname(context()), "box\n");
+
+  assert_eq!(
+    hostname(context().hostname(Some("judge".into()))),
+    "judge\n"
+  );
+
+  assert_eq!(
+    hostname(context().hostname(None)),
+    format!(
+      "{}\n",
+      fs::read_to_string("/proc/sys/kernel/hostname")
+        .unwrap()
+        .trim()
+    )
+  );
+}
+
+#[test]
+fn execute_with_nested_mounts() {
+  let outer = TempDir::new().unwrap();
+  let inner = TempDir::new().unwrap();
+
+  fs::create_dir(outer.path().join("inner")).unwrap();
+  fs::write(inner.path().join("marker"), "inner\n").unwrap();
+
+  for directory in [&outer, &inner] {
+    fs::set_permissions(directory.path(), fs::Permissions::from_mode(0o755)).unwrap();
+  }
+
+  // Specified child first, but mounted parent first.
+  let result = execute(
+    ExecutionContext::new("/bin/cat".into(), Some(vec!["/srv/inner/marker"]))
+      .capture_output(true)
+      .mount(Mount::read_only("/srv/inner", Some(inner.path().to_str().unwrap())).unwrap())
+      .mount(Mount::read_only("/srv", Some(outer.path().to_str().unwrap())).unwrap()),
+  );
+
+  assert_matches!(result.status, Status::Ok, "{}", result.status_message);
+  assert_eq!(result.stdout, "inner\n");
+}
+
+#[test]
+fn execute_with_minimal_dev() {
+  let result = execute(
+    ExecutionContext::new(
+      "/bin/sh".into(),
+      Some(vec![
+        "-c",
+        "ls /dev && head -c 3 /dev/zero | wc -c && echo > /dev/null && ! touch /dev/sda",
+      ]),
+    )
+    .capture_output(true)
+    .process_limit(4)
+    .mount(Mount::minimal_dev("dev").unwrap()),
+  );
+
+  assert_matches!(result.status, Status::Ok, "{}", result.stderr);
+  assert_eq!(result.stdout, "full\nnull\nrandom\ntty\nurandom\nzero\n3\n");
+}
+
+#[test]
+fn execute_script_without_interpreter() {
+  with_sandbox(Config::default(), |sandbox| {
+    let script = sandbox.directory().join("box/script");
+
+    fs::write(&script, "#!/opt/interpreter\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let result = sandbox
+      .execute(ExecutionContext::new("/box/script".into(), None))
+      .unwrap();
+
+    assert_matches!(result.status, Status::InternalError);
+    assert!(
+      result.status_message.contains(
+        "failed to execute `/box/script`: interpreter not found in sandbox (check shebang)"
+      ),
+      "{}",
+      result.status_message
+    );
+
+    let result = sandbox
+      .execute(ExecutionContext::new("/box/missing".into(), None))
+      .unwrap();
+
+    assert!(
+      result
+        .status_message
+        .contains("failed to execute `/box/missing`: ENOENT"),
+      "{}",
+      result.status_message
+    );
+  });
+}
+
+#[test]
+fn execute_closes_inherited_fds() {
+  // Unlike the descriptors Rust opens, a duplicate is not close-on-exec.
+  let null = fs::File::open("/dev/null").unwrap();
+
+  let leaked = dup2(null.as_raw_fd(), 100).unwrap();
+
+  let context =
+    || ExecutionContext::new("/bin/ls".into(), Some(vec!["/proc/self/fd"])).capture_output(true);
+
+  let closed = execute(context());
+
+  let inherited = execute(context().inherit_fds(true));
+
+  close(leaked).unwrap();
+
+  assert_matches!(closed.status, Status::Ok, "{}", closed.stderr);
+  assert!(
+    !closed.stdout.lines().any(|fd| fd == "100"),
+    "{}",
+    closed.stdout
+  );
+
+  assert_matches!(inherited.status, Status::Ok, "{}", inherited.stderr);
+  assert!(
+    inherited.stdout.lines().any(|fd| fd == "100"),
+    "{}",
+    inherited.stdout
+  );
+}
+
+#[test]
+fn execute_measuring_after_warmup() {
+  let context = |script| {
+    ExecutionContext::new("/bin/sh".into(), Some(vec!["-c", script]))
+      .capture_output(true)
+      .measure_after_warmup(true)
+      .process_limit(4)
+  };
+
+  let result = execute(context(
+    "i=0; while [ $i -lt 100000 ]; do i=$((i+1)); done; echo >&$ISOLATE_WARMUP_FD; sleep 0.1",
+  ));
+
+  assert_matches!(result.status, Status::Ok, "{}", result.stderr);
+
+  let cpu_time_after_warmup_ms = result.cpu_time_after_warmup_ms.unwrap();
+  let wall_time_after_warmup_ms = result.wall_time_after_warmup_ms.unwrap();
+
+  assert!(
+    cpu_time_after_warmup_ms < result.usage.cpu_time_ms,
+    "{cpu_time_after_warmup_ms} {}",
+    result.usage.cpu_time_ms
+  );
+
+  assert!(
+    (100.0..result.wall_time_ms).contains(&wall_time_after_warmup_ms),
+    "{wall_time_after_warmup_ms} {}",
+    result.wall_time_ms
+  );
+
+  let result = execute(context("true"));
+
+  assert_matches!(result.status, Status::Ok, "{}", result.stderr);
+  assert_eq!(result.cpu_time_after_warmup_ms, None);
+  assert_eq!(result.wall_time_after_warmup_ms, None);
+}
+
+#[test]
+fn execute_with_sysfs() {
+  let result = execute(
+    ExecutionContext::new(
+      "/bin/sh".into(),
+      Some(vec![
+        "-c",
+        "test -d /sys/devices/system/cpu/cpu0 && ! touch /sys/test && ls /sys/class/net",
+      ]),
+    )
+    .capture_output(true)
+    .process_limit(4)
+    .mount(Mount::sysfs("sys").unwrap()),
+  );
+
+  assert_matches!(result.status, Status::Ok, "{}", result.stderr);
+  assert_eq!(result.stdout, "lo\n");
+}
+
+#[test]
+fn execute_with_overlay() {
+  let directory = TempDir::new().unwrap();
+
+  let path = |name: &str| {
+    let path = Utf8PathBuf::try_from(directory.path().join(name)).unwrap();
+    fs::create_dir(&path).unwrap();
+    path
+  };
+
+  let (lower, upper, work) = (path("lower"), path("upper"), path("work"));
+
+  fs::set_permissions(directory.path(), fs::Permissions::from_mode(0o755)).unwrap();
+  // The root of the overlay takes its attributes from the upper directory.
+  fs::set_permissions(&upper, fs::Permissions::from_mode(0o777)).unwrap();
+  fs::write(lower.join("base"), "base\n").unwrap();
+  fs::set_permissions(lower.join("base"), fs::Permissions::from_mode(0o666)).unwrap();
+
+  let result = execute(
+    ExecutionContext::new(
+      "/bin/sh".into(),
+      Some(vec![
+        "-c",
+        "echo changed >> /image/base && echo new > /image/new && cat /image/base",
+      ]),
+    )
+    .capture_output(true)
+    .process_limit(4)
+    .mount(Mount::overlay("/image", &lower, &upper, &work).unwrap()),
+  );
+
+  assert_matches!(result.status, Status::Ok, "{}", result.status_message);
+  assert_eq!(result.stdout, "base\nchanged\n");
+
+  assert_eq!(fs::read_to_string(lower.join("base")).unwrap(), "base\n");
+  assert!(!lower.join("new").exists());
+
+  assert_eq!(
+    fs::read_to_string(upper.join("base")).unwrap(),
+    "base\nchanged\n"
+  );
+  assert_eq!(fs::read_to_string(upper.join("new")).unwrap(), "new\n");
+}
+
+#[test]
+fn execute_with_tty_hack() {
+  let pty = openpty(None, None).unwrap();
+
+  assert!(tcgetattr(&pty.slave)
+    .unwrap()
+    .local_flags
+    .contains(LocalFlags::ECHO));
+
+  let path = ttyname(&pty.slave).unwrap();
+
+  // SAFETY: The child only runs the sandbox and exits without returning to
+  // the test harness.
+  match unsafe { fork() }.unwrap() {
+    ForkResult::Child => {
+      let handed_back = panic::catch_unwind(AssertUnwindSafe(|| {
+        setsid().unwrap();
+
+        // Opening a terminal as a session leader makes it the controlling
+        // terminal of the session.
+        let terminal = fs::OpenOptions::new()
+          .read(true)
+          .write(true)
+          .open(&path)
+          .unwrap();
+
+        dup2(terminal.as_raw_fd(), libc::STDIN_FILENO).unwrap();
+
+        // Changing the terminal settings stops a background process.
+        let result = execute(
+          ExecutionContext::new("/bin/stty".into(), Some(vec!["-echo"]))
+            .tty_hack(true)
+            .wall_time_limit_ms(5000.0),
+        );
+
+        assert_matches!(result.status, Status::Ok, "{}", result.status_message);
+
+        assert_eq!(tcgetpgrp(&terminal).unwrap(), getpgrp());
+
+        assert!(tcgetattr(&terminal)
+          .unwrap()
+          .local_flags
+          .contains(LocalFlags::ECHO));
+      }));
+
+      // SAFETY: Exiting right away skips the test harness' exit handlers.
+      unsafe { libc::_exit(i32::from(handed_back.is_err())) }
+    }
+    ForkResult::Parent { child } => {
+      assert_eq!(waitpid(child, None).unwrap(), WaitStatus::Exited(child, 0));
+    }
+  }
+}
+
+#[test]
+fn execute_with_tty_hack_without_terminal() {
+  let result = with_sandbox(Config::default(), |sandbox| {
+    sandbox.execute(ExecutionContext::new("/bin/true".into(), None).tty_hack(true))
+  });
+
+  assert_matches!(
+    result,
+    Err(Error::Config(message)) if message.contains("controlling terminal")
+  );
+}
+
+#[test]
+fn execute_removes_special_files() {
+  let run = |special_files| {
+    with_sandbox(Config::default(), |sandbox| {
+      let result = sandbox
+        .execute(
+          ExecutionContext::new(
+            "/bin/sh".into(),
+            Some(vec![
+              "-c",
+              "mkdir -p /box/nested && mkfifo /box/fifo /box/nested/fifo \
+               && ln -s /etc/passwd /box/link && echo data > /box/nested/file",
+            ]),
+          )
+          .process_limit(4)
+          .special_files(special_files),
+        )
+        .unwrap();
+
+      assert_matches!(result.status, Status::Ok, "{}", result.status_message);
+
+      let exists = |path| fs::symlink_metadata(sandbox.directory().join("box").join(path)).is_ok();
+
+      (
+        ["fifo", "nested/fifo", "link"].map(exists),
+        exists("nested/file"),
+      )
+    })
+  };
+
+  assert_eq!(run(false), ([false, false, false], true));
+  assert_eq!(run(true), ([true, true, true], true));
+}
+
+#[test]
+fn execute_with_synthetic_passwd() {
+  let run = |context: ExecutionContext| {
+    execute(
+      context
+        .arguments(Some(vec![
+          "-c",
+          "whoami && id -gn && ! echo >> /etc/passwd && grep -c . /etc/passwd",
+        ]))
+        .capture_output(true)
+        .process_limit(8),
+    )
+  };
+
+  let result = run(ExecutionContext::new("/bin/sh".into(), None).with_synthetic_passwd());
+
+  assert_matches!(result.status, Status::Ok, "{}", result.stderr);
+  assert_eq!(result.stdout, "sandbox\nsandbox\n1\n");
+
+  let result = run(
+    ExecutionContext::new("/bin/sh".into(), None)
+      .mount(Mount::read_only("/etc", None::<&str>).unwrap())
+      .with_synthetic_passwd(),
+  );
+
+  assert_matches!(result.status, Status::Ok, "{}", result.stderr);
+  assert_eq!(result.stdout, "sandbox\nsandbox\n1\n");
+
+  let result = run(ExecutionContext::new("/bin/sh".into(), None));
+
+  assert_matches!(result.status, Status::RuntimeError);
+}
+
+#[test]
+fn execute_measures_box_disk_usage() {
+  with_sandbox(Config::default(), |sandbox| {
+    fs::write(sandbox.directory().join("box/compiled"), vec![0; 8192]).unwrap();
+
+    let result = sandbox
+      .execute(
+        ExecutionContext::new(
+          "/bin/sh".into(),
+          Some(vec![
+            "-c",
+            "head -c 10000 /dev/zero > /box/output && head -c 1024 /dev/zero >> /box/compiled \
+             && cat /box/compiled > /dev/null",
+          ]),
+        )
+        .process_limit(4),
+      )
+      .unwrap();
+
+    assert_matches!(result.status, Status::Ok, "{}", result.status_message);
+
+    // 10000 bytes of output and 1024 appended to the existing file.
+    assert_eq!(result.box_disk_usage_kb, 11);
+  });
+}
+
+#[test]
+fn execute_searching_path() {
+  let context = |program: &str| {
+    ExecutionContext::new(program.into(), Some(vec!["-c", "echo found"]))
+      .capture_output(true)
+      .process_limit(4)
+      .search_path(true)
+  };
+
+  let result = execute(context("sh"));
+
+  assert_matches!(result.status, Status::Ok, "{}", result.status_message);
+  assert_eq!(result.stdout, "found\n");
+
+  // Only the sandbox's `PATH` is searched, not the caller's.
+  let result = execute(context("sh").set_variables([("PATH", "/box")]));
+
+  assert_matches!(result.status, Status::InternalError);
+  assert!(
+    result
+      .status_message
+      .contains("failed to execute `sh`: ENOENT"),
+    "{}",
+    result.status_message
+  );
+
+  let result = execute(context("sh").search_path(false));
+
+  assert_matches!(result.status, Status::InternalError);
+}
+
+#[test]
+fn execute_with_kernel_memory_limits() {
+  let result = execute(
+    ExecutionContext::new(
+      "/bin/sh".into(),
+      Some(vec![
+        "-c",
+        "grep -E '^Max (locked memory|msgqueue size|pending signals)' /proc/self/limits",
+      ]),
+    )
+    .capture_output(true)
+    .pending_signals_limit(16)
+    .process_limit(4),
+  );
+
+  let limits = result
+    .stdout
+    .lines()
+    .map(|line| line.split_whitespace().nth(3).unwrap())
+    .collect::<Vec<_>>();
+
+  assert_eq!(limits, ["0", "16", "0"], "{}", result.stdout);
+}
+
+#[test]
+fn sample_running_program() {
+  with_sandbox(Config::default(), |sandbox| {
+    let sandbox = &*sandbox;
+
+    assert_matches!(sandbox.sample(), Err(Error::NotRunning(0)));
+
+    thread::scope(|scope| {
+      let execution = scope.spawn(|| {
+        sandbox.execute(
+          ExecutionContext::new("/bin/sh".into(), Some(vec!["-c", "while :; do :; done"]))
+            .time_limit_ms(500.0)
+            .extra_time_ms(0.0),
+        )
+      });
+
+      let sample = loop {
+        match sandbox.sample() {
+          Ok(sample) if sample.cpu_time_ms > 0.0 => break sample,
+          Ok(_) | Err(Error::NotRunning(_)) => thread::sleep(Duration::from_millis(10)),
+          Err(error) => panic!("{error}"),
+        }
+      };
+
+      assert!(sample.memory_kb > 0);
+
+      assert_matches!(execution.join().unwrap().unwrap().status, Status::Timeout);
+    });
+
+    assert_matches!(sandbox.sample(), Err(Error::NotRunning(0)));
+  });
+}
+
+#[test]
+fn kill_running_program() {
+  with_sandbox(Config::default(), |sandbox| {
+    let sandbox = &*sandbox;
+
+    assert_matches!(sandbox.kill(), Err(Error::NotRunning(0)));
+
+    thread::scope(|scope| {
+      let execution = scope.spawn(|| {
+        sandbox.execute(
+          ExecutionContext::new("/bin/sleep".into(), Some(vec!["10"])).wall_time_limit_ms(5000.0),
+        )
+      });
+
+      while let Err(Error::NotRunning(_)) = sandbox.kill() {
+        thread::sleep(Duration::from_millis(10));
+      }
+
+      let result = execution.join().unwrap().unwrap();
+
+      assert_eq!(result.kill_reason, Some(KillReason::Shutdown));
+      assert_matches!(result.status, Status::InternalError);
+      assert!(result.wall_time_ms < 5000.0);
+    });
+  });
+}